@@ -7,34 +7,310 @@ export interface Configuration {
   dbConnectionString?: string
   redisConnectionString?: string
   maxColumns?: number
+  /**
+   * How many chunks from a Node.js stream can wait to be ingested before `readData` holds off
+   * resolving, defaults to 256
+   */
+  ingestionBufferChunks?: number
+  /**
+   * How many bytes from a Node.js stream can wait to be ingested before `readData` holds off
+   * resolving, defaults to 64MiB
+   */
+  ingestionBufferBytes?: number
 }
 export interface Options {
   stopNodes?: Array<string>
   valueNodes?: Array<string>
 }
 export declare function hash(a: string, options: Options): string
+/**
+ * How a CSV file is laid out. Every field is optional, anything left out falls back to DataFusion's
+ * defaults: comma delimited, double quoted, a header row and a schema inferred from the first 1000
+ * rows. Single character options take exactly one ASCII character, e.g. `"\t"` for tab separated
+ * files.
+ */
+export interface CsvDialect {
+  delimiter?: string
+  quote?: string
+  escape?: string
+  /**
+   * Whether the first row holds the column names, when it doesn't the columns are named
+   * `column_1`, `column_2` and so on unless `schema` names them
+   */
+  hasHeader?: boolean
+  /** Lines starting with this character are skipped */
+  comment?: string
+  /** A value that's read as null wherever it's the whole field, e.g. `NaN` or `-9999` */
+  nullValue?: string
+  /** How many rows are read to infer the schema, ignored when `schema` is set */
+  schemaInferMaxRecords?: number
+  /** The columns of the file in order, skips schema inference altogether */
+  schema?: Array<CsvColumn>
+}
+export interface CsvColumn {
+  name: string
+  /** An Arrow data type, e.g. `Int64`, `Float64`, `Utf8` or `Timestamp(Nanosecond, None)` */
+  dataType: string
+  /** Defaults to true */
+  nullable?: boolean
+}
 export interface FileMetadata {
   id: string
   file_name: string
   access_path: string
+  /** How the file is laid out if it's a CSV, ignored for every other type of file */
+  csv_options?: CsvDialect
+  /**
+   * Hive style `name=value` directories between `access_path` and the files, e.g. year, month and
+   * day for `year=2024/month=07/day=30/`. Only used when `glob` is set, in which case they're
+   * inferred as strings if left out
+   */
+  partition_columns?: Array<PartitionColumn>
+  /**
+   * Whether `file_name` is a glob such as `**\/*.parquet` rather than the name of a single file.
+   * Defaults to false, so a name like `run[1].csv` is read as it is
+   */
+  glob?: boolean
+}
+export interface PartitionColumn {
+  name: string
+  /**
+   * An Arrow data type, e.g. `Int32` or `Utf8`. Defaults to `Utf8`. Equality filters on partition
+   * columns are turned into a directory to list, so zero padded values such as `month=07` need to
+   * stay `Utf8` to be found
+   */
+  dataType?: string
+}
+/**
+ * The graph of a container to query alongside the files, registered as a `nodes` and an `edges`
+ * table. Both are the graph as it was at `timestamp`, or as it is now if that's left out, so
+ * readings can be joined to the nodes they belong to as of when they were taken, e.g.
+ * `SELECT n.metatype_name, r.* FROM table_1 r JOIN nodes n ON n.original_data_id = r.sensor_id`
+ */
+export interface GraphSnapshot {
+  dbConnectionString: string
+  containerId: string
+  /** Anything Postgres can read as a timestamp, e.g. `2024-07-30 07:32:27` */
+  timestamp?: string
+}
+/**
+ * What is being ingested and where it's written. The data is checked against `schema` as it's read,
+ * a row that doesn't fit fails the ingestion and nothing is left behind in the store
+ */
+export interface ParquetIngestionOptions {
+  /** The id of the returned FileMetadata */
+  id: string
+  /** Folder the Parquet files are written to, relative to the root of the storage connection */
+  filePath: string
+  /** `csv` or `json`, which is newline delimited JSON. Defaults to `csv` */
+  format?: string
+  schema: Array<CsvColumn>
+  /**
+   * How the incoming CSV is laid out, ignored for JSON. `schema` on the dialect is ignored in favour
+   * of the schema above and `schemaInferMaxRecords` isn't used
+   */
+  csvOptions?: CsvDialect
+  /**
+   * Columns of the schema to partition the files by, as hive style `name=value` folders in the order
+   * they're listed. They're left out of the files themselves like DataFusion expects. Values that
+   * would have to be escaped in a path, like ones with a `/` or `%`, fail the ingestion
+   */
+  partitionBy?: Array<string>
+}
+/**
+ * The legacy timeseries tables to make queryable alongside the files, each data source's
+ * `y_{id}` table is registered under that same name so queries written against Postgres still work
+ */
+export interface LegacyTables {
+  dbConnectionString: string
+  dataSourceIds: Array<string>
 }
 /**
  * For processing file uploads
- * Returns the results of a SQL `DESCRIBE` of the file as stringified JSON.
+ * Runs the SQL `DESCRIBE` statements in `query` and returns the description of each file as
+ * stringified JSON. A file that can't be read or described is listed under `errors` rather than
+ * failing the whole report.
  */
 export declare function processUpload(reportId: string, query: string, storageConnection: string, files: Array<FileMetadata>): Promise<string>
+/** The optional parts of a processQuery call */
+export interface ProcessQueryOptions {
+  /** The format and compression of the results, CSV without compression by default */
+  output?: QueryOutputOptions
+  /** How long the query can run and how much memory it can use */
+  limits?: QueryLimits
+  /** Legacy `y_{id}` tables the query can read, and join with the files, under those same names */
+  legacyTables?: LegacyTables
+  /** The container's graph, queryable as `nodes` and `edges` */
+  graphSnapshot?: GraphSnapshot
+}
 /**
  * For processing a query against a set of files.
- * Uploads results to a location specified in the request object.
+ * Uploads results to a location specified in the request object, as CSV unless the `output` option
+ * asks for another format or compression. Only a single `SELECT` can be run and it's stopped once it
+ * goes past the timeout or memory limit in `limits`, either is reported with the `InvalidArg` code.
+ * The legacy `y_{id}` tables in `legacy_tables` can be queried, and joined with the files, under
+ * those same names, and the container's graph as `nodes` and `edges` when `graph_snapshot` is set.
  * Returns the metadata of the query results as stringified JSON.
  */
-export declare function processQuery(reportId: string, query: string, storageConnection: string, files: Array<FileMetadata>): Promise<string>
+export declare function processQuery(reportId: string, query: string, storageConnection: string, files: Array<FileMetadata>, options?: ProcessQueryOptions | undefined | null): Promise<string>
+/**
+ * Controls how the results of `process_query` are written out. Both fields are optional, leaving
+ * them out writes an uncompressed CSV with a header row.
+ */
+export interface QueryOutputOptions {
+  /** `csv` (default), `parquet`, `json` (newline delimited) or `arrow` (IPC file format) */
+  format?: string
+  /**
+   * `none`, `gzip` or `zstd`. CSV and JSON default to `none`, Parquet defaults to `zstd`. Arrow IPC
+   * files are always written with LZ4 compressed buffers so `lz4` is the only value they accept
+   */
+  compression?: string
+}
+/**
+ * Limits put on the queries run by `process_query` and `stream_query`. Both fields are optional,
+ * leaving them out uses a 5 minute timeout and a 1024 MB memory limit.
+ */
+export interface QueryLimits {
+  /**
+   * How long a query may run, including writing out its results, before it's cancelled. The query
+   * notices the timeout whenever it's waiting on reading or writing data, DataFusion doesn't
+   * interrupt an operator that's in the middle of a long computation
+   */
+  timeoutMs?: number
+  /**
+   * Memory available to sorts, joins and aggregations. Those that can spill to disk do once it's
+   * reached, a query that still needs more fails
+   */
+  memoryLimitMb?: number
+}
+export interface QueryStreamOptions {
+  /**
+   * Stop after this many rows. The limit is part of the query plan so DataFusion stops reading the
+   * files once it has been reached
+   */
+  rowLimit?: number
+  /**
+   * The same limits processQuery has. The timeout covers the whole stream, from starting the query
+   * to handing over its last batch. A TimeseriesQueryEngine uses the limits it was created with when
+   * these are left out
+   */
+  limits?: QueryLimits
+  /** Legacy `y_{id}` tables the query can read, the same as processQuery's */
+  legacyTables?: LegacyTables
+  /** The container's graph, queryable as `nodes` and `edges` */
+  graphSnapshot?: GraphSnapshot
+}
+/**
+ * For running a query against a set of files without uploading the results anywhere.
+ * Returns a QueryStream which hands the results back a record batch at a time.
+ */
+export declare function streamQuery(query: string, storageConnection: string, files: Array<FileMetadata>, options?: QueryStreamOptions | undefined | null): Promise<QueryStream>
 export interface LegacyTimeseriesColumn {
   column_name: string
   property_name: string
   is_primary_timestamp: boolean
   type: string
   date_conversion_format_string?: string
+  /**
+   * IANA name or fixed offset of the timezone a date column's values are in when they don't have an
+   * offset of their own, e.g. `America/Denver` or `-07:00`. Defaults to UTC
+   */
+  timezone?: string
+}
+/** How a bucket table is created */
+export interface BucketTableOptions {
+  /**
+   * TimescaleDB's chunk_time_interval for the hypertable, e.g. `1 day` for a date primary timestamp
+   * or `86400` for a number one. Left to TimescaleDB's default when it's not set, and not used
+   * without TimescaleDB
+   */
+  chunkInterval?: string
+}
+/**
+ * The bucket table that was created, `partitioning` is `hypertable` when TimescaleDB is installed,
+ * `range` for a table range partitioned by its primary timestamp otherwise, and `none` when the
+ * bucket doesn't have a primary timestamp
+ */
+export interface BucketTable {
+  tableName: string
+  partitioning: string
+}
+/** A change to the columns of a bucket table */
+export interface BucketColumnChange {
+  /**
+   * `add` adds `column`, `rename` renames the column called `from` to column's column_name and
+   * `change_type` changes the type of column's column_name to column's type. Existing values are
+   * cast to the new type, a value that can't be fails the change
+   */
+  action: string
+  column: LegacyTimeseriesColumn
+  /** The current name of a column being renamed */
+  from?: string
+}
+/**
+ * A column proposed by inferColumns, `confidence` is the share of the sampled values that fit the
+ * proposed type, from 0 to 1. Every value fits a string, so strings always have a confidence of 1
+ * unless every sampled value was empty, then it's 0
+ */
+export interface InferredColumn {
+  column: LegacyTimeseriesColumn
+  confidence: number
+}
+/**
+ * Samples the start of a CSV file and proposes the LegacyTimeseriesColumns of a bucket for it. Every
+ * header becomes a column named after it, typed by what most of its values parse as. The first date
+ * column is proposed as the primary timestamp. The sample can stop part way through a row, that last
+ * row is left out
+ */
+export declare function inferColumns(sampleBytes: Buffer): Array<InferredColumn>
+/** What an ingestion reports back once it's complete */
+export interface IngestionReport {
+  /** Metadata of the written files, for ingestions that write files */
+  fileMetadata?: FileMetadata
+  /** How many rows were left out under the error policy of a legacy CSV ingestion */
+  rowsRejected: number
+  /** The first of the rejected rows and why they were rejected */
+  errors: Array<RowError>
+}
+/**
+ * How a legacy CSV ingestion deals with rows that can't be ingested, e.g. a date that doesn't match
+ * its column's format, a value that doesn't fit its column's type or a record that's missing a field
+ */
+export interface LegacyIngestionOptions {
+  /**
+   * `fail_fast` fails the whole ingestion on the first bad row, `skip` leaves bad rows out and
+   * `collect` leaves them out until more than `maxErrors` have been. Defaults to `fail_fast`
+   */
+  errorPolicy?: string
+  /** How many rows `collect` rejects before failing the ingestion. Defaults to 100 */
+  maxErrors?: number
+  /**
+   * Local file the rejected rows are written to as CSV, with the header of the ingested file and
+   * `_row` and `_error` columns added. The added columns don't match any bucket column so the file
+   * can be ingested again once it's fixed. JSON ingestions write newline delimited JSON instead, with
+   * `_row` and `_error` keys added to each record
+   */
+  deadLetterPath?: string
+  /**
+   * Values ingested as nulls, compared after trimming whitespace. Defaults to just the empty string,
+   * which is always a null whether it's listed or not
+   */
+  nullValues?: Array<string>
+}
+/** A row that was rejected during a legacy CSV ingestion */
+export interface RowError {
+  /**
+   * Line of the file the row starts on, the header is line 1. For JSON it's the number of the record,
+   * starting at 1
+   */
+  row: number
+  /**
+   * The CSV header, or JSON property name, of the rejected value when the problem is with a single
+   * value
+   */
+  column?: string
+  value?: string
+  reason: string
 }
 export type JsRedisGraphLoader = RedisGraphLoader
 export declare class RedisGraphLoader {
@@ -76,24 +352,112 @@ export declare class BucketRepository {
    */
   init(config: Configuration): Promise<void>
   /**
-   * # Safety
-   *
-   * This spawns multithreaded operations so be wary. The beginCsvIngestion function initializes the
-   * repository to receive CSV data from a node.js source
+   * This spawns multithreaded operations so be wary. The beginLegacyCsvIngestion function starts an
+   * ingestion that receives CSV data from a node.js source, the data is passed in through the
+   * returned Ingestion. Any number of ingestions can run at once. Without options a bad row fails
+   * the ingestion, see LegacyIngestionOptions for skipping them instead
    */
-  beginLegacyCsvIngestion(dataSourceId: string, columns: Array<LegacyTimeseriesColumn>): void
+  beginLegacyCsvIngestion(dataSourceId: string, columns: Array<LegacyTimeseriesColumn>, options?: LegacyIngestionOptions | undefined | null): Ingestion
   /**
-   * # Safety
-   *
-   * A "begin_x_ingestion" must have been called successfully before you attempt to read.
-   * This is how data is passed into our internal pipeline
+   * This spawns multithreaded operations so be wary. The beginLegacyJsonIngestion function is
+   * beginLegacyCsvIngestion for JSON arrays or newline delimited JSON. Each column's property_name is
+   * the key of its values in the records, or the path to them for nested values, e.g. `reading.value`
+   * or `readings[0].value`
    */
-  readData(bytes: Buffer): void
+  beginLegacyJsonIngestion(dataSourceId: string, columns: Array<LegacyTimeseriesColumn>, options?: LegacyIngestionOptions | undefined | null): Ingestion
   /**
-   * # Safety
-   *
-   * This terminates multithreaded operations so be wary. This is called when you've completed the
-   * ingestion and can also be used to check for errors during the operation
+   * This spawns multithreaded operations so be wary. The beginParquetIngestion function starts an
+   * ingestion that receives CSV or JSON data from a node.js source and writes it as Parquet into
+   * the storage `storage_connection` points to. Completing the returned Ingestion returns the
+   * metadata of the files
+   */
+  beginParquetIngestion(storageConnection: string, options: ParquetIngestionOptions): Ingestion
+  /**
+   * Creates the table of a legacy bucket from its columns. With a primary timestamp it's a
+   * TimescaleDB hypertable when the extension is installed, and range partitioned by the primary
+   * timestamp with a default partition otherwise
+   */
+  createBucket(dataSourceId: string, columns: Array<LegacyTimeseriesColumn>, options?: BucketTableOptions | undefined | null): Promise<BucketTable>
+  /**
+   * Adds, renames or changes the type of columns of a legacy bucket's table, all of the changes are
+   * made or none of them are
+   */
+  alterBucket(dataSourceId: string, changes: Array<BucketColumnChange>): Promise<void>
+  /** Drops the table of a legacy bucket and all of its data */
+  dropBucket(dataSourceId: string): Promise<void>
+}
+/**
+ * JsIngestion is the Javascript friendly wrapper over a single ingestion, returned by the
+ * beginXIngestion functions of BucketRepository.
+ */
+export declare class Ingestion {
+  /**
+   * This is how data is passed into the ingestion. Resolves once the pipeline has room for the
+   * data, wait for it before passing in the next chunk so a fast stream can't buffer more than the
+   * configured ingestion buffer
+   */
+  readData(bytes: Buffer): Promise<void>
+  /** How many bytes passed in through readData are still waiting to be ingested */
+  bufferedBytes(): number
+  /**
+   * This terminates multithreaded operations so be wary. This is called when you've passed in all
+   * the data and can also be used to check for errors during the operation. Resolves to the report
+   * of the ingestion, the metadata of the written files or the rows a legacy ingestion left out
+   */
+  complete(): Promise<IngestionReport>
+  /**
+   * Stops the ingestion without waiting for the rest of the data, e.g. when the upload it's reading
+   * from is cancelled. A legacy CSV ingestion's COPY is aborted so none of its rows are committed.
+   * Resolves once the ingestion has stopped, readData and complete reject as aborted from then on
+   */
+  abort(): Promise<void>
+}
+/**
+ * QueryStream pulls record batches from a running query as Javascript asks for them, so only the
+ * batch currently being handed over is held in memory. Both next functions resolve to null once the
+ * results are exhausted or the stream has been cancelled, e.g.
+ * `while ((batch = await stream.nextBatch()) !== null) { ... }`
+ */
+export declare class QueryStream {
+  /**
+   * Returns the next record batch encoded as a self-contained Arrow IPC stream (schema and batch),
+   * which can be read with `tableFromIPC` from the apache-arrow package
+   */
+  nextBatch(): Promise<Buffer | null>
+  /** Returns the rows of the next record batch as a stringified JSON array of objects */
+  nextRows(): Promise<string | null>
+  /** Stops the query. Any pending or later calls to the next functions resolve to null */
+  cancel(): void
+}
+/**
+ * TimeseriesQueryEngine keeps one DataFusion session alive across queries against the same storage
+ * connection. Files are registered the first time a query includes them and stay registered,
+ * keyed by their id, so their schemas are only inferred once. Parquet statistics are cached across
+ * the whole session and are re-read if the file underneath changes size or modification time.
+ * Queries run through the engine are held to its `limits`, the memory limit is shared by all of the
+ * queries running at once. A query that brings limits of its own gets its own memory limit instead.
+ */
+export declare class TimeseriesQueryEngine {
+  constructor(storageConnection: string, limits?: QueryLimits | undefined | null)
+  /** Same as the processUpload function, using the engine's session */
+  processUpload(reportId: string, query: string, files: Array<FileMetadata>): Promise<string>
+  /**
+   * Same as the processQuery function, using the engine's session. The query is held to the
+   * engine's limits unless `options` has limits of its own
+   */
+  processQuery(reportId: string, query: string, files: Array<FileMetadata>, options?: ProcessQueryOptions | undefined | null): Promise<string>
+  /**
+   * Same as the streamQuery function, using the engine's session. The stream is held to the
+   * engine's limits unless `options` has limits of its own
+   */
+  streamQuery(query: string, files: Array<FileMetadata>, options?: QueryStreamOptions | undefined | null): Promise<QueryStream>
+  /**
+   * Drops the tables registered for a file so the next query that includes it registers it again,
+   * use this when a file has been replaced. Returns false if the file wasn't registered
    */
-  completeIngestion(): Promise<void>
+  invalidate(fileId: string): Promise<boolean>
+  /** Drops every registered table */
+  clear(): Promise<void>
+  /** The ids of the files currently registered with the engine */
+  registeredFiles(): Promise<Array<string>>
 }
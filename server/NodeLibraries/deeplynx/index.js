@@ -310,11 +310,16 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { RedisGraphLoader, hash, SnapshotGenerator, BucketRepository, processUpload, processQuery } = nativeBinding
+const { RedisGraphLoader, hash, SnapshotGenerator, BucketRepository, Ingestion, inferColumns, processUpload, processQuery, TimeseriesQueryEngine, streamQuery, QueryStream } = nativeBinding
 
 module.exports.RedisGraphLoader = RedisGraphLoader
 module.exports.hash = hash
 module.exports.SnapshotGenerator = SnapshotGenerator
 module.exports.BucketRepository = BucketRepository
+module.exports.Ingestion = Ingestion
+module.exports.inferColumns = inferColumns
 module.exports.processUpload = processUpload
 module.exports.processQuery = processQuery
+module.exports.TimeseriesQueryEngine = TimeseriesQueryEngine
+module.exports.streamQuery = streamQuery
+module.exports.QueryStream = QueryStream
@@ -9,6 +9,12 @@ pub struct Configuration {
   pub db_connection_string: Option<String>,
   pub redis_connection_string: Option<String>,
   pub max_columns: Option<u32>,
+  /// How many chunks from a Node.js stream can wait to be ingested before `readData` holds off
+  /// resolving, defaults to 256
+  pub ingestion_buffer_chunks: Option<u32>,
+  /// How many bytes from a Node.js stream can wait to be ingested before `readData` holds off
+  /// resolving, defaults to 64MiB
+  pub ingestion_buffer_bytes: Option<u32>,
 }
 
 impl Configuration {
@@ -25,7 +31,9 @@ impl Configuration {
         Ok(Configuration{
             db_connection_string: Some(db_connection_string),
             redis_connection_string: None,
-            max_columns: None
+            max_columns: None,
+            ingestion_buffer_chunks: None,
+            ingestion_buffer_bytes: None,
         })
     } 
 }
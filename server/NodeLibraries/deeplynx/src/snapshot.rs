@@ -2,8 +2,8 @@ use crate::config::Configuration;
 use crate::snapshot::generator::{SnapshotGenerator, SnapshotParameters};
 use std::sync::Arc;
 
-mod errors;
-mod generator;
+pub(crate) mod errors;
+pub(crate) mod generator;
 mod snapshot_tests;
 
 #[napi(js_name = "SnapshotGenerator")]
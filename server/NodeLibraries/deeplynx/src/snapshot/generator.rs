@@ -1,11 +1,15 @@
 use crate::config::Configuration;
 use crate::snapshot::errors::SnapshotError;
+use arrow::array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+use arrow::datatypes::{DataType as ArrowType, Field, Schema};
+use chrono::NaiveDateTime;
 use futures_util::{StreamExt, TryStreamExt};
 use polars::frame::DataFrame;
-use polars::prelude::{col, lit, Expr, IntoLazy, NamedFrom, Series};
+use polars::prelude::{col, lit, DataType, Expr, IntoLazy, NamedFrom, Series};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sqlx::PgPool;
+use std::sync::Arc;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 
 #[derive(Clone)]
@@ -13,6 +17,8 @@ pub struct SnapshotGenerator {
   db: PgPool,
   // be warned - DataFrame is NOT Copy, you will have to Clone to work with it.
   frame: Option<DataFrame>,
+  // the edges between the nodes in frame, only built when asked for with generate_edge_snapshot
+  edges: Option<DataFrame>,
   _config: Configuration,
 }
 
@@ -28,6 +34,7 @@ impl SnapshotGenerator {
     Ok(SnapshotGenerator {
       db,
       frame: None,
+      edges: None,
       _config: config,
     })
   }
@@ -40,6 +47,10 @@ impl SnapshotGenerator {
     timestamp: Option<String>,
   ) -> Result<(), SnapshotError> {
     let mut connection = self.db.acquire().await?;
+    let timestamp = match timestamp {
+      None => None,
+      Some(ts) => Some(self.timestamp_literal(ts.as_str()).await?),
+    };
 
     // we use this query for both count and copy, so easy to just work with it once
     let query = match timestamp {
@@ -70,8 +81,8 @@ impl SnapshotGenerator {
                    FROM (nodes
                      LEFT JOIN metatypes ON ((metatypes.id = nodes.metatype_id)))
                   WHERE (nodes.container_id = {container_id})
-                  AND nodes.created_at <= '{ts}'
-                  AND (nodes.deleted_at > '{ts}' OR nodes.deleted_at IS NULL) AND (nodes.data_source_id IS NOT NULL)
+                  AND nodes.created_at <= {ts}
+                  AND (nodes.deleted_at > {ts} OR nodes.deleted_at IS NULL) AND (nodes.data_source_id IS NOT NULL)
                   ORDER BY nodes.id, nodes.created_at) q ORDER BY q.metatype_id"#
       ),
     };
@@ -150,6 +161,135 @@ impl SnapshotGenerator {
     Ok(())
   }
 
+  /// generate_edge_snapshot builds the dataframe of the edges in a container at a given point in
+  /// time, the same point in time as the node snapshot so the two can be joined together
+  pub async fn generate_edge_snapshot(
+    &mut self,
+    container_id: u64,
+    timestamp: Option<String>,
+  ) -> Result<(), SnapshotError> {
+    let mut connection = self.db.acquire().await?;
+
+    let filter = match timestamp {
+      None => "edges.deleted_at IS NULL".to_string(),
+      Some(ts) => {
+        let ts = self.timestamp_literal(ts.as_str()).await?;
+        format!(
+          "edges.created_at <= {ts} AND (edges.deleted_at > {ts} OR edges.deleted_at IS NULL)"
+        )
+      }
+    };
+    // the latest version of each edge, the same way current_edges picks them
+    let query = format!(
+      r#"SELECT DISTINCT ON (edges.origin_id, edges.destination_id, edges.data_source_id, edges.relationship_pair_id) edges.id,
+                    edges.container_id,
+                    edges.relationship_pair_id,
+                    edges.data_source_id,
+                    edges.origin_id,
+                    edges.destination_id,
+                    edges.properties,
+                    metatype_relationships.name AS metatype_relationship_name
+                   FROM ((edges
+                     LEFT JOIN metatype_relationship_pairs ON ((edges.relationship_pair_id = metatype_relationship_pairs.id)))
+                     LEFT JOIN metatype_relationships ON ((metatype_relationship_pairs.relationship_id = metatype_relationships.id)))
+                  WHERE (edges.container_id = {container_id}::bigint) AND {filter}
+                  ORDER BY edges.origin_id, edges.destination_id, edges.data_source_id, edges.relationship_pair_id, edges.created_at DESC"#
+    );
+
+    let count: (i64,) = sqlx::query_as(format!("SELECT COUNT(*) FROM ({}) q", query).as_str())
+      .fetch_one(&self.db)
+      .await?;
+
+    let async_reader = connection
+      .copy_out_raw(
+        format!(
+          "COPY ({} LIMIT {}) TO STDOUT WITH (FORMAT csv, HEADER true);",
+          query, count.0
+        )
+        .as_str(),
+      )
+      .await?
+      .map_err(std::io::Error::other)
+      .into_async_read();
+
+    let mut async_reader = csv_async::AsyncDeserializer::from_reader(async_reader.compat());
+    let mut records = async_reader.deserialize::<Edge>();
+
+    let mut ids: Vec<u64> = Vec::with_capacity(count.0 as usize);
+    let mut container_ids: Vec<u64> = Vec::with_capacity(count.0 as usize);
+    let mut relationship_pair_ids: Vec<u64> = Vec::with_capacity(count.0 as usize);
+    let mut data_source_ids: Vec<Option<u64>> = Vec::with_capacity(count.0 as usize);
+    let mut origin_ids: Vec<Option<u64>> = Vec::with_capacity(count.0 as usize);
+    let mut destination_ids: Vec<Option<u64>> = Vec::with_capacity(count.0 as usize);
+    let mut properties: Vec<String> = Vec::with_capacity(count.0 as usize);
+    let mut relationship_names: Vec<Option<String>> = Vec::with_capacity(count.0 as usize);
+
+    while let Some(record) = records.next().await {
+      let e = record?;
+      ids.push(e.id);
+      container_ids.push(e.container_id);
+      relationship_pair_ids.push(e.relationship_pair_id);
+      data_source_ids.push(e.data_source_id);
+      origin_ids.push(e.origin_id);
+      destination_ids.push(e.destination_id);
+      properties.push(e.properties);
+      relationship_names.push(e.metatype_relationship_name);
+    }
+
+    let df = DataFrame::new(vec![
+      Series::new("id".into(), ids).into(),
+      Series::new("container_id".into(), container_ids).into(),
+      Series::new("relationship_pair_id".into(), relationship_pair_ids).into(),
+      Series::new("data_source_id".into(), data_source_ids).into(),
+      Series::new("origin_id".into(), origin_ids).into(),
+      Series::new("destination_id".into(), destination_ids).into(),
+      Series::new("properties".into(), properties).into(),
+      Series::new("metatype_relationship_name".into(), relationship_names).into(),
+    ])?;
+
+    self.edges = Some(df);
+    Ok(())
+  }
+
+  /// The snapshot's point in time as a timestamp literal for the snapshot queries. The COPY they end
+  /// up in can't take parameters, so Postgres parses the timestamp as a bound parameter first and
+  /// only its result goes into the query
+  async fn timestamp_literal(&self, timestamp: &str) -> Result<String, SnapshotError> {
+    let parsed: NaiveDateTime = sqlx::query_scalar("SELECT $1::timestamp")
+      .bind(timestamp)
+      .fetch_one(&self.db)
+      .await?;
+
+    Ok(format!(
+      "'{}'::timestamp",
+      parsed.format("%Y-%m-%d %H:%M:%S%.6f")
+    ))
+  }
+
+  /// The node snapshot as an Arrow record batch, for querying it alongside timeseries data
+  pub fn nodes_batch(&self) -> Result<RecordBatch, SnapshotError> {
+    to_record_batch(
+      self
+        .frame
+        .as_ref()
+        .ok_or(SnapshotError::General(String::from(
+          "no dataframe initiated",
+        )))?,
+    )
+  }
+
+  /// The edge snapshot as an Arrow record batch, for querying it alongside timeseries data
+  pub fn edges_batch(&self) -> Result<RecordBatch, SnapshotError> {
+    to_record_batch(
+      self
+        .edges
+        .as_ref()
+        .ok_or(SnapshotError::General(String::from(
+          "no edge dataframe initiated",
+        )))?,
+    )
+  }
+
   /// find_nodes takes a set of parameters and returns the node ids of those nodes that match all
   /// parameters. Parameters are done an AND filter
   pub async fn find_nodes(
@@ -289,6 +429,19 @@ pub struct Node {
   metatype_uuid: String,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+/// Edge represents the structure contained in the DeepLynx table.
+pub struct Edge {
+  id: u64,
+  container_id: u64,
+  relationship_pair_id: u64,
+  data_source_id: Option<u64>,
+  origin_id: Option<u64>,
+  destination_id: Option<u64>,
+  properties: String,
+  metatype_relationship_name: Option<String>,
+}
+
 // unfortunately we have to pass in the params as JSON so that we can get the proper type for
 // the value field
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -316,3 +469,35 @@ fn to_expr<E: Into<Expr>>(
     _ => Err(SnapshotError::General(String::from("unsupported operator"))),
   }
 }
+
+/// to_record_batch copies a snapshot dataframe into an Arrow record batch. Polars keeps its own
+/// Arrow implementation, so the columns are copied over rather than shared. Snapshot frames only
+/// ever hold ids and strings
+pub fn to_record_batch(df: &DataFrame) -> Result<RecordBatch, SnapshotError> {
+  let mut fields = vec![];
+  let mut arrays: Vec<ArrayRef> = vec![];
+
+  for column in df.get_columns() {
+    let (data_type, array): (ArrowType, ArrayRef) = match column.dtype() {
+      DataType::UInt64 => (
+        ArrowType::UInt64,
+        Arc::new(column.u64()?.iter().collect::<UInt64Array>()),
+      ),
+      DataType::String => (
+        ArrowType::Utf8,
+        Arc::new(column.str()?.iter().collect::<StringArray>()),
+      ),
+      t => {
+        return Err(SnapshotError::General(format!(
+          "unsupported snapshot column type {t} for {}",
+          column.name()
+        )))
+      }
+    };
+
+    fields.push(Field::new(column.name().as_str(), data_type, true));
+    arrays.push(array);
+  }
+
+  Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
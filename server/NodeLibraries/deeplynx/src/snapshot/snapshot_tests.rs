@@ -17,6 +17,25 @@ mod main_tests {
     Ok(())
   }
 
+  #[tokio::test]
+  async fn test_snapshot_timestamp_is_not_sql() -> Result<(), SnapshotError> {
+    let mut generator =
+      SnapshotGenerator::new(Configuration::from_path(Some(String::from(".config.yml"))).unwrap())
+        .await?;
+
+    // Postgres has to be able to read it as a timestamp before it goes anywhere near the queries
+    let timestamp = Some("2024-07-30' OR true) --".to_string());
+    for result in [
+      generator.generate_snapshot(1, timestamp.clone()).await,
+      generator.generate_edge_snapshot(1, timestamp.clone()).await,
+    ] {
+      let e = result.unwrap_err().to_string();
+      assert!(e.contains("invalid input syntax for type timestamp"), "{e}");
+    }
+
+    Ok(())
+  }
+
   #[tokio::test]
   async fn test_node_filter() -> Result<(), SnapshotError> {
     let mut generator =
@@ -1,10 +1,29 @@
 mod azure_object_store;
 mod bucket_repository;
+mod bucket_tables;
+mod column_inference;
+mod csv_dialect;
 mod data_types;
 mod datafusion_session;
 mod errors;
 mod file_metadata;
+mod graph_snapshot;
+#[cfg(feature = "hdf5")]
+mod hdf5_table;
 mod ingestion;
+mod json_ingestion;
+mod parquet_ingestion;
+mod postgres_table;
 mod query;
+mod query_engine;
+mod query_output;
+mod query_policy;
+mod query_stream;
 mod repository;
+mod s3_object_store;
+mod storage_connection;
+mod tdms_reader;
+mod tdms_table;
 mod tests;
+mod time_functions;
+mod timestamp_parser;
@@ -1,126 +1,38 @@
-use azure_storage::{prelude::*, CloudLocation};
-use azure_storage_blobs::prelude::*;
-use connection_string::AdoNetString;
 use object_store::azure::MicrosoftAzure;
 use object_store::azure::MicrosoftAzureBuilder;
 
 use crate::timeseries::errors::QueryError;
+use crate::timeseries::storage_connection::{AzureAuth, AzureBlobConnection};
 
 pub fn register_azure_store(
-  storage_connection: &AdoNetString,
+  connection: &AzureBlobConnection,
 ) -> Result<MicrosoftAzure, QueryError> {
+  let builder = MicrosoftAzureBuilder::new()
+    .with_endpoint(connection.blob_endpoint.clone())
+    .with_account(connection.account_name.clone())
+    .with_container_name(connection.container_name.clone());
+
+  let builder = match &connection.auth {
+    AzureAuth::AccountKey(key) => builder.with_access_key(key.clone()),
+    AzureAuth::SasToken(token) => builder.with_config(
+      object_store::azure::AzureConfigKey::SasKey,
+      token.trim_start_matches('?'),
+    ),
+    // with no other credentials set the builder falls back to the instance metadata endpoint
+    AzureAuth::ManagedIdentity { client_id: None } => builder,
+    AzureAuth::ManagedIdentity {
+      client_id: Some(client_id),
+    } => builder.with_client_id(client_id.clone()),
+  };
+
+  // the local Azurite setup uses account keys, SAS tokens and managed identities always go to Azure
   #[cfg(debug_assertions)]
-  return Ok(
-    MicrosoftAzureBuilder::new()
-      .with_endpoint(
-        storage_connection
-          .get("blobendpoint")
-          .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-            msg: "Azure Blob Endpoint is not set in connection string".to_string(),
-          })?
-          .to_owned(),
-      )
-      .with_account(storage_connection.get("accountname").ok_or_else(|| {
-        QueryError::ReceivedNullDataInRequest {
-          msg: "Azure Account Name is not set in connection string".to_string(),
-        }
-      })?)
-      .with_access_key(storage_connection.get("accountkey").ok_or_else(|| {
-        QueryError::ReceivedNullDataInRequest {
-          msg: "Azure Account Access Key is not set in connection string".to_string(),
-        }
-      })?)
-      .with_container_name(storage_connection.get("containername").ok_or_else(|| {
-        QueryError::ReceivedNullDataInRequest {
-          msg: "Azure Container Name is not set in connection string".to_string(),
-        }
-      })?)
+  let builder = match connection.auth {
+    AzureAuth::AccountKey(_) => builder
       .with_allow_http(true) // only on dev builds
-      .with_use_emulator(true) // only on dev builds
-      .build()?,
-  );
-
-  #[cfg(not(debug_assertions))]
-  Ok(
-    MicrosoftAzureBuilder::new()
-      .with_endpoint(
-        storage_connection
-          .get("blobendpoint")
-          .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-            msg: "Azure Blob Endpoint is not set in connection string".to_string(),
-          })?
-          .to_owned(),
-      )
-      .with_account(storage_connection.get("accountname").ok_or_else(|| {
-        QueryError::ReceivedNullDataInRequest {
-          msg: "Azure Account Name is not set in connection string".to_string(),
-        }
-      })?)
-      .with_access_key(storage_connection.get("accountkey").ok_or_else(|| {
-        QueryError::ReceivedNullDataInRequest {
-          msg: "Azure Account Access Key is not set in connection string".to_string(),
-        }
-      })?)
-      .with_container_name(storage_connection.get("containername").ok_or_else(|| {
-        QueryError::ReceivedNullDataInRequest {
-          msg: "Azure Container Name is not set in connection string".to_string(),
-        }
-      })?)
-      .build()?,
-  )
-}
-
-pub async fn get_blob_size(
-  storage_connection: &AdoNetString,
-  upload_path: &String,
-  file_name: &String,
-) -> Result<u64, QueryError> {
-  let account = storage_connection
-    .get("accountname")
-    .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-      msg: "Azure Account Name is not set in connection string".to_string(),
-    })?
-    .to_owned();
-
-  let access_key = storage_connection
-    .get("accountkey")
-    .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-      msg: "Azure Account Access Key is not set in connection string".to_string(),
-    })?
-    .to_owned();
-  let storage_credentials = StorageCredentials::access_key(account.clone(), access_key);
-
-  let container = storage_connection
-    .get("containername")
-    .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-      msg: "Azure Container Name is not set in connection string".to_string(),
-    })?
-    .to_owned();
-
-  let uri = storage_connection
-    .get("blobendpoint")
-    .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-      msg: "Azure Blob Endpoint is not set in connection string".to_string(),
-    })?
-    .to_owned();
-
-  let cloud_location = match uri.contains(account.as_str()) {
-    true => CloudLocation::Custom { account, uri },
-    false => CloudLocation::Custom {
-      account: account.clone(),
-      uri: format!("{uri}/{account}"),
-    },
+      .with_use_emulator(true), // only on dev builds
+    _ => builder,
   };
 
-  let blob_client = ClientBuilder::with_location(cloud_location, storage_credentials)
-    .blob_client(&container, format!("{upload_path}/{file_name}"));
-
-  Ok(
-    blob_client
-      .get_properties()
-      .await?
-      .blob
-      .properties
-      .content_length,
-  )
+  Ok(builder.build()?)
 }
@@ -1,5 +1,11 @@
 use crate::config::Configuration;
-use crate::timeseries::repository::{BucketRepository, LegacyTimeseriesColumn};
+use crate::timeseries::bucket_tables::{BucketColumnChange, BucketTable, BucketTableOptions};
+use crate::timeseries::ingestion::LegacyIngestionOptions;
+use crate::timeseries::parquet_ingestion::ParquetIngestionOptions;
+use crate::timeseries::repository::{
+  BucketRepository, Ingestion, IngestionReport, LegacyTimeseriesColumn,
+};
+use crate::timeseries::storage_connection::StorageConnection;
 use napi::bindgen_prelude::Buffer;
 
 #[napi(js_name = "BucketRepository")]
@@ -31,21 +37,46 @@ impl JsBucketRepository {
   }
 
   #[napi]
-  /// # Safety
-  ///
-  /// This spawns multithreaded operations so be wary. The beginCsvIngestion function initializes the
-  /// repository to receive CSV data from a node.js source
-  pub unsafe fn begin_legacy_csv_ingestion(
-    &mut self,
+  /// Creates the table of a legacy bucket from its columns. With a primary timestamp it's a
+  /// TimescaleDB hypertable when the extension is installed, and range partitioned by the primary
+  /// timestamp with a default partition otherwise
+  pub async fn create_bucket(
+    &self,
     data_source_id: String,
     columns: Vec<LegacyTimeseriesColumn>,
+    options: Option<BucketTableOptions>,
+  ) -> Result<BucketTable, napi::Error> {
+    let inner = self.inner.as_ref().ok_or(napi::Error::new(
+      napi::Status::GenericFailure,
+      "must call init before calling functions",
+    ))?;
+
+    match inner
+      .create_bucket(data_source_id, columns, options.unwrap_or_default())
+      .await
+    {
+      Ok(table) => Ok(table),
+      Err(e) => Err(napi::Error::new(
+        napi::Status::GenericFailure,
+        e.to_string(),
+      )),
+    }
+  }
+
+  #[napi]
+  /// Adds, renames or changes the type of columns of a legacy bucket's table, all of the changes are
+  /// made or none of them are
+  pub async fn alter_bucket(
+    &self,
+    data_source_id: String,
+    changes: Vec<BucketColumnChange>,
   ) -> Result<(), napi::Error> {
-    let inner = self.inner.as_mut().ok_or(napi::Error::new(
+    let inner = self.inner.as_ref().ok_or(napi::Error::new(
       napi::Status::GenericFailure,
       "must call init before calling functions",
     ))?;
 
-    match inner.begin_legacy_csv_ingestion(data_source_id, columns) {
+    match inner.alter_bucket(data_source_id, changes).await {
       Ok(_) => Ok(()),
       Err(e) => Err(napi::Error::new(
         napi::Status::GenericFailure,
@@ -55,19 +86,14 @@ impl JsBucketRepository {
   }
 
   #[napi]
-  /// # Safety
-  ///
-  /// A "begin_x_ingestion" must have been called successfully before you attempt to read.
-  /// This is how data is passed into our internal pipeline
-  pub fn read_data(&mut self, bytes: Buffer) -> Result<(), napi::Error> {
-    let bytes: Vec<u8> = bytes.into();
-
-    let inner = self.inner.as_mut().ok_or(napi::Error::new(
+  /// Drops the table of a legacy bucket and all of its data
+  pub async fn drop_bucket(&self, data_source_id: String) -> Result<(), napi::Error> {
+    let inner = self.inner.as_ref().ok_or(napi::Error::new(
       napi::Status::GenericFailure,
       "must call init before calling functions",
     ))?;
 
-    match inner.read_data(bytes) {
+    match inner.drop_bucket(data_source_id).await {
       Ok(_) => Ok(()),
       Err(e) => Err(napi::Error::new(
         napi::Status::GenericFailure,
@@ -77,17 +103,137 @@ impl JsBucketRepository {
   }
 
   #[napi]
-  /// # Safety
-  ///
-  /// This terminates multithreaded operations so be wary. This is called when you've completed the
-  /// ingestion and can also be used to check for errors during the operation
-  pub async unsafe fn complete_ingestion(&mut self) -> Result<(), napi::Error> {
-    let inner = self.inner.as_mut().ok_or(napi::Error::new(
+  /// This spawns multithreaded operations so be wary. The beginLegacyCsvIngestion function starts an
+  /// ingestion that receives CSV data from a node.js source, the data is passed in through the
+  /// returned Ingestion. Any number of ingestions can run at once. Without options a bad row fails
+  /// the ingestion, see LegacyIngestionOptions for skipping them instead
+  pub fn begin_legacy_csv_ingestion(
+    &self,
+    data_source_id: String,
+    columns: Vec<LegacyTimeseriesColumn>,
+    options: Option<LegacyIngestionOptions>,
+  ) -> Result<JsIngestion, napi::Error> {
+    let inner = self.inner.as_ref().ok_or(napi::Error::new(
+      napi::Status::GenericFailure,
+      "must call init before calling functions",
+    ))?;
+
+    match inner.begin_legacy_csv_ingestion(data_source_id, columns, options.unwrap_or_default()) {
+      Ok(inner) => Ok(JsIngestion { inner }),
+      Err(e) => Err(napi::Error::new(
+        napi::Status::GenericFailure,
+        e.to_string(),
+      )),
+    }
+  }
+
+  #[napi]
+  /// This spawns multithreaded operations so be wary. The beginLegacyJsonIngestion function is
+  /// beginLegacyCsvIngestion for JSON arrays or newline delimited JSON. Each column's property_name is
+  /// the key of its values in the records, or the path to them for nested values, e.g. `reading.value`
+  /// or `readings[0].value`
+  pub fn begin_legacy_json_ingestion(
+    &self,
+    data_source_id: String,
+    columns: Vec<LegacyTimeseriesColumn>,
+    options: Option<LegacyIngestionOptions>,
+  ) -> Result<JsIngestion, napi::Error> {
+    let inner = self.inner.as_ref().ok_or(napi::Error::new(
+      napi::Status::GenericFailure,
+      "must call init before calling functions",
+    ))?;
+
+    match inner.begin_legacy_json_ingestion(data_source_id, columns, options.unwrap_or_default()) {
+      Ok(inner) => Ok(JsIngestion { inner }),
+      Err(e) => Err(napi::Error::new(
+        napi::Status::GenericFailure,
+        e.to_string(),
+      )),
+    }
+  }
+
+  #[napi]
+  /// This spawns multithreaded operations so be wary. The beginParquetIngestion function starts an
+  /// ingestion that receives CSV or JSON data from a node.js source and writes it as Parquet into
+  /// the storage `storage_connection` points to. Completing the returned Ingestion returns the
+  /// metadata of the files
+  pub fn begin_parquet_ingestion(
+    &self,
+    storage_connection: String,
+    options: ParquetIngestionOptions,
+  ) -> Result<JsIngestion, napi::Error> {
+    let inner = self.inner.as_ref().ok_or(napi::Error::new(
       napi::Status::GenericFailure,
       "must call init before calling functions",
     ))?;
 
-    match inner.complete_ingestion().await {
+    let storage_connection: StorageConnection = storage_connection.parse().map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to parse storage_connection string with reason: {e}"
+      ))
+    })?;
+
+    match inner.begin_parquet_ingestion(storage_connection, options) {
+      Ok(inner) => Ok(JsIngestion { inner }),
+      Err(e) => Err(napi::Error::new(
+        napi::Status::GenericFailure,
+        e.to_string(),
+      )),
+    }
+  }
+}
+
+#[napi(js_name = "Ingestion")]
+pub struct JsIngestion {
+  inner: Ingestion,
+}
+
+/// JsIngestion is the Javascript friendly wrapper over a single ingestion, returned by the
+/// beginXIngestion functions of BucketRepository.
+#[napi]
+impl JsIngestion {
+  #[napi]
+  /// This is how data is passed into the ingestion. Resolves once the pipeline has room for the
+  /// data, wait for it before passing in the next chunk so a fast stream can't buffer more than the
+  /// configured ingestion buffer
+  pub async fn read_data(&self, bytes: Buffer) -> Result<(), napi::Error> {
+    let bytes: Vec<u8> = bytes.into();
+
+    match self.inner.read_data(bytes).await {
+      Ok(_) => Ok(()),
+      Err(e) => Err(napi::Error::new(
+        napi::Status::GenericFailure,
+        e.to_string(),
+      )),
+    }
+  }
+
+  #[napi]
+  /// How many bytes passed in through readData are still waiting to be ingested
+  pub fn buffered_bytes(&self) -> u32 {
+    self.inner.buffered_bytes() as u32
+  }
+
+  #[napi]
+  /// This terminates multithreaded operations so be wary. This is called when you've passed in all
+  /// the data and can also be used to check for errors during the operation. Resolves to the report
+  /// of the ingestion, the metadata of the written files or the rows a legacy ingestion left out
+  pub async fn complete(&self) -> Result<IngestionReport, napi::Error> {
+    match self.inner.complete().await {
+      Ok(report) => Ok(report),
+      Err(e) => Err(napi::Error::new(
+        napi::Status::GenericFailure,
+        e.to_string(),
+      )),
+    }
+  }
+
+  #[napi]
+  /// Stops the ingestion without waiting for the rest of the data, e.g. when the upload it's reading
+  /// from is cancelled. A legacy CSV ingestion's COPY is aborted so none of its rows are committed.
+  /// Resolves once the ingestion has stopped, readData and complete reject as aborted from then on
+  pub async fn abort(&self) -> Result<(), napi::Error> {
+    match self.inner.abort().await {
       Ok(_) => Ok(()),
       Err(e) => Err(napi::Error::new(
         napi::Status::GenericFailure,
@@ -0,0 +1,227 @@
+use crate::timeseries::data_types::LegacyDataTypes;
+use crate::timeseries::errors::TimeseriesError;
+use crate::timeseries::ingestion::{quote, table_name};
+use crate::timeseries::repository::LegacyTimeseriesColumn;
+use sqlx::postgres::PgPool;
+
+/// How a bucket table is created
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct BucketTableOptions {
+  /// TimescaleDB's chunk_time_interval for the hypertable, e.g. `1 day` for a date primary timestamp
+  /// or `86400` for a number one. Left to TimescaleDB's default when it's not set, and not used
+  /// without TimescaleDB
+  pub chunk_interval: Option<String>,
+}
+
+/// The bucket table that was created, `partitioning` is `hypertable` when TimescaleDB is installed,
+/// `range` for a table range partitioned by its primary timestamp otherwise, and `none` when the
+/// bucket doesn't have a primary timestamp
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketTable {
+  pub table_name: String,
+  pub partitioning: String,
+}
+
+/// A change to the columns of a bucket table
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct BucketColumnChange {
+  /// `add` adds `column`, `rename` renames the column called `from` to column's column_name and
+  /// `change_type` changes the type of column's column_name to column's type. Existing values are
+  /// cast to the new type, a value that can't be fails the change
+  pub action: String,
+  pub column: LegacyTimeseriesColumn,
+  /// The current name of a column being renamed
+  pub from: Option<String>,
+}
+
+/// Creates the `y_{data_source_id}` table of a legacy bucket, what the legacy ingestions write into.
+/// With a primary timestamp it's a TimescaleDB hypertable when the extension is installed in the
+/// database, and range partitioned by the primary timestamp otherwise. Rows go to a default partition
+/// until partitions for their ranges are attached
+pub async fn create_bucket_table(
+  db: &PgPool,
+  data_source_id: &str,
+  columns: &[LegacyTimeseriesColumn],
+  options: BucketTableOptions,
+) -> Result<BucketTable, TimeseriesError> {
+  let table_name = table_name(data_source_id)?;
+  if columns.is_empty() {
+    return Err(TimeseriesError::InvalidOptions(
+      "a bucket needs at least one column".to_string(),
+    ));
+  }
+
+  let primary_timestamps: Vec<&LegacyTimeseriesColumn> =
+    columns.iter().filter(|c| c.is_primary_timestamp).collect();
+  let primary_timestamp = match primary_timestamps.as_slice() {
+    [] => None,
+    [column] => match LegacyDataTypes::from(column.data_type.clone()) {
+      LegacyDataTypes::Date | LegacyDataTypes::Number | LegacyDataTypes::Number64 => Some(*column),
+      _ => {
+        return Err(TimeseriesError::InvalidOptions(format!(
+          "primary timestamp {} has to be a date or a number",
+          column.column_name
+        )))
+      }
+    },
+    _ => {
+      return Err(TimeseriesError::InvalidOptions(
+        "a bucket can only have one primary timestamp".to_string(),
+      ))
+    }
+  };
+
+  let timescale: bool =
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb')")
+      .fetch_one(db)
+      .await?;
+
+  let column_statements: Vec<String> = columns
+    .iter()
+    .map(|c| format!("{} {} DEFAULT NULL", quote(&c.column_name), column_type(c)))
+    .collect();
+  // the same bookkeeping columns the tables created by the DeepLynx server have
+  let mut create = format!(
+    "CREATE TABLE {table_name} ({}, _metadata jsonb DEFAULT NULL, _import_id bigint DEFAULT NULL)",
+    column_statements.join(", ")
+  );
+
+  let partitioning = match primary_timestamp {
+    None => "none",
+    Some(_) if timescale => "hypertable",
+    Some(column) => {
+      create.push_str(format!(" PARTITION BY RANGE ({})", quote(&column.column_name)).as_str());
+      "range"
+    }
+  };
+
+  let mut transaction = db.begin().await?;
+  sqlx::query(create.as_str())
+    .execute(&mut *transaction)
+    .await?;
+
+  match primary_timestamp {
+    Some(column) if timescale => {
+      let interval_type = match LegacyDataTypes::from(column.data_type.clone()) {
+        LegacyDataTypes::Date => "interval",
+        _ => "bigint",
+      };
+      match options.chunk_interval {
+        None => {
+          sqlx::query("SELECT create_hypertable($1::regclass, $2::name)")
+            .bind(table_name.as_str())
+            .bind(column.column_name.as_str())
+            .execute(&mut *transaction)
+            .await?;
+        }
+        Some(chunk_interval) => {
+          sqlx::query(
+            format!(
+              "SELECT create_hypertable($1::regclass, $2::name, \
+               chunk_time_interval => $3::{interval_type})"
+            )
+            .as_str(),
+          )
+          .bind(table_name.as_str())
+          .bind(column.column_name.as_str())
+          .bind(chunk_interval)
+          .execute(&mut *transaction)
+          .await?;
+        }
+      }
+    }
+    Some(_) => {
+      sqlx::query(
+        format!("CREATE TABLE {table_name}_default PARTITION OF {table_name} DEFAULT").as_str(),
+      )
+      .execute(&mut *transaction)
+      .await?;
+    }
+    None => {}
+  }
+
+  transaction.commit().await?;
+
+  Ok(BucketTable {
+    table_name,
+    partitioning: partitioning.to_string(),
+  })
+}
+
+/// Applies the changes to the bucket table in order, either all of them are made or none are
+pub async fn alter_bucket_table(
+  db: &PgPool,
+  data_source_id: &str,
+  changes: Vec<BucketColumnChange>,
+) -> Result<(), TimeseriesError> {
+  let table_name = table_name(data_source_id)?;
+
+  let mut transaction = db.begin().await?;
+  for change in changes {
+    let column = quote(&change.column.column_name);
+    let statement = match change.action.as_str() {
+      "add" => format!(
+        "ALTER TABLE {table_name} ADD COLUMN {column} {} DEFAULT NULL",
+        column_type(&change.column)
+      ),
+      "rename" => {
+        let from = change.from.as_deref().ok_or_else(|| {
+          TimeseriesError::InvalidOptions(format!(
+            "renaming to {} needs the column it's renamed from",
+            change.column.column_name
+          ))
+        })?;
+        format!(
+          "ALTER TABLE {table_name} RENAME COLUMN {} TO {column}",
+          quote(from)
+        )
+      }
+      "change_type" => {
+        let column_type = column_type(&change.column);
+        format!(
+          "ALTER TABLE {table_name} ALTER COLUMN {column} TYPE {column_type} \
+           USING {column}::{column_type}"
+        )
+      }
+      action => {
+        return Err(TimeseriesError::InvalidOptions(format!(
+          "unknown column change {action}, expected add, rename or change_type"
+        )))
+      }
+    };
+
+    sqlx::query(statement.as_str())
+      .execute(&mut *transaction)
+      .await?;
+  }
+
+  transaction.commit().await?;
+  Ok(())
+}
+
+/// Drops the bucket table along with all of its data, and its partitions or chunks
+pub async fn drop_bucket_table(db: &PgPool, data_source_id: &str) -> Result<(), TimeseriesError> {
+  let table_name = table_name(data_source_id)?;
+
+  sqlx::query(format!("DROP TABLE IF EXISTS {table_name}").as_str())
+    .execute(db)
+    .await?;
+
+  Ok(())
+}
+
+/// Postgres types of the legacy data types, the same the DeepLynx server creates bucket tables with
+fn column_type(column: &LegacyTimeseriesColumn) -> &'static str {
+  match LegacyDataTypes::from(column.data_type.clone()) {
+    LegacyDataTypes::Number if column.is_primary_timestamp => "bigint",
+    LegacyDataTypes::Number => "integer",
+    LegacyDataTypes::Number64 => "bigint",
+    LegacyDataTypes::Float | LegacyDataTypes::Float64 => "numeric",
+    LegacyDataTypes::Date => "timestamptz",
+    LegacyDataTypes::Boolean => "boolean",
+    LegacyDataTypes::String | LegacyDataTypes::Json => "text",
+  }
+}
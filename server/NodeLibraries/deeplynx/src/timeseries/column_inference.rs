@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use crate::timeseries::data_types::LegacyDataTypes;
+use crate::timeseries::errors::TimeseriesError;
+use crate::timeseries::ingestion::strip_thousands_separators;
+use crate::timeseries::repository::LegacyTimeseriesColumn;
+use crate::timeseries::timestamp_parser::TimestampParser;
+use chrono::NaiveDateTime;
+use napi::bindgen_prelude::Buffer;
+
+/// A column proposed by inferColumns, `confidence` is the share of the sampled values that fit the
+/// proposed type, from 0 to 1. Every value fits a string, so strings always have a confidence of 1
+/// unless every sampled value was empty, then it's 0
+#[napi(object)]
+#[derive(Debug, Clone)]
+pub struct InferredColumn {
+  pub column: LegacyTimeseriesColumn,
+  pub confidence: f64,
+}
+
+/// Date formats tried against columns in this order, a format that fits as many values as one
+/// before it loses out to it. Dates none of them fit, but which the ingestion detects on its own, are
+/// proposed without a format string
+const DATE_FORMATS: [&str; 14] = [
+  "%Y-%m-%d %H:%M:%S",
+  "%Y-%m-%d %H:%M:%S%.f",
+  "%Y-%m-%dT%H:%M:%S",
+  "%Y-%m-%dT%H:%M:%S%.f",
+  "%Y-%m-%d %H:%M",
+  "%Y-%m-%dT%H:%M",
+  "%Y-%m-%d:%H:%M:%S",
+  "%Y-%m-%d:%H:%M",
+  "%Y/%m/%d %H:%M:%S",
+  "%Y/%m/%d %H:%M",
+  "%m/%d/%Y %H:%M:%S",
+  "%m/%d/%Y %H:%M",
+  "%d/%m/%Y %H:%M:%S",
+  "%d/%m/%Y %H:%M",
+];
+
+/// Below this share of fitting values a column is left as a string
+const MIN_CONFIDENCE: f64 = 0.5;
+
+/// Samples the start of a CSV file and proposes the LegacyTimeseriesColumns of a bucket for it. Every
+/// header becomes a column named after it, typed by what most of its values parse as. The first date
+/// column is proposed as the primary timestamp. The sample can stop part way through a row, that last
+/// row is left out
+#[napi]
+// only ever called from Javascript, the tests go through infer
+#[cfg_attr(test, allow(dead_code))]
+pub fn infer_columns(sample_bytes: Buffer) -> napi::Result<Vec<InferredColumn>> {
+  infer(sample_bytes.as_ref()).map_err(|e| napi::Error::from_reason(e.to_string()))
+}
+
+pub(crate) fn infer(sample: &[u8]) -> Result<Vec<InferredColumn>, TimeseriesError> {
+  // a sample cut off part way through a row would have its last value misread
+  let sample = match sample.iter().rposition(|b| *b == b'\n') {
+    Some(end) if end + 1 < sample.len() => &sample[..=end],
+    _ => sample,
+  };
+
+  let mut csv_reader = csv::ReaderBuilder::new().flexible(true).from_reader(sample);
+  let headers = csv_reader.headers()?.clone();
+
+  let mut values: Vec<Vec<String>> = vec![vec![]; headers.len()];
+  for record in csv_reader.records() {
+    let record = record?;
+    for (i, value) in record.iter().enumerate().take(headers.len()) {
+      let value = value.trim();
+      if !value.is_empty() {
+        values[i].push(value.to_string());
+      }
+    }
+  }
+
+  let mut columns: Vec<InferredColumn> = headers
+    .iter()
+    .zip(values.iter())
+    .map(|(header, values)| {
+      let (data_type, format_string, confidence) = infer_type(values);
+      InferredColumn {
+        column: LegacyTimeseriesColumn {
+          column_name: column_name(header),
+          property_name: header.to_string(),
+          is_primary_timestamp: false,
+          data_type: data_type.into(),
+          date_conversion_format_string: format_string.map(|f| f.to_string()),
+          timezone: None,
+        },
+        confidence,
+      }
+    })
+    .collect();
+
+  // headers that only differ in case or punctuation end up with the same name, the later ones are
+  // numbered
+  let mut names = HashSet::new();
+  for column in columns.iter_mut() {
+    let name = column.column.column_name.clone();
+    let mut number = 1;
+    while !names.insert(column.column.column_name.clone()) {
+      number += 1;
+      column.column.column_name = format!("{name}_{number}");
+    }
+  }
+
+  if let Some(primary) = columns.iter_mut().find(|c| c.column.data_type == "date") {
+    primary.column.is_primary_timestamp = true;
+  }
+
+  Ok(columns)
+}
+
+/// The most specific type that fits the most values, as the type, the date format if it's a date and
+/// the share of values that fit
+fn infer_type(values: &[String]) -> (LegacyDataTypes, Option<&'static str>, f64) {
+  if values.is_empty() {
+    return (LegacyDataTypes::String, None, 0.0);
+  }
+
+  let share = |fits: &dyn Fn(&str) -> bool| {
+    values.iter().filter(|v| fits(v.as_str())).count() as f64 / values.len() as f64
+  };
+
+  let mut candidates: Vec<(LegacyDataTypes, Option<&'static str>, f64)> = vec![
+    (LegacyDataTypes::Boolean, None, share(&is_boolean)),
+    (
+      LegacyDataTypes::Number,
+      None,
+      share(&|v| strip_thousands_separators(v).is_some_and(|n| n.parse::<i32>().is_ok())),
+    ),
+    (
+      LegacyDataTypes::Number64,
+      None,
+      share(&|v| strip_thousands_separators(v).is_some_and(|n| n.parse::<i64>().is_ok())),
+    ),
+    (LegacyDataTypes::Float, None, share(&is_float)),
+    (
+      LegacyDataTypes::Float64,
+      None,
+      share(&|v| {
+        strip_thousands_separators(v).is_some_and(|n| n.parse::<f64>().is_ok_and(|f| f.is_finite()))
+      }),
+    ),
+  ];
+  for format in DATE_FORMATS {
+    candidates.push((
+      LegacyDataTypes::Date,
+      Some(format),
+      share(&|v| NaiveDateTime::parse_from_str(v, format).is_ok()),
+    ));
+  }
+  // ISO-8601 with an offset and the like are left to the ingestion to detect, numbers aren't since
+  // every one of them would pass as an epoch time or Excel serial date
+  let detected = TimestampParser::new(None, None).expect("the defaults are valid");
+  candidates.push((
+    LegacyDataTypes::Date,
+    None,
+    share(&|v| v.parse::<f64>().is_err() && detected.parse(v).is_ok()),
+  ));
+  candidates.push((
+    LegacyDataTypes::Json,
+    None,
+    share(&|v| {
+      serde_json::from_str::<serde_json::Value>(v).is_ok_and(|j| j.is_object() || j.is_array())
+    }),
+  ));
+
+  // the candidates are in order of how specific they are, so the first of the best ones wins a tie
+  let best = candidates
+    .into_iter()
+    .reduce(|best, candidate| {
+      if candidate.2 > best.2 {
+        candidate
+      } else {
+        best
+      }
+    })
+    .expect("there are always candidates");
+
+  if best.2 < MIN_CONFIDENCE {
+    return (LegacyDataTypes::String, None, 1.0);
+  }
+
+  best
+}
+
+fn is_boolean(value: &str) -> bool {
+  matches!(
+    value.to_lowercase().as_str(),
+    "true" | "false" | "t" | "f" | "yes" | "no"
+  )
+}
+
+/// Anything past the 7 significant digits of a 32 bit float makes for a float64
+fn is_float(value: &str) -> bool {
+  let Some(value) = strip_thousands_separators(value) else {
+    return false;
+  };
+  let digits = value
+    .split(['e', 'E'])
+    .next()
+    .unwrap_or_default()
+    .chars()
+    .filter(|c| c.is_ascii_digit())
+    .collect::<String>();
+
+  value.parse::<f32>().is_ok_and(|f| f.is_finite()) && digits.trim_start_matches('0').len() <= 7
+}
+
+/// Headers as Postgres friendly column names, lower case with anything other than letters, digits
+/// and underscores replaced by underscores
+fn column_name(header: &str) -> String {
+  let name: String = header
+    .trim()
+    .to_lowercase()
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect();
+
+  match name.chars().next() {
+    None => "column".to_string(),
+    Some(c) if c.is_ascii_digit() => format!("_{name}"),
+    Some(_) => name,
+  }
+}
@@ -0,0 +1,158 @@
+use std::str::FromStr;
+
+use datafusion::arrow::csv::reader::Format;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::prelude::CsvReadOptions;
+use regex::Regex;
+
+use crate::timeseries::errors::QueryError;
+
+/// How a CSV file is laid out. Every field is optional, anything left out falls back to DataFusion's
+/// defaults: comma delimited, double quoted, a header row and a schema inferred from the first 1000
+/// rows. Single character options take exactly one ASCII character, e.g. `"\t"` for tab separated
+/// files.
+#[napi(object)]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CsvDialect {
+  pub delimiter: Option<String>,
+  pub quote: Option<String>,
+  pub escape: Option<String>,
+  /// Whether the first row holds the column names, when it doesn't the columns are named
+  /// `column_1`, `column_2` and so on unless `schema` names them
+  pub has_header: Option<bool>,
+  /// Lines starting with this character are skipped
+  pub comment: Option<String>,
+  /// A value that's read as null wherever it's the whole field, e.g. `NaN` or `-9999`
+  pub null_value: Option<String>,
+  /// How many rows are read to infer the schema, ignored when `schema` is set
+  pub schema_infer_max_records: Option<u32>,
+  /// The columns of the file in order, skips schema inference altogether
+  pub schema: Option<Vec<CsvColumn>>,
+}
+
+#[napi(object)]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CsvColumn {
+  pub name: String,
+  /// An Arrow data type, e.g. `Int64`, `Float64`, `Utf8` or `Timestamp(Nanosecond, None)`
+  pub data_type: String,
+  /// Defaults to true
+  pub nullable: Option<bool>,
+}
+
+impl CsvDialect {
+  /// The schema the file is read with, if one was given
+  pub fn schema(&self) -> Result<Option<Schema>, QueryError> {
+    match &self.schema {
+      None => Ok(None),
+      Some(columns) => Ok(Some(columns_schema(columns, "csv schema")?)),
+    }
+  }
+
+  /// Validates the dialect and turns it into DataFusion's read options. `schema` is the one returned
+  /// by `schema()`, it's passed back in because the read options only borrow it
+  pub fn read_options<'a>(
+    &self,
+    schema: Option<&'a Schema>,
+  ) -> Result<CsvReadOptions<'a>, QueryError> {
+    let mut options = CsvReadOptions::new();
+
+    if let Some(delimiter) = single_byte(&self.delimiter, "delimiter")? {
+      options = options.delimiter(delimiter);
+    }
+    if let Some(quote) = single_byte(&self.quote, "quote")? {
+      options = options.quote(quote);
+    }
+    if let Some(escape) = single_byte(&self.escape, "escape")? {
+      options = options.escape(escape);
+    }
+    if let Some(comment) = single_byte(&self.comment, "comment")? {
+      options = options.comment(comment);
+    }
+    if let Some(has_header) = self.has_header {
+      options = options.has_header(has_header);
+    }
+    // only used while inferring the schema, see register_csv_with_null_value for reading. The null
+    // value is matched literally
+    if let Some(null_value) = &self.null_value {
+      options = options.null_regex(Some(format!("^{}$", regex::escape(null_value))));
+    }
+    if let Some(max_records) = self.schema_infer_max_records {
+      if max_records == 0 {
+        return Err(QueryError::BadData(
+          "csv schemaInferMaxRecords must be greater than 0".to_string(),
+        ));
+      }
+      options = options.schema_infer_max_records(max_records as usize);
+    }
+    if let Some(schema) = schema {
+      options = options.schema(schema);
+    }
+
+    Ok(options)
+  }
+
+  /// Validates the dialect and turns it into the format Arrow's CSV reader reads with, for reading
+  /// files outside of a session
+  pub fn format(&self) -> Result<Format, QueryError> {
+    let mut format = Format::default().with_header(self.has_header.unwrap_or(true));
+
+    if let Some(delimiter) = single_byte(&self.delimiter, "delimiter")? {
+      format = format.with_delimiter(delimiter);
+    }
+    if let Some(quote) = single_byte(&self.quote, "quote")? {
+      format = format.with_quote(quote);
+    }
+    if let Some(escape) = single_byte(&self.escape, "escape")? {
+      format = format.with_escape(escape);
+    }
+    if let Some(comment) = single_byte(&self.comment, "comment")? {
+      format = format.with_comment(comment);
+    }
+    if let Some(null_value) = &self.null_value {
+      let null_regex = Regex::new(format!("^{}$", regex::escape(null_value)).as_str())
+        .map_err(|e| QueryError::BadData(format!("csv nullValue is not valid: {e}")))?;
+      format = format.with_null_regex(null_regex);
+    }
+
+    Ok(format)
+  }
+}
+
+/// Turns declared columns into a schema, `what` names them in errors
+pub fn columns_schema(columns: &[CsvColumn], what: &str) -> Result<Schema, QueryError> {
+  if columns.is_empty() {
+    return Err(QueryError::BadData(format!(
+      "{what} must have at least one column"
+    )));
+  }
+
+  let mut fields = vec![];
+  for column in columns {
+    let data_type = DataType::from_str(column.data_type.as_str()).map_err(|e| {
+      QueryError::BadData(format!(
+        "{what} column {} has an invalid data type {}: {e}",
+        column.name, column.data_type
+      ))
+    })?;
+
+    fields.push(Field::new(
+      column.name.clone(),
+      data_type,
+      column.nullable.unwrap_or(true),
+    ));
+  }
+
+  Ok(Schema::new(fields))
+}
+
+fn single_byte(value: &Option<String>, name: &str) -> Result<Option<u8>, QueryError> {
+  match value.as_deref().map(str::as_bytes) {
+    None => Ok(None),
+    Some([byte]) if byte.is_ascii() => Ok(Some(*byte)),
+    Some(_) => Err(QueryError::BadData(format!(
+      "csv {name} must be a single ASCII character, got {:?}",
+      value.as_deref().unwrap_or_default()
+    ))),
+  }
+}
@@ -1,81 +1,401 @@
-use connection_string::AdoNetString;
-use datafusion::prelude::{CsvReadOptions, SessionConfig, SessionContext};
+use datafusion::arrow::array::{ArrayRef, RecordBatch, RecordBatchOptions};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::error::ArrowError;
+use datafusion::datasource::file_format::csv::CsvFormat;
+use datafusion::datasource::listing::{ListingOptions, ListingTableConfig, ListingTableUrl};
+use datafusion::datasource::MemTable;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::functions::core::expr_fn::nullif;
+use datafusion::logical_expr::cast;
+use datafusion::prelude::{
+  ident, lit, CsvReadOptions, NdJsonReadOptions, ParquetReadOptions, SessionConfig, SessionContext,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 use url::Url;
 
 use super::file_metadata::extract_table_info;
 use super::file_metadata::FileMetadata;
 use super::file_metadata::FileType;
-use crate::timeseries::azure_object_store::register_azure_store;
+use super::file_metadata::TableMetadata;
+use super::graph_snapshot::GraphSnapshot;
+#[cfg(feature = "hdf5")]
+use super::hdf5_table::{hdf5_tables, Hdf5Source};
+use super::postgres_table::{LegacyTables, PostgresTableProvider};
+use super::tdms_table::TdmsTableProvider;
 use crate::timeseries::errors::QueryError;
+use crate::timeseries::storage_connection::StorageConnection;
+use crate::timeseries::time_functions::register_time_functions;
 
+/// How many rows go into each record batch, for the table providers handing them to DataFusion as
+/// well as the ingestions reading them
+pub(crate) const BATCH_SIZE: usize = 8192;
+
+/// A record batch of `rows` rows. The row count is given rather than taken from the arrays since an
+/// empty projection (e.g. COUNT(*)) has none but still needs to report how many rows it covers
+pub(crate) fn record_batch(
+  schema: SchemaRef,
+  arrays: Vec<ArrayRef>,
+  rows: usize,
+) -> Result<RecordBatch, ArrowError> {
+  let options = RecordBatchOptions::new().with_row_count(Some(rows));
+  RecordBatch::try_new_with_options(schema, arrays, &options)
+}
+
+/// A session with every one of `files` registered and no memory limit, the napi functions all go
+/// through populate_session_with_runtime so their limits hold
+#[cfg(test)]
 pub async fn populate_session(
-  storage_connection: &AdoNetString,
+  storage_connection: &StorageConnection,
+  files: Vec<FileMetadata>,
+) -> Result<SessionContext, QueryError> {
+  populate_session_with_runtime(storage_connection, files, Arc::new(RuntimeEnv::default())).await
+}
+
+/// Like populate_session, with queries run by `runtime` so its memory pool limits them
+pub async fn populate_session_with_runtime(
+  storage_connection: &StorageConnection,
   files: Vec<FileMetadata>,
+  runtime: Arc<RuntimeEnv>,
 ) -> Result<SessionContext, QueryError> {
-  let ctx = SessionContext::new_with_config(SessionConfig::new().with_information_schema(true));
-
-  let provider =
-    storage_connection
-      .get("provider")
-      .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
-        msg: "Provider not set in connection string".to_string(),
-      })?;
-  let root_endpoint = match provider.as_str() {
-    "azure_blob" => storage_connection.get("blobendpoint").ok_or_else(|| {
-      QueryError::ReceivedNullDataInRequest {
-        msg: "blobEndpoint not set in connection string with provider: azure".to_string(),
+  let ctx = new_session(storage_connection, runtime)?;
+
+  for table in &extract_table_info(files)? {
+    register_file(&ctx, storage_connection, table).await?;
+  }
+
+  Ok(ctx)
+}
+
+/// Like populate_session, but a file that can't be registered is left out of the session instead of
+/// failing the rest. Returns the id of the file behind each table, and the ids of the files that
+/// were left out along with why
+pub async fn populate_session_partial(
+  storage_connection: &StorageConnection,
+  files: Vec<FileMetadata>,
+) -> Result<(SessionContext, TableFiles, Vec<(String, QueryError)>), QueryError> {
+  let ctx = new_session(storage_connection, Arc::new(RuntimeEnv::default()))?;
+  let mut table_files = TableFiles::new();
+  let mut failed = vec![];
+
+  for file in files {
+    let id = file.id.clone();
+    let registered = async {
+      let mut table_names = vec![];
+      for table in &extract_table_info(vec![file])? {
+        table_names.extend(register_file(&ctx, storage_connection, table).await?);
       }
-    })?,
-    "filesystem" => storage_connection.get("rootfilepath").ok_or_else(|| {
-      QueryError::ReceivedNullDataInRequest {
-        msg: "rootFilePath not set in connection string with provider: filesystem".to_string(),
+      Ok::<Vec<String>, QueryError>(table_names)
+    }
+    .await;
+
+    match registered {
+      Ok(table_names) => {
+        for name in table_names {
+          table_files.insert(name, id.clone());
+        }
+      }
+      Err(e) => {
+        // the table it would have been, so statements about it can still be traced back to it
+        table_files
+          .entry(format!("table_{id}"))
+          .or_insert_with(|| id.clone());
+        failed.push((id, e));
       }
-    })?,
-    _ => {
-      return Err(QueryError::BadData(format!(
-        "couldn't set base/root endpoint. {provider} is not supported as a storage provider"
-      )))
     }
+  }
+
+  Ok((ctx, table_files, failed))
+}
+
+/// The id of the file each table was registered from, keyed by table name
+pub type TableFiles = HashMap<String, String>;
+
+/// Creates a session with no tables registered, the object store for the connection is registered
+/// so that files can be read from and results written to it. The time-series functions from
+/// time_functions are available in every session
+pub fn new_session(
+  storage_connection: &StorageConnection,
+  runtime: Arc<RuntimeEnv>,
+) -> Result<SessionContext, QueryError> {
+  // filter pushdown lets parquet scans skip row groups and rows using the query predicates instead
+  // of reading the whole file and filtering afterwards
+  let config = SessionConfig::new()
+    .with_information_schema(true)
+    .set_bool("datafusion.execution.parquet.pushdown_filters", true)
+    .set_bool("datafusion.execution.parquet.reorder_filters", true)
+    // glob tables reach into nested partition directories
+    .set_bool(
+      "datafusion.execution.listing_table_ignore_subdirectory",
+      false,
+    );
+  let ctx = SessionContext::new_with_config_rt(config, runtime);
+  register_time_functions(&ctx);
+
+  if let Some(store) = storage_connection.object_store()? {
+    let object_store_url = Url::parse(storage_connection.root_url().as_str())?;
+    ctx.register_object_store(&object_store_url, store);
+  }
+
+  Ok(ctx)
+}
+
+/// Registers a file with the session and returns the names of the tables it was registered as,
+/// which is just the table name for everything except HDF5 files with groups
+pub async fn register_file(
+  ctx: &SessionContext,
+  storage_connection: &StorageConnection,
+  table: &TableMetadata,
+) -> Result<Vec<String>, QueryError> {
+  let path = format!("{}/{}", storage_connection.root_url(), table.file_path);
+  let partition_cols = partition_columns(ctx, table, path.as_str()).await?;
+  // DataFusion reads `*`, `?` and `[` in a filesystem path as a glob, a single file is handed over as
+  // a URL instead so its name is read as it is
+  let location = match storage_connection {
+    StorageConnection::Filesystem(_) if !table.is_listing => file_url(path.as_str())?,
+    _ => path.clone(),
   };
 
-  match provider.as_str() {
-    "azure_blob" => {
-      let object_store_url = Url::parse(root_endpoint.as_str())?;
-      let azure_store = register_azure_store(storage_connection)?;
-      ctx.register_object_store(&object_store_url, Arc::new(azure_store));
+  match table.file_type {
+    FileType::Csv => {
+      let dialect = table.csv_options.clone().unwrap_or_default();
+      let schema = dialect.schema()?;
+      let mut options = dialect
+        .read_options(schema.as_ref())?
+        .table_partition_cols(partition_cols);
+      // a glob already picks out the files it wants, nothing more is filtered out by extension
+      if table.is_listing {
+        options = options.file_extension("");
+      }
+
+      match &dialect.null_value {
+        None => {
+          ctx
+            .register_csv(table.name.as_str(), location.as_str(), options)
+            .await?
+        }
+        Some(null_value) => {
+          return register_csv_with_null_value(ctx, table, location.as_str(), options, null_value)
+            .await
+        }
+      }
     }
-    "filesystem" => (),
-    _ => {
-      return Err(QueryError::BadData(format!(
-        "Failed to register object store. {provider} is not supported as a storage provider"
-      )))
+    FileType::Parquet => {
+      let mut options = ParquetReadOptions::default().table_partition_cols(partition_cols);
+      if table.is_listing {
+        options = options.file_extension("");
+      }
+
+      ctx
+        .register_parquet(table.name.as_str(), location.as_str(), options)
+        .await?;
     }
-  }
-  let table_info = extract_table_info(files)?;
-
-  for table in &table_info {
-    let path = format!("{}/{}", root_endpoint, table.file_path);
-    match table.file_type {
-      FileType::Csv => {
-        ctx
-          .register_csv(
-            table.name.as_str(),
-            path.as_str(),
-            CsvReadOptions::default(),
-          )
-          .await?;
+    FileType::Json => {
+      let mut options = NdJsonReadOptions::default().table_partition_cols(partition_cols);
+      if table.is_listing {
+        options = options.file_extension("");
       }
-      // FileType::Parquet => {
-      //   todo!("haven't gotten to Parquet files yet.")
-      // }
-      FileType::Json => {
-        ctx
-          .register_json(table.name.as_str(), path.as_str(), Default::default())
-          .await?;
+
+      ctx
+        .register_json(table.name.as_str(), location.as_str(), options)
+        .await?;
+    }
+    FileType::Tdms => {
+      let url = ListingTableUrl::parse(location.as_str())?;
+      let store = ctx.runtime_env().object_store(&url)?;
+      let bytes = store.get(url.prefix()).await?.bytes().await?;
+
+      ctx.register_table(
+        table.name.as_str(),
+        Arc::new(TdmsTableProvider::try_new(bytes)?),
+      )?;
+    }
+    #[cfg(feature = "hdf5")]
+    FileType::Hdf5 => {
+      // libhdf5 needs a local file, so anything that isn't on the filesystem is downloaded first
+      let source = match storage_connection {
+        StorageConnection::Filesystem(_) => Hdf5Source::from_path(path.as_str()),
+        _ => {
+          let url = ListingTableUrl::parse(path.as_str())?;
+          let store = ctx.runtime_env().object_store(&url)?;
+          Hdf5Source::download(store, url.prefix()).await?
+        }
+      };
+
+      let mut table_names = vec![];
+      for (name, hdf5_table) in hdf5_tables(table.name.as_str(), source)? {
+        ctx.register_table(name.as_str(), Arc::new(hdf5_table))?;
+        table_names.push(name);
       }
+      return Ok(table_names);
     }
   }
 
-  Ok(ctx)
+  Ok(vec![table.name.clone()])
+}
+
+/// `path` as a `file://` URL
+fn file_url(path: &str) -> Result<String, QueryError> {
+  let url = std::path::absolute(path)
+    .ok()
+    .and_then(|absolute| Url::from_file_path(absolute).ok())
+    .ok_or_else(|| QueryError::InvalidFileMetadata(format!("{path} is not a valid file path")))?;
+
+  Ok(url.to_string())
+}
+
+/// Registers the legacy `y_{id}` table of each data source with the session under the same name.
+/// Their schemas are read from Postgres now, their rows only once a query reads them
+pub async fn register_legacy_tables(
+  ctx: &SessionContext,
+  legacy_tables: &LegacyTables,
+) -> Result<Vec<String>, QueryError> {
+  let pool = legacy_tables.pool()?;
+
+  let mut table_names = vec![];
+  for id in &legacy_tables.data_source_ids {
+    let name = format!("y_{id}");
+    let provider = PostgresTableProvider::try_new(pool.clone(), name.clone()).await?;
+
+    ctx.register_table(name.as_str(), Arc::new(provider))?;
+    table_names.push(name);
+  }
+
+  Ok(table_names)
+}
+
+/// Registers the node and edge snapshots of a container with the session as `nodes` and `edges`.
+/// They're loaded into memory up front, unlike the files and legacy tables
+pub async fn register_graph_snapshot(
+  ctx: &SessionContext,
+  snapshot: &GraphSnapshot,
+) -> Result<Vec<String>, QueryError> {
+  let (nodes, edges) = snapshot.load().await?;
+
+  register_snapshot_tables(ctx, nodes, edges)
+}
+
+/// Registers already loaded node and edge snapshots as `nodes` and `edges`
+pub fn register_snapshot_tables(
+  ctx: &SessionContext,
+  nodes: RecordBatch,
+  edges: RecordBatch,
+) -> Result<Vec<String>, QueryError> {
+  let mut table_names = vec![];
+  for (name, batch) in [("nodes", nodes), ("edges", edges)] {
+    let table = MemTable::try_new(batch.schema(), vec![vec![batch]])?;
+
+    ctx.register_table(name, Arc::new(table))?;
+    table_names.push(name.to_string());
+  }
+
+  Ok(table_names)
+}
+
+/// The hive style partition columns of a glob table, as given in its metadata or inferred from the
+/// directories of the files it matches
+async fn partition_columns(
+  ctx: &SessionContext,
+  table: &TableMetadata,
+  path: &str,
+) -> Result<Vec<(String, DataType)>, QueryError> {
+  if !table.is_listing {
+    return Ok(vec![]);
+  }
+  if matches!(table.file_type, FileType::Tdms) {
+    return Err(QueryError::InvalidFileMetadata(format!(
+      "{} can't be a glob, TDMS files are registered one at a time",
+      table.file_path
+    )));
+  }
+  #[cfg(feature = "hdf5")]
+  if matches!(table.file_type, FileType::Hdf5) {
+    return Err(QueryError::InvalidFileMetadata(format!(
+      "{} can't be a glob, HDF5 files are registered one at a time",
+      table.file_path
+    )));
+  }
+
+  if let Some(columns) = &table.partition_columns {
+    let mut partition_cols = vec![];
+    for column in columns {
+      let data_type = match &column.data_type {
+        None => DataType::Utf8,
+        Some(data_type) => DataType::from_str(data_type.as_str()).map_err(|e| {
+          QueryError::BadData(format!(
+            "partition column {} has an invalid data type {data_type}: {e}",
+            column.name
+          ))
+        })?,
+      };
+      partition_cols.push((column.name.clone(), data_type));
+    }
+    return Ok(partition_cols);
+  }
+
+  // the format only matters for reading the files, inferring the partitions just lists them
+  let options = ListingOptions::new(Arc::new(CsvFormat::default()));
+  let config = ListingTableConfig::new(ListingTableUrl::parse(path)?)
+    .with_listing_options(options)
+    .infer_partitions_from_path(&ctx.state())
+    .await?;
+
+  Ok(
+    config
+      .options
+      .map(|options| options.table_partition_cols)
+      .unwrap_or_default(),
+  )
+}
+
+/// DataFusion only uses the null regex of a CSV while inferring its schema, not while reading it. So
+/// the table is a view over the file read with every column as text, which turns the null value into
+/// nulls and casts each column to its inferred type. The file itself isn't registered as a table
+async fn register_csv_with_null_value(
+  ctx: &SessionContext,
+  table: &TableMetadata,
+  path: &str,
+  options: CsvReadOptions<'_>,
+  null_value: &str,
+) -> Result<Vec<String>, QueryError> {
+  let typed = ctx.read_csv(path, options.clone()).await?.schema().clone();
+
+  // partition columns come from the directories rather than the file, they're left as they are
+  let is_partition = |name: &str| {
+    options
+      .table_partition_cols
+      .iter()
+      .any(|(col, _)| col == name)
+  };
+
+  let text = Schema::new(
+    typed
+      .fields()
+      .iter()
+      .filter(|f| !is_partition(f.name()))
+      .map(|f| Field::new(f.name(), DataType::Utf8, true))
+      .collect::<Vec<_>>(),
+  );
+
+  let columns = typed
+    .fields()
+    .iter()
+    .map(|f| match is_partition(f.name()) {
+      true => ident(f.name()),
+      false => cast(
+        nullif(ident(f.name()), lit(null_value)),
+        f.data_type().clone(),
+      )
+      .alias(f.name()),
+    })
+    .collect::<Vec<_>>();
+
+  let view = ctx
+    .read_csv(path, options.schema(&text))
+    .await?
+    .select(columns)?;
+  ctx.register_table(table.name.as_str(), view.into_view())?;
+
+  Ok(vec![table.name.clone()])
 }
@@ -1,3 +1,4 @@
+use crate::timeseries::ingestion::RowError;
 use napi::Status;
 use thiserror::Error;
 
@@ -9,6 +10,8 @@ pub enum TimeseriesError {
   Unwrap(String),
   #[error("thread error {0}")]
   Thread(String),
+  #[error("ingestion was aborted")]
+  Aborted,
   #[error("sqlx error {0}")]
   SqlX(#[from] sqlx::Error),
   #[error("invalid data or structure: {0}")]
@@ -19,6 +22,12 @@ pub enum TimeseriesError {
   CsvAsync(#[from] csv_async::Error),
   #[error("system IO error: {0}")]
   StdIO(#[from] std::io::Error),
+  #[error("invalid ingestion options: {0}")]
+  InvalidOptions(String),
+  #[error("rejected {0}")]
+  RejectedRow(RowError),
+  #[error("more than {0} rows were rejected")]
+  TooManyRejectedRows(u32),
   #[error("csv not valid : {0}")]
   CsvValidation(#[from] ValidationError),
   #[error("time parse error : {0}")]
@@ -29,6 +38,27 @@ pub enum TimeseriesError {
   Json(#[from] serde_json::Error),
   #[error("timeseries query error {0}")]
   Query(#[from] QueryError),
+  /// An error an ingestion stopped with, reported again by a later call on the same Ingestion
+  #[error("{0}")]
+  Ingestion(String),
+}
+
+impl TimeseriesError {
+  /// A copy of the error for reporting it more than once. Errors that come from other crates can't
+  /// be copied, they're kept as their message
+  pub fn duplicate(&self) -> TimeseriesError {
+    match self {
+      TimeseriesError::MissingConnectionString => TimeseriesError::MissingConnectionString,
+      TimeseriesError::Unwrap(msg) => TimeseriesError::Unwrap(msg.clone()),
+      TimeseriesError::Thread(msg) => TimeseriesError::Thread(msg.clone()),
+      TimeseriesError::Aborted => TimeseriesError::Aborted,
+      TimeseriesError::InvalidOptions(msg) => TimeseriesError::InvalidOptions(msg.clone()),
+      TimeseriesError::RejectedRow(row) => TimeseriesError::RejectedRow(row.clone()),
+      TimeseriesError::TooManyRejectedRows(n) => TimeseriesError::TooManyRejectedRows(*n),
+      TimeseriesError::Ingestion(msg) => TimeseriesError::Ingestion(msg.clone()),
+      e => TimeseriesError::Ingestion(e.to_string()),
+    }
+  }
 }
 
 impl From<TimeseriesError> for napi::Error {
@@ -49,6 +79,10 @@ pub enum QueryError {
   Napi(#[from] napi::Error),
   #[error("datafusion error {0}")]
   DataFusion(#[from] datafusion::error::DataFusionError),
+  #[error("arrow error {0}")]
+  Arrow(#[from] datafusion::arrow::error::ArrowError),
+  #[error("parquet error {0}")]
+  Parquet(#[from] datafusion::parquet::errors::ParquetError),
   #[error("connection string error {0}")]
   ConnectionString(#[from] connection_string::Error),
   #[error("received null required parameter in request {msg}")]
@@ -57,8 +91,6 @@ pub enum QueryError {
   BadData(String),
   #[error("azure builder error {0}")]
   AzureBuilder(#[from] object_store::Error),
-  #[error("azure blob storage sdk error {0}")]
-  AzureSDK(#[from] azure_storage::Error),
   #[error("serde json error {0}")]
   Json(#[from] serde_json::Error),
   #[error("dotenvy error {0}")]
@@ -69,15 +101,43 @@ pub enum QueryError {
   StdIO(#[from] std::io::Error),
   #[error("url parse error {0}")]
   ParseURL(#[from] url::ParseError),
+  #[cfg(feature = "hdf5")]
+  #[error("hdf5 error {0}")]
+  Hdf5(#[from] hdf5::Error),
+  #[error("tdms error {0}")]
+  Tdms(String),
+  #[error("sqlx error {0}")]
+  SqlX(#[from] sqlx::Error),
+  #[error("graph snapshot error {0}")]
+  Snapshot(#[from] crate::snapshot::errors::SnapshotError),
   #[error("invalid file metadata {0}")]
   InvalidFileMetadata(String),
   #[error("not unimplemented yet error {0}")]
   ToDo(String),
+  #[error("query limit exceeded {0}")]
+  Limit(#[from] QueryLimitError),
+}
+
+/// A query that was refused or stopped by its QueryPolicy. These are the caller's fault rather than
+/// a failure on our side, so they reach Javascript with the `InvalidArg` status as their code
+#[derive(Error, Debug)]
+pub enum QueryLimitError {
+  #[error("only SELECT queries are allowed, {0} statements are not")]
+  ReadOnly(String),
+  #[error("query did not finish within the {0}ms timeout")]
+  Timeout(u64),
+  #[error("query needed more memory than the limit allows: {0}")]
+  MemoryLimit(String),
 }
 
 impl From<QueryError> for napi::Error {
   fn from(value: QueryError) -> Self {
-    napi::Error::new(Status::GenericFailure, format!("{:?}", value))
+    match value {
+      QueryError::Limit(e) => {
+        napi::Error::new(Status::InvalidArg, format!("query limit exceeded {e}"))
+      }
+      _ => napi::Error::new(Status::GenericFailure, format!("{:?}", value)),
+    }
   }
 }
 
@@ -1,18 +1,42 @@
 use std::path::Path;
 
+use crate::timeseries::csv_dialect::CsvDialect;
 use crate::timeseries::errors::QueryError;
 
 #[napi(object)]
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct FileMetadata {
   pub id: String,
   #[napi(js_name = "file_name")]
   pub file_name: String,
   #[napi(js_name = "access_path")]
   pub file_path: String,
+  /// How the file is laid out if it's a CSV, ignored for every other type of file
+  #[napi(js_name = "csv_options")]
+  pub csv_options: Option<CsvDialect>,
+  /// Hive style `name=value` directories between `access_path` and the files, e.g. year, month and
+  /// day for `year=2024/month=07/day=30/`. Only used when `glob` is set, in which case they're
+  /// inferred as strings if left out
+  #[napi(js_name = "partition_columns")]
+  pub partition_columns: Option<Vec<PartitionColumn>>,
+  /// Whether `file_name` is a glob such as `**/*.parquet` rather than the name of a single file.
+  /// Defaults to false, so a name like `run[1].csv` is read as it is
+  pub glob: Option<bool>,
 }
 
-/// Extracts the table_name and file extension and returns it with the path
+#[napi(object)]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PartitionColumn {
+  pub name: String,
+  /// An Arrow data type, e.g. `Int32` or `Utf8`. Defaults to `Utf8`. Equality filters on partition
+  /// columns are turned into a directory to list, so zero padded values such as `month=07` need to
+  /// stay `Utf8` to be found
+  pub data_type: Option<String>,
+}
+
+/// Extracts the table_name and file extension and returns it with the path. A `file_name` can also
+/// be a glob such as `**/*.parquet` when `glob` is set, in which case every matching file under
+/// `access_path` makes up the table
 pub fn extract_table_info(files: Vec<FileMetadata>) -> Result<Vec<TableMetadata>, QueryError> {
   let mut table_info = Vec::new();
   for file in files {
@@ -35,24 +59,17 @@ pub fn extract_table_info(files: Vec<FileMetadata>) -> Result<Vec<TableMetadata>
       Some(ex) => match ex {
         s if s.starts_with("csv") => FileType::Csv,
         s if s.starts_with("json") => FileType::Json,
-        s if s.starts_with("parquet") => {
-          return Err(QueryError::ToDo(format!(
-            "Parquet file (id: {}). Parquet is currently unsupported for Timeseries2",
-            file.id
-          )))
-        }
-        s if s.starts_with("hdf5") => {
-          return Err(QueryError::ToDo(format!(
-            "HDF5 file (id: {}). HDF5 is currently unsupported for Timeseries2",
-            file.id
-          )))
-        }
-        s if s.starts_with("tdms") => {
+        s if s.starts_with("parquet") => FileType::Parquet,
+        #[cfg(feature = "hdf5")]
+        s if s.starts_with("hdf5") || s.starts_with("h5") => FileType::Hdf5,
+        #[cfg(not(feature = "hdf5"))]
+        s if s.starts_with("hdf5") || s.starts_with("h5") => {
           return Err(QueryError::ToDo(format!(
-            "TDMS file (id: {}). TDMS is currently unsupported for Timeseries2",
+            "HDF5 file (id: {}). This build of Timeseries2 was compiled without the hdf5 feature",
             file.id
           )))
         }
+        s if s.starts_with("tdms") => FileType::Tdms,
         _ => {
           return Err(QueryError::ToDo(format!(
             "File Path Metadata file extension was corrupted by the uuid from id: {}",
@@ -66,6 +83,9 @@ pub fn extract_table_info(files: Vec<FileMetadata>) -> Result<Vec<TableMetadata>
       name: format!("table_{}", file.id),
       file_path: full_path_string.clone(),
       file_type: ext,
+      csv_options: file.csv_options,
+      partition_columns: file.partition_columns,
+      is_listing: file.glob.unwrap_or(false),
     });
   }
   Ok(table_info)
@@ -75,10 +95,17 @@ pub struct TableMetadata {
   pub name: String,
   pub file_path: String,
   pub file_type: FileType,
+  pub csv_options: Option<CsvDialect>,
+  pub partition_columns: Option<Vec<PartitionColumn>>,
+  /// Whether the table is made up of every file matching a glob rather than a single file
+  pub is_listing: bool,
 }
 
 pub enum FileType {
   Csv,
-  // Parquet,
+  Parquet,
   Json,
+  Tdms,
+  #[cfg(feature = "hdf5")]
+  Hdf5,
 }
@@ -0,0 +1,42 @@
+use datafusion::arrow::array::RecordBatch;
+
+use crate::config::Configuration;
+use crate::snapshot::generator::SnapshotGenerator;
+use crate::timeseries::errors::QueryError;
+
+/// The graph of a container to query alongside the files, registered as a `nodes` and an `edges`
+/// table. Both are the graph as it was at `timestamp`, or as it is now if that's left out, so
+/// readings can be joined to the nodes they belong to as of when they were taken, e.g.
+/// `SELECT n.metatype_name, r.* FROM table_1 r JOIN nodes n ON n.original_data_id = r.sensor_id`
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct GraphSnapshot {
+  pub db_connection_string: String,
+  pub container_id: String,
+  /// Anything Postgres can read as a timestamp, e.g. `2024-07-30 07:32:27`
+  pub timestamp: Option<String>,
+}
+
+impl GraphSnapshot {
+  /// Builds the node and edge snapshots, in that order
+  pub async fn load(&self) -> Result<(RecordBatch, RecordBatch), QueryError> {
+    let container_id = self.container_id.parse::<u64>().map_err(|e| {
+      QueryError::BadData(format!(
+        "container id {} is not valid: {e}",
+        self.container_id
+      ))
+    })?;
+    let config = Configuration::from_connection_string(self.db_connection_string.clone())
+      .map_err(|e| QueryError::BadData(e.to_string()))?;
+
+    let mut generator = SnapshotGenerator::new(config).await?;
+    generator
+      .generate_snapshot(container_id, self.timestamp.clone())
+      .await?;
+    generator
+      .generate_edge_snapshot(container_id, self.timestamp.clone())
+      .await?;
+
+    Ok((generator.nodes_batch()?, generator.edges_batch()?))
+  }
+}
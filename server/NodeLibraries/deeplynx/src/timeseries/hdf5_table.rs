@@ -0,0 +1,436 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+  ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array,
+  Int8Array, StringArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::catalog::Session;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::DataFusionError;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::stream::RecordBatchReceiverStream;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use hdf5::types::{
+  FixedAscii, FixedUnicode, FloatSize, IntSize, TypeDescriptor, VarLenAscii, VarLenUnicode,
+};
+use hdf5::{Dataset, Group, H5Type};
+use object_store::ObjectStore;
+
+use crate::timeseries::datafusion_session::{record_batch, BATCH_SIZE};
+use crate::timeseries::errors::QueryError;
+
+/// Schema metadata key listing the datasets of a group that couldn't be read as columns, with why
+pub const SKIPPED_DATASETS: &str = "skipped_datasets";
+
+// the longest fixed length string read as a column, in bytes
+const MAX_FIXED_STRING: usize = 4096;
+
+/// Hdf5Source is the local copy of an HDF5 file. libhdf5 can only read from disk, so files that live
+/// in an object store are downloaded to a temporary file that is removed once every table built from
+/// it has been dropped.
+#[derive(Debug)]
+pub struct Hdf5Source {
+  path: PathBuf,
+  _download: Option<tempfile::TempPath>,
+}
+
+impl Hdf5Source {
+  pub fn from_path(path: impl Into<PathBuf>) -> Self {
+    Hdf5Source {
+      path: path.into(),
+      _download: None,
+    }
+  }
+
+  /// Copies the object at `location` into a temporary file so that libhdf5 can open it
+  pub async fn download(
+    store: Arc<dyn ObjectStore>,
+    location: &object_store::path::Path,
+  ) -> Result<Self, QueryError> {
+    let bytes = store.get(location).await?.bytes().await?;
+
+    let mut file = tempfile::Builder::new().suffix(".hdf5").tempfile()?;
+    file.write_all(&bytes)?;
+
+    Ok(Hdf5Source {
+      path: file.path().to_path_buf(),
+      _download: Some(file.into_temp_path()),
+    })
+  }
+}
+
+/// Builds a table for every group in the file that holds at least one tabular dataset. The root group
+/// is registered as `table_name` and nested groups as `{table_name}_{group}` (e.g. `table_1_run_2`)
+/// so they can be joined against each other or any other file in the session.
+pub fn hdf5_tables(
+  table_name: &str,
+  source: Hdf5Source,
+) -> Result<Vec<(String, Hdf5TableProvider)>, QueryError> {
+  let source = Arc::new(source);
+  let file = hdf5::File::open(&source.path)?;
+
+  let mut tables = vec![];
+  collect_tables(&file, table_name.to_string(), &source, &mut tables)?;
+
+  if tables.is_empty() {
+    return Err(QueryError::InvalidFileMetadata(format!(
+      "HDF5 file for {table_name} does not contain any one or two dimensional datasets"
+    )));
+  }
+
+  Ok(tables)
+}
+
+fn collect_tables(
+  group: &Group,
+  table_name: String,
+  source: &Arc<Hdf5Source>,
+  tables: &mut Vec<(String, Hdf5TableProvider)>,
+) -> Result<(), QueryError> {
+  let mut fields = vec![];
+  let mut columns = vec![];
+  // datasets that can't be read as columns, listed in the schema metadata so they aren't missed
+  let mut skipped = vec![];
+
+  for dataset in group.datasets()? {
+    let descriptor = dataset.dtype()?.to_descriptor()?;
+    let dataset_name = dataset.name();
+    let column_name = leaf_name(&dataset_name);
+
+    let data_type = match arrow_type(&descriptor) {
+      None => {
+        // compound, enum and reference types and very long strings aren't supported as columns yet
+        skipped.push(format!("{column_name} ({descriptor})"));
+        continue;
+      }
+      Some(t) => t,
+    };
+
+    match dataset.shape().as_slice() {
+      [len] => {
+        fields.push(Field::new(column_name, data_type, true));
+        columns.push(Hdf5Column {
+          dataset: dataset_name,
+          index: None,
+          len: *len,
+          descriptor,
+        });
+      }
+      // two dimensional datasets are split into a column per index, named like `velocity[0]`
+      [len, width] if data_type != DataType::Utf8 => {
+        for i in 0..*width {
+          fields.push(Field::new(
+            format!("{column_name}[{i}]"),
+            data_type.clone(),
+            true,
+          ));
+          columns.push(Hdf5Column {
+            dataset: dataset_name.clone(),
+            index: Some(i),
+            len: *len,
+            descriptor: descriptor.clone(),
+          });
+        }
+      }
+      // scalars and higher rank datasets don't map cleanly onto rows
+      shape => skipped.push(format!("{column_name} ({} dimensions)", shape.len())),
+    }
+  }
+
+  if !columns.is_empty() {
+    // datasets in a group don't have to be the same length, shorter ones are padded with nulls
+    let num_rows = columns.iter().map(|c| c.len).max().unwrap_or(0);
+    let mut metadata = HashMap::new();
+    if !skipped.is_empty() {
+      metadata.insert(SKIPPED_DATASETS.to_string(), skipped.join(", "));
+    }
+
+    tables.push((
+      table_name.clone(),
+      Hdf5TableProvider {
+        source: source.clone(),
+        schema: Arc::new(Schema::new_with_metadata(fields, metadata)),
+        columns,
+        num_rows,
+      },
+    ));
+  }
+
+  for child in group.groups()? {
+    let child_name = format!(
+      "{table_name}_{}",
+      sanitize_table_name(leaf_name(&child.name()))
+    );
+    collect_tables(&child, child_name, source, tables)?;
+  }
+
+  Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct Hdf5Column {
+  // full path of the dataset inside the file
+  dataset: String,
+  // the column index when this is a slice of a two dimensional dataset
+  index: Option<usize>,
+  len: usize,
+  descriptor: TypeDescriptor,
+}
+
+/// Hdf5TableProvider exposes the datasets of a single HDF5 group as a DataFusion table. Only the
+/// projected datasets are read, and reads stop once a pushed down limit has been reached.
+#[derive(Debug)]
+pub struct Hdf5TableProvider {
+  source: Arc<Hdf5Source>,
+  schema: SchemaRef,
+  columns: Vec<Hdf5Column>,
+  num_rows: usize,
+}
+
+#[async_trait]
+impl TableProvider for Hdf5TableProvider {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn schema(&self) -> SchemaRef {
+    self.schema.clone()
+  }
+
+  fn table_type(&self) -> TableType {
+    TableType::Base
+  }
+
+  async fn scan(
+    &self,
+    _state: &dyn Session,
+    projection: Option<&Vec<usize>>,
+    _filters: &[Expr],
+    limit: Option<usize>,
+  ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+    let indices = match projection {
+      None => (0..self.columns.len()).collect(),
+      Some(p) => p.clone(),
+    };
+    let schema = Arc::new(self.schema.project(&indices)?);
+    let columns: Vec<Hdf5Column> = indices.iter().map(|i| self.columns[*i].clone()).collect();
+    let num_rows = limit.map_or(self.num_rows, |l| l.min(self.num_rows));
+
+    let partition = Hdf5Scan {
+      source: self.source.clone(),
+      schema: schema.clone(),
+      columns,
+      num_rows,
+    };
+
+    Ok(Arc::new(StreamingTableExec::try_new(
+      schema,
+      vec![Arc::new(partition)],
+      None,
+      vec![],
+      false,
+      limit,
+    )?))
+  }
+}
+
+/// The datasets a query reads, read a batch at a time once DataFusion starts reading the table so
+/// that only the batches DataFusion hasn't gotten to yet are held in memory
+#[derive(Debug)]
+struct Hdf5Scan {
+  source: Arc<Hdf5Source>,
+  schema: SchemaRef,
+  columns: Vec<Hdf5Column>,
+  num_rows: usize,
+}
+
+impl PartitionStream for Hdf5Scan {
+  fn schema(&self) -> &SchemaRef {
+    &self.schema
+  }
+
+  fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+    let mut builder = RecordBatchReceiverStream::builder(self.schema.clone(), 2);
+    let tx = builder.tx();
+    let (source, schema, columns, num_rows) = (
+      self.source.clone(),
+      self.schema.clone(),
+      self.columns.clone(),
+      self.num_rows,
+    );
+
+    // libhdf5 is blocking, keep it off of the async runtime
+    builder.spawn_blocking(move || {
+      let to_df_error = |e: hdf5::Error| DataFusionError::Execution(format!("hdf5 read error {e}"));
+      let file = hdf5::File::open(&source.path).map_err(to_df_error)?;
+      let datasets = columns
+        .iter()
+        .map(|c| file.dataset(&c.dataset))
+        .collect::<Result<Vec<Dataset>, hdf5::Error>>()
+        .map_err(to_df_error)?;
+
+      let mut start = 0;
+      while start < num_rows {
+        let rows = start..(start + BATCH_SIZE).min(num_rows);
+
+        let arrays = columns
+          .iter()
+          .zip(datasets.iter())
+          .map(|(column, dataset)| read_column(dataset, column, rows.clone()))
+          .collect::<Result<Vec<ArrayRef>, hdf5::Error>>()
+          .map_err(to_df_error)?;
+        let batch = record_batch(schema.clone(), arrays, rows.len())?;
+        // the stream has been dropped, the query finished early or was cancelled
+        if tx.blocking_send(Ok(batch)).is_err() {
+          return Ok(());
+        }
+
+        start = rows.end;
+      }
+
+      Ok(())
+    });
+
+    builder.build()
+  }
+}
+
+fn read_column(
+  dataset: &Dataset,
+  column: &Hdf5Column,
+  rows: Range<usize>,
+) -> Result<ArrayRef, hdf5::Error> {
+  // only read the part of the requested rows that this dataset actually has, the rest is null
+  let end = rows.end.min(column.len);
+  let start = rows.start.min(end);
+  let padding = rows.len() - (end - start);
+
+  macro_rules! read_array {
+    ($native:ty, $array:ty) => {{
+      let values = read_values::<$native>(dataset, column, start..end)?;
+      Arc::new(<$array>::from_iter(
+        values
+          .into_iter()
+          .map(Some)
+          .chain(std::iter::repeat_n(None, padding)),
+      )) as ArrayRef
+    }};
+  }
+
+  macro_rules! read_strings {
+    ($string:ty) => {{
+      let values = read_values::<$string>(dataset, column, start..end)?;
+      Arc::new(StringArray::from_iter(
+        values
+          .iter()
+          .map(|v| Some(v.as_str()))
+          .chain(std::iter::repeat_n(None, padding)),
+      )) as ArrayRef
+    }};
+  }
+
+  // libhdf5 converts between fixed length strings of different lengths, so they're read into the
+  // smallest of these that fits them and the padding is trimmed off
+  macro_rules! read_fixed {
+    ($string:ident, $size:expr) => {{
+      match $size {
+        0..=16 => read_strings!($string<16>),
+        17..=64 => read_strings!($string<64>),
+        65..=256 => read_strings!($string<256>),
+        257..=1024 => read_strings!($string<1024>),
+        _ => read_strings!($string<MAX_FIXED_STRING>),
+      }
+    }};
+  }
+
+  let array = match &column.descriptor {
+    TypeDescriptor::Integer(IntSize::U1) => read_array!(i8, Int8Array),
+    TypeDescriptor::Integer(IntSize::U2) => read_array!(i16, Int16Array),
+    TypeDescriptor::Integer(IntSize::U4) => read_array!(i32, Int32Array),
+    TypeDescriptor::Integer(IntSize::U8) => read_array!(i64, Int64Array),
+    TypeDescriptor::Unsigned(IntSize::U1) => read_array!(u8, UInt8Array),
+    TypeDescriptor::Unsigned(IntSize::U2) => read_array!(u16, UInt16Array),
+    TypeDescriptor::Unsigned(IntSize::U4) => read_array!(u32, UInt32Array),
+    TypeDescriptor::Unsigned(IntSize::U8) => read_array!(u64, UInt64Array),
+    TypeDescriptor::Float(FloatSize::U4) => read_array!(f32, Float32Array),
+    TypeDescriptor::Float(FloatSize::U8) => read_array!(f64, Float64Array),
+    TypeDescriptor::Boolean => read_array!(bool, BooleanArray),
+    TypeDescriptor::VarLenAscii => read_strings!(VarLenAscii),
+    TypeDescriptor::VarLenUnicode => read_strings!(VarLenUnicode),
+    TypeDescriptor::FixedAscii(size) => read_fixed!(FixedAscii, *size),
+    TypeDescriptor::FixedUnicode(size) => read_fixed!(FixedUnicode, *size),
+    t => {
+      return Err(hdf5::Error::from(format!(
+        "unsupported hdf5 type {t} in dataset {}",
+        column.dataset
+      )))
+    }
+  };
+
+  Ok(array)
+}
+
+fn read_values<T: H5Type + Clone>(
+  dataset: &Dataset,
+  column: &Hdf5Column,
+  rows: Range<usize>,
+) -> Result<Vec<T>, hdf5::Error> {
+  // libhdf5 rejects empty selections
+  if rows.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let values = match column.index {
+    None => dataset.read_slice_1d::<T, _>(rows)?,
+    Some(i) => dataset.read_slice_1d::<T, _>((rows, i))?,
+  };
+
+  Ok(values.to_vec())
+}
+
+fn arrow_type(descriptor: &TypeDescriptor) -> Option<DataType> {
+  match descriptor {
+    TypeDescriptor::Integer(IntSize::U1) => Some(DataType::Int8),
+    TypeDescriptor::Integer(IntSize::U2) => Some(DataType::Int16),
+    TypeDescriptor::Integer(IntSize::U4) => Some(DataType::Int32),
+    TypeDescriptor::Integer(IntSize::U8) => Some(DataType::Int64),
+    TypeDescriptor::Unsigned(IntSize::U1) => Some(DataType::UInt8),
+    TypeDescriptor::Unsigned(IntSize::U2) => Some(DataType::UInt16),
+    TypeDescriptor::Unsigned(IntSize::U4) => Some(DataType::UInt32),
+    TypeDescriptor::Unsigned(IntSize::U8) => Some(DataType::UInt64),
+    TypeDescriptor::Float(FloatSize::U4) => Some(DataType::Float32),
+    TypeDescriptor::Float(FloatSize::U8) => Some(DataType::Float64),
+    TypeDescriptor::Boolean => Some(DataType::Boolean),
+    TypeDescriptor::VarLenAscii | TypeDescriptor::VarLenUnicode => Some(DataType::Utf8),
+    TypeDescriptor::FixedAscii(size) | TypeDescriptor::FixedUnicode(size)
+      if *size <= MAX_FIXED_STRING =>
+    {
+      Some(DataType::Utf8)
+    }
+    _ => None,
+  }
+}
+
+// hdf5 object names are full paths like `/run_2/temperature`
+fn leaf_name(path: &str) -> &str {
+  path.rsplit('/').next().unwrap_or(path)
+}
+
+fn sanitize_table_name(name: &str) -> String {
+  name
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() {
+      true => c.to_ascii_lowercase(),
+      false => '_',
+    })
+    .collect()
+}
@@ -1,10 +1,138 @@
 use crate::timeseries::data_types::LegacyDataTypes;
 use crate::timeseries::errors::{TimeseriesError, ValidationError};
-use crate::timeseries::repository::LegacyTimeseriesColumn;
-use chrono::NaiveDateTime;
-use sqlx::{Pool, Postgres};
+use crate::timeseries::repository::{IngestionReport, LegacyTimeseriesColumn};
+use crate::timeseries::timestamp_parser::TimestampParser;
+use chrono::SecondsFormat;
+use csv::ByteRecord;
+use sqlx::postgres::PgCopyIn;
+use sqlx::{PgConnection, Pool, Postgres};
+use std::fmt::{Display, Formatter};
 use std::io::Read;
 
+/// How a legacy CSV ingestion deals with rows that can't be ingested, e.g. a date that doesn't match
+/// its column's format, a value that doesn't fit its column's type or a record that's missing a field
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct LegacyIngestionOptions {
+  /// `fail_fast` fails the whole ingestion on the first bad row, `skip` leaves bad rows out and
+  /// `collect` leaves them out until more than `maxErrors` have been. Defaults to `fail_fast`
+  pub error_policy: Option<String>,
+  /// How many rows `collect` rejects before failing the ingestion. Defaults to 100
+  pub max_errors: Option<u32>,
+  /// Local file the rejected rows are written to as CSV, with the header of the ingested file and
+  /// `_row` and `_error` columns added. The added columns don't match any bucket column so the file
+  /// can be ingested again once it's fixed. JSON ingestions write newline delimited JSON instead, with
+  /// `_row` and `_error` keys added to each record
+  pub dead_letter_path: Option<String>,
+  /// Values ingested as nulls, compared after trimming whitespace. Defaults to just the empty string,
+  /// which is always a null whether it's listed or not
+  pub null_values: Option<Vec<String>>,
+}
+
+/// A row that was rejected during a legacy CSV ingestion
+#[napi(object)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowError {
+  /// Line of the file the row starts on, the header is line 1. For JSON it's the number of the record,
+  /// starting at 1
+  pub row: u32,
+  /// The CSV header, or JSON property name, of the rejected value when the problem is with a single
+  /// value
+  pub column: Option<String>,
+  pub value: Option<String>,
+  pub reason: String,
+}
+
+impl Display for RowError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match &self.column {
+      None => write!(f, "row {}: {}", self.row, self.reason),
+      Some(column) => write!(f, "row {}, column {}: {}", self.row, column, self.reason),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+  FailFast,
+  Skip,
+  Collect(u32),
+}
+
+/// How many rows `collect` rejects when max_errors isn't set
+const DEFAULT_MAX_ERRORS: u32 = 100;
+/// Only this many rejected rows are listed in the report, the rest are still counted and written to
+/// the dead letter file
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+impl TryFrom<&LegacyIngestionOptions> for ErrorPolicy {
+  type Error = TimeseriesError;
+
+  fn try_from(options: &LegacyIngestionOptions) -> Result<Self, Self::Error> {
+    match options.error_policy.as_deref() {
+      None | Some("fail_fast") => Ok(ErrorPolicy::FailFast),
+      Some("skip") => Ok(ErrorPolicy::Skip),
+      Some("collect") => Ok(ErrorPolicy::Collect(
+        options.max_errors.unwrap_or(DEFAULT_MAX_ERRORS),
+      )),
+      Some(policy) => Err(TimeseriesError::InvalidOptions(format!(
+        "unknown error policy {policy}, expected fail_fast, skip or collect"
+      ))),
+    }
+  }
+}
+
+/// A column of the bucket being ingested into, with what's needed to convert values for it
+#[derive(Clone)]
+pub(crate) struct BucketColumn {
+  column_name: String,
+  data_type: LegacyDataTypes,
+  // only date columns have one
+  timestamps: Option<TimestampParser>,
+  // the primary timestamp of a legacy table is a bigint when it's a number
+  is_primary_timestamp: bool,
+}
+
+impl TryFrom<&LegacyTimeseriesColumn> for BucketColumn {
+  type Error = TimeseriesError;
+
+  fn try_from(column: &LegacyTimeseriesColumn) -> Result<Self, Self::Error> {
+    let data_type: LegacyDataTypes = column.data_type.clone().into();
+    let timestamps = match data_type {
+      LegacyDataTypes::Date => Some(
+        TimestampParser::new(
+          column.date_conversion_format_string.as_deref(),
+          column.timezone.as_deref(),
+        )
+        .map_err(|e| {
+          TimeseriesError::InvalidOptions(format!("column {}: {e}", column.column_name))
+        })?,
+      ),
+      _ => None,
+    };
+
+    Ok(BucketColumn {
+      column_name: column.column_name.clone(),
+      data_type,
+      timestamps,
+      is_primary_timestamp: column.is_primary_timestamp,
+    })
+  }
+}
+
+impl BucketColumn {
+  pub(crate) fn is_json(&self) -> bool {
+    self.data_type == LegacyDataTypes::Json
+  }
+}
+
+// they should stay in the order they are in the CSV - index, csv name, column name
+#[derive(Clone)]
+struct Position {
+  index: usize,
+  column: BucketColumn,
+}
+
 /// ingest_csv_legacy allows us to use the same paradigm as all other bucket ingestion patterns here
 /// to ingest csv data formatted for the original DeepLynx timeseries integration
 pub async fn ingest_csv_legacy<T: Read>(
@@ -12,34 +140,24 @@ pub async fn ingest_csv_legacy<T: Read>(
   reader: T,
   data_source_id: String,
   columns: Vec<LegacyTimeseriesColumn>,
-) -> Result<(), TimeseriesError> {
+  options: LegacyIngestionOptions,
+) -> Result<IngestionReport, TimeseriesError> {
+  let mut rejections = Rejections::new(&options)?;
   let mut csv_reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
   // let's fetch the headers - also a quick way to check if we're actually dealing with a csv
-  // they should stay in the order they are in the CSV - index, csv name, column name
-  #[derive(Clone)]
-  struct Position {
-    index: usize,
-    column_name: String,
-    data_type: LegacyDataTypes,
-    format_string: Option<String>,
-  }
-
   let mut positions: Vec<Position> = vec![];
-  let headers = csv_reader.headers()?;
+  let headers = csv_reader.headers()?.clone();
   for (i, header) in headers.iter().enumerate() {
     // check to see if that header exists in the bucket definition, if it does, record its
     // spot and name
-    match columns
+    if let Some(bc) = columns
       .iter()
       .find(|bc| bc.property_name.as_str() == header)
     {
-      None => {}
-      Some(bc) => positions.push(Position {
+      positions.push(Position {
         index: i,
-        column_name: bc.column_name.clone(),
-        data_type: bc.data_type.clone().into(),
-        format_string: bc.date_conversion_format_string.clone(),
-      }),
+        column: bc.try_into()?,
+      });
     }
   }
 
@@ -49,57 +167,309 @@ pub async fn ingest_csv_legacy<T: Read>(
     ));
   }
 
-  let column_names: Vec<String> = positions
-    .clone()
-    .iter()
-    .map(|pos| format!("\"{}\"", pos.column_name.clone()))
-    .collect();
-
   let mut connection = db.acquire().await?;
+  let mut copier = begin_copy(
+    &mut connection,
+    &data_source_id,
+    positions.iter().map(|p| &p.column),
+  )
+  .await?;
 
-  let mut copier = connection
-    .copy_in_raw(
-      format!(
-        "COPY y_{}({}) FROM STDIN WITH (FORMAT csv, HEADER FALSE, DELIMITER \",\")",
-        data_source_id,
-        column_names.join(",")
-      )
-      .as_str(),
-    )
-    .await?;
-
-  // in order to append the bucket_id we have to actually parse the csv row per row and send
-  // it into the copier - it's really not that slow since the underlying async reader has is
-  // buffered
-  while let Some(record) = csv_reader.records().next() {
-    let record = record?;
-
-    for (i, position) in positions.iter().enumerate() {
-      let value = record
-        .get(position.index)
-        .ok_or(TimeseriesError::Unwrap("csv record field".to_string()))?;
-
-      match position.data_type {
-        LegacyDataTypes::Date => {
-          let format_string = match position.format_string.clone() {
-            None => "%Y-%m-%d %H:%M:%S".to_string(),
-            Some(s) => s,
-          };
-
-          let timestamp = NaiveDateTime::parse_from_str(value, format_string.as_str())?;
-          copier.send(timestamp.to_string().as_bytes()).await?
+  let mut dead_letter = match &options.dead_letter_path {
+    None => None,
+    Some(path) => {
+      let mut writer = csv::WriterBuilder::new().flexible(true).from_path(path)?;
+      let mut header = headers.clone();
+      header.push_field("_row");
+      header.push_field("_error");
+      writer.write_record(&header)?;
+      Some(writer)
+    }
+  };
+  let null_values = options.null_values.clone().unwrap_or_default();
+
+  // rows are sent in a block of their own so that a failure part way through, or the ingestion being
+  // aborted, fails the whole COPY instead of committing the rows before it
+  let copied = async {
+    // in order to append the bucket_id we have to actually parse the csv row per row and send
+    // it into the copier - it's really not that slow since the underlying async reader has is
+    // buffered
+    let mut record = ByteRecord::new();
+    let mut row = String::new();
+    while csv_reader.read_byte_record(&mut record)? {
+      // the row is checked as a whole before any of it is sent, a rejected row can't leave half a
+      // line behind in the COPY
+      row.clear();
+      match copy_row(&record, &positions, &headers, &null_values, &mut row) {
+        Ok(()) => {
+          copier.send(row.as_bytes()).await?;
         }
-        _ => copier.send(value.to_string().as_bytes()).await?,
-      };
+        Err(row_error) => rejections.reject(row_error, |row_error| {
+          if let Some(writer) = dead_letter.as_mut() {
+            let mut rejected = record.clone();
+            rejected.push_field(row_error.row.to_string().as_bytes());
+            rejected.push_field(row_error.reason.as_bytes());
+            writer.write_byte_record(&rejected)?;
+          }
+          Ok(())
+        })?,
+      }
+    }
+
+    if let Some(writer) = dead_letter.as_mut() {
+      writer.flush()?;
+    }
+
+    Ok::<(), TimeseriesError>(())
+  }
+  .await;
+
+  end_copy(copier, copied).await?;
+  Ok(rejections.report)
+}
+
+/// Starts the COPY into the legacy table of the data source, rows are sent to it as CSV lines with
+/// the columns in the order they're given here
+pub(crate) async fn begin_copy<'c, 'b>(
+  connection: &'c mut PgConnection,
+  data_source_id: &str,
+  columns: impl Iterator<Item = &'b BucketColumn>,
+) -> Result<PgCopyIn<&'c mut PgConnection>, TimeseriesError> {
+  let table_name = table_name(data_source_id)?;
+  let column_names: Vec<String> = columns.map(|column| quote(&column.column_name)).collect();
+
+  Ok(
+    connection
+      .copy_in_raw(
+        format!(
+          "COPY {table_name}({}) FROM STDIN WITH (FORMAT csv, HEADER FALSE, DELIMITER \",\")",
+          column_names.join(",")
+        )
+        .as_str(),
+      )
+      .await?,
+  )
+}
 
-      if i == &positions.len() - 1 {
-        copier.send("\n".as_bytes()).await?;
-      } else {
-        copier.send(",".as_bytes()).await?;
+/// The table the ingestions write into, the id is part of the name so it's limited to what's safe
+/// in an identifier. It's lowercased, the name is used unquoted and Postgres would fold it anyway
+pub(crate) fn table_name(data_source_id: &str) -> Result<String, TimeseriesError> {
+  if data_source_id.is_empty()
+    || !data_source_id
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_')
+  {
+    return Err(TimeseriesError::InvalidOptions(format!(
+      "{data_source_id} isn't a valid data source id"
+    )));
+  }
+
+  Ok(format!("y_{}", data_source_id.to_lowercase()))
+}
+
+/// A quoted identifier, with any quotes in it doubled so they can't end it early
+pub(crate) fn quote(identifier: &str) -> String {
+  format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Commits the COPY if all of its rows were sent, or aborts it so none of them are
+pub(crate) async fn end_copy(
+  copier: PgCopyIn<&mut PgConnection>,
+  copied: Result<(), TimeseriesError>,
+) -> Result<(), TimeseriesError> {
+  match copied {
+    Ok(()) => {
+      copier.finish().await?;
+      Ok(())
+    }
+    Err(e) => {
+      // the abort only fails when the connection is already gone, which takes the COPY with it
+      let _ = copier.abort(e.to_string()).await;
+      Err(e)
+    }
+  }
+}
+
+/// Keeps track of the rejected rows of an ingestion, and fails it when its error policy says so
+pub(crate) struct Rejections {
+  policy: ErrorPolicy,
+  pub(crate) report: IngestionReport,
+}
+
+impl Rejections {
+  pub(crate) fn new(options: &LegacyIngestionOptions) -> Result<Self, TimeseriesError> {
+    Ok(Rejections {
+      policy: ErrorPolicy::try_from(options)?,
+      report: IngestionReport::default(),
+    })
+  }
+
+  /// Rejects a row, `dead_letter` writes it to the dead letter file when there is one
+  pub(crate) fn reject<F>(
+    &mut self,
+    row_error: RowError,
+    dead_letter: F,
+  ) -> Result<(), TimeseriesError>
+  where
+    F: FnOnce(&RowError) -> Result<(), TimeseriesError>,
+  {
+    if self.policy == ErrorPolicy::FailFast {
+      return Err(TimeseriesError::RejectedRow(row_error));
+    }
+
+    dead_letter(&row_error)?;
+
+    self.report.rows_rejected += 1;
+    if self.report.errors.len() < MAX_REPORTED_ERRORS {
+      self.report.errors.push(row_error);
+    }
+
+    if let ErrorPolicy::Collect(max_errors) = self.policy {
+      if self.report.rows_rejected > max_errors as i64 {
+        return Err(TimeseriesError::TooManyRejectedRows(max_errors));
       }
     }
+
+    Ok(())
+  }
+}
+
+/// Checks and converts the bucket's columns of `record` into a line of the COPY, or says why the row
+/// has to be rejected
+fn copy_row(
+  record: &ByteRecord,
+  positions: &[Position],
+  headers: &csv::StringRecord,
+  null_values: &[String],
+  row: &mut String,
+) -> Result<(), RowError> {
+  let line = record.position().map_or(0, |p| p.line()) as u32;
+  let row_error = |position: &Position, value: Option<&str>, reason: String| RowError {
+    row: line,
+    column: headers.get(position.index).map(|h| h.to_string()),
+    value: value.map(|v| v.to_string()),
+    reason,
+  };
+
+  for (i, position) in positions.iter().enumerate() {
+    let bytes = record
+      .get(position.index)
+      .ok_or_else(|| row_error(position, None, "record is missing this field".to_string()))?;
+    let value = std::str::from_utf8(bytes).map_err(|_| {
+      row_error(
+        position,
+        Some(String::from_utf8_lossy(bytes).as_ref()),
+        "value is not valid UTF-8".to_string(),
+      )
+    })?;
+
+    push_value(
+      row,
+      &position.column,
+      value,
+      null_values,
+      i == positions.len() - 1,
+    )
+    .map_err(|reason| row_error(position, Some(value), reason))?;
+  }
+
+  Ok(())
+}
+
+/// Converts `value` for `column` and adds it to the COPY line in `row`, `last` ends the line. Nulls
+/// are left unquoted, everything else is quoted so that commas and quotes in strings and JSON make it
+/// through the COPY
+pub(crate) fn push_value(
+  row: &mut String,
+  column: &BucketColumn,
+  value: &str,
+  null_values: &[String],
+  last: bool,
+) -> Result<(), String> {
+  if !is_null(value, null_values) {
+    let converted = convert(column, value)?;
+    row.push('"');
+    row.push_str(converted.replace('"', "\"\"").as_str());
+    row.push('"');
   }
 
-  copier.finish().await?;
+  row.push(if last { '\n' } else { ',' });
   Ok(())
 }
+
+fn is_null(value: &str, null_values: &[String]) -> bool {
+  let value = value.trim();
+  value.is_empty() || null_values.iter().any(|null| null.trim() == value)
+}
+
+/// The number without its thousands separators, the same ones the legacy import of JSON data
+/// strips. None when it has a comma anywhere else, a decimal comma like `2,5` would otherwise be
+/// read as 25
+pub(crate) fn strip_thousands_separators(value: &str) -> Option<String> {
+  if !value.contains(',') {
+    return Some(value.to_string());
+  }
+
+  let unsigned = value.trim_start_matches(['-', '+']);
+  let whole = unsigned.split(['.', 'e', 'E']).next().unwrap_or_default();
+  if unsigned[whole.len()..].contains(',') {
+    return None;
+  }
+
+  let is_digits = |group: &str| group.chars().all(|c| c.is_ascii_digit());
+  let mut groups = whole.split(',');
+  let first = groups.next().unwrap_or_default();
+  let separated = (1..=3).contains(&first.len())
+    && is_digits(first)
+    && groups.all(|group| group.len() == 3 && is_digits(group));
+
+  separated.then(|| value.replace(',', ""))
+}
+
+/// The value as Postgres expects it in a column of the column's type, or why it doesn't fit
+fn convert(column: &BucketColumn, value: &str) -> Result<String, String> {
+  let number = || {
+    strip_thousands_separators(value.trim())
+      .ok_or_else(|| "not a number, commas can only separate thousands".to_string())
+  };
+
+  match column.data_type {
+    LegacyDataTypes::Number if column.is_primary_timestamp => number()?
+      .parse::<i64>()
+      .map(|n| n.to_string())
+      .map_err(|_| "not a whole number that fits in 64 bits".to_string()),
+    LegacyDataTypes::Number => number()?
+      .parse::<i32>()
+      .map(|n| n.to_string())
+      .map_err(|_| "not a whole number that fits in 32 bits".to_string()),
+    LegacyDataTypes::Number64 => number()?
+      .parse::<i64>()
+      .map(|n| n.to_string())
+      .map_err(|_| "not a whole number that fits in 64 bits".to_string()),
+    // both are numeric columns, the value is passed on as it was so none of its precision is lost
+    LegacyDataTypes::Float | LegacyDataTypes::Float64 => {
+      let number = number()?;
+      match number.parse::<f64>() {
+        Ok(f) if f.is_finite() => Ok(number),
+        _ => Err("not a finite number".to_string()),
+      }
+    }
+    LegacyDataTypes::Boolean => match value.trim().to_lowercase().as_str() {
+      "true" | "t" | "yes" | "y" | "1" => Ok("true".to_string()),
+      "false" | "f" | "no" | "n" | "0" => Ok("false".to_string()),
+      _ => Err("not a boolean, expected true/false, yes/no or 1/0".to_string()),
+    },
+    LegacyDataTypes::Json => serde_json::from_str::<serde_json::Value>(value)
+      .map(|json| json.to_string())
+      .map_err(|e| format!("not valid JSON: {e}")),
+    // stored as UTC with the offset spelled out, a timestamptz column keeps the instant and a
+    // timestamp column the UTC time
+    LegacyDataTypes::Date => match &column.timestamps {
+      Some(timestamps) => timestamps
+        .parse(value)
+        .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+      None => Err("date column without a timestamp parser".to_string()),
+    },
+    LegacyDataTypes::String => Ok(value.to_string()),
+  }
+}
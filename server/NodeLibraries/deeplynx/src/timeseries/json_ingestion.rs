@@ -0,0 +1,328 @@
+use crate::timeseries::errors::{TimeseriesError, ValidationError};
+use crate::timeseries::ingestion::{
+  begin_copy, end_copy, push_value, BucketColumn, LegacyIngestionOptions, Rejections, RowError,
+};
+use crate::timeseries::repository::{IngestionReport, LegacyTimeseriesColumn};
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// A bucket column and where its values are in each JSON record
+struct JsonPosition {
+  property_name: String,
+  path: Vec<String>,
+  column: BucketColumn,
+}
+
+/// ingest_json_legacy is ingest_csv_legacy for data sources that send JSON, either an array of records
+/// or newline delimited records. Each column's property_name is the key of its values in a record, or
+/// the path to them for nested values, e.g. `reading.value` or `readings[0].value`. Records that don't
+/// have a value for a column get a null for it
+pub async fn ingest_json_legacy<T: Read>(
+  db: Pool<Postgres>,
+  reader: T,
+  data_source_id: String,
+  columns: Vec<LegacyTimeseriesColumn>,
+  options: LegacyIngestionOptions,
+) -> Result<IngestionReport, TimeseriesError> {
+  let mut rejections = Rejections::new(&options)?;
+  let positions = columns
+    .iter()
+    .map(|bc| {
+      Ok(JsonPosition {
+        property_name: bc.property_name.clone(),
+        path: parse_path(bc.property_name.as_str()),
+        column: bc.try_into()?,
+      })
+    })
+    .collect::<Result<Vec<JsonPosition>, TimeseriesError>>()?;
+
+  if positions.is_empty() {
+    return Err(TimeseriesError::CsvValidation(
+      ValidationError::MissingColumns,
+    ));
+  }
+
+  let mut connection = db.acquire().await?;
+  let mut copier = begin_copy(
+    &mut connection,
+    &data_source_id,
+    positions.iter().map(|p| &p.column),
+  )
+  .await?;
+
+  let mut dead_letter = match &options.dead_letter_path {
+    None => None,
+    Some(path) => Some(BufWriter::new(File::create(path)?)),
+  };
+  let null_values = options.null_values.clone().unwrap_or_default();
+
+  let copied = async {
+    // serde_json reads a byte at a time, the buffer keeps that from reaching the stream reader
+    let records = serde_json::Deserializer::from_reader(BufReader::new(ArrayElements::new(reader)))
+      .into_iter::<Value>();
+    let mut row = String::new();
+    for (i, record) in records.enumerate() {
+      let record = record?;
+
+      row.clear();
+      match copy_record(i as u32 + 1, &record, &positions, &null_values, &mut row) {
+        Ok(()) => {
+          copier.send(row.as_bytes()).await?;
+        }
+        Err(row_error) => rejections.reject(row_error, |row_error| {
+          if let Some(writer) = dead_letter.as_mut() {
+            serde_json::to_writer(&mut *writer, &rejected(record, row_error))?;
+            writer.write_all(b"\n")?;
+          }
+          Ok(())
+        })?,
+      }
+    }
+
+    if let Some(writer) = dead_letter.as_mut() {
+      writer.flush()?;
+    }
+
+    Ok::<(), TimeseriesError>(())
+  }
+  .await;
+
+  end_copy(copier, copied).await?;
+  Ok(rejections.report)
+}
+
+/// Checks and converts the bucket's columns of `record` into a line of the COPY, or says why the row
+/// has to be rejected
+fn copy_record(
+  number: u32,
+  record: &Value,
+  positions: &[JsonPosition],
+  null_values: &[String],
+  row: &mut String,
+) -> Result<(), RowError> {
+  if !record.is_object() {
+    return Err(RowError {
+      row: number,
+      column: None,
+      value: Some(record.to_string()),
+      reason: "record is not an object".to_string(),
+    });
+  }
+
+  for (i, position) in positions.iter().enumerate() {
+    // a key that's the whole property name wins over a path, so flat keys with dots still work
+    let value = record
+      .get(position.property_name.as_str())
+      .or_else(|| lookup(record, &position.path));
+    let value = match value {
+      None | Some(Value::Null) => "".to_string(),
+      Some(Value::String(s)) if !position.column.is_json() => s.clone(),
+      Some(value) => value.to_string(),
+    };
+
+    push_value(
+      row,
+      &position.column,
+      value.as_str(),
+      null_values,
+      i == positions.len() - 1,
+    )
+    .map_err(|reason| RowError {
+      row: number,
+      column: Some(position.property_name.clone()),
+      value: Some(value.clone()),
+      reason,
+    })?;
+  }
+
+  Ok(())
+}
+
+/// `a.b[0].c`, with an optional leading `$`, as the keys and indexes to follow
+fn parse_path(path: &str) -> Vec<String> {
+  let path = path.strip_prefix('$').unwrap_or(path);
+
+  path
+    .split(['.', '[', ']'])
+    .filter(|segment| !segment.is_empty())
+    .map(|segment| segment.to_string())
+    .collect()
+}
+
+fn lookup<'a>(record: &'a Value, path: &[String]) -> Option<&'a Value> {
+  path.iter().try_fold(record, |value, segment| match value {
+    Value::Object(object) => object.get(segment.as_str()),
+    Value::Array(array) => segment.parse::<usize>().ok().and_then(|i| array.get(i)),
+    _ => None,
+  })
+}
+
+/// The record as it's written to the dead letter file, the `_row` and `_error` keys are added to it
+/// like the columns are added to a CSV's
+fn rejected(record: Value, row_error: &RowError) -> Value {
+  let mut object = match record {
+    Value::Object(object) => object,
+    record => {
+      let mut object = serde_json::Map::new();
+      object.insert("_record".to_string(), record);
+      object
+    }
+  };
+  object.insert("_row".to_string(), Value::from(row_error.row));
+  object.insert("_error".to_string(), Value::from(row_error.reason.clone()));
+
+  Value::Object(object)
+}
+
+/// Turns a JSON array into the records in it, one after the other, so they can be streamed like
+/// newline delimited JSON rather than read into memory as a whole. The brackets of the array and the
+/// commas between its records are blanked out as they're read, anything that isn't an array is passed
+/// through as it is
+struct ArrayElements<R> {
+  inner: R,
+  started: bool,
+  is_array: bool,
+  closed: bool,
+  // how deep into the records of the array we are, 0 is between them
+  depth: usize,
+  in_string: bool,
+  escaped: bool,
+  // what may come next between the records
+  next: Between,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Between {
+  // right after the opening bracket, a record or the closing bracket
+  First,
+  // after a comma, only a record
+  Record,
+  // inside a record that isn't an object, array or string, e.g. a number
+  Scalar,
+  // after a record, a comma or the closing bracket
+  Separator,
+}
+
+impl<R: Read> ArrayElements<R> {
+  fn new(inner: R) -> Self {
+    ArrayElements {
+      inner,
+      started: false,
+      is_array: false,
+      closed: false,
+      depth: 0,
+      in_string: false,
+      escaped: false,
+      next: Between::First,
+    }
+  }
+}
+
+impl<R: Read> Read for ArrayElements<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+
+    let read = self.inner.read(buf)?;
+    if read == 0 && self.is_array && !self.closed {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "the JSON array isn't closed",
+      ));
+    }
+
+    for byte in buf[..read].iter_mut() {
+      if !self.started {
+        if byte.is_ascii_whitespace() {
+          continue;
+        }
+        self.started = true;
+        if *byte == b'[' {
+          self.is_array = true;
+          *byte = b' ';
+          continue;
+        }
+      }
+
+      if !self.is_array {
+        continue;
+      }
+
+      if self.closed {
+        if byte.is_ascii_whitespace() {
+          continue;
+        }
+        return Err(invalid_array(
+          "there's more after the end of the JSON array",
+        ));
+      }
+
+      if self.in_string {
+        if self.escaped {
+          self.escaped = false;
+        } else if *byte == b'\\' {
+          self.escaped = true;
+        } else if *byte == b'"' {
+          self.in_string = false;
+        }
+        continue;
+      }
+
+      if self.depth > 0 {
+        match *byte {
+          b'"' => self.in_string = true,
+          b'{' | b'[' => self.depth += 1,
+          b'}' | b']' => self.depth -= 1,
+          _ => {}
+        }
+        continue;
+      }
+
+      // between the records, or in one that isn't nested
+      self.next = match (*byte, self.next) {
+        (b',', Between::Scalar | Between::Separator) => {
+          *byte = b'\n';
+          Between::Record
+        }
+        (b',', _) => {
+          return Err(invalid_array(
+            "the JSON array is missing a record before a comma",
+          ))
+        }
+        (b']', Between::First | Between::Scalar | Between::Separator) => {
+          self.closed = true;
+          *byte = b' ';
+          Between::Separator
+        }
+        (b']', _) => return Err(invalid_array("the JSON array ends with a comma")),
+        (byte, next) if byte.is_ascii_whitespace() => match next {
+          Between::Scalar => Between::Separator,
+          next => next,
+        },
+        (_, Between::Separator) => {
+          return Err(invalid_array(
+            "the records of the JSON array aren't separated by a comma",
+          ))
+        }
+        (b'"', _) => {
+          self.in_string = true;
+          Between::Separator
+        }
+        (b'{' | b'[', _) => {
+          self.depth += 1;
+          Between::Separator
+        }
+        (_, _) => Between::Scalar,
+      };
+    }
+
+    Ok(read)
+  }
+}
+
+fn invalid_array(reason: &str) -> std::io::Error {
+  std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+}
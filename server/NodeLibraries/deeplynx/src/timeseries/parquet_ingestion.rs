@@ -0,0 +1,396 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::Arc;
+
+use datafusion::arrow::array::{Array, AsArray, RecordBatch, UInt32Array};
+use datafusion::arrow::compute::{cast, take_record_batch};
+use datafusion::arrow::datatypes::{DataType, Schema, SchemaRef};
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::parquet::arrow::AsyncArrowWriter;
+use datafusion::parquet::basic::{Compression, ZstdLevel};
+use datafusion::parquet::file::properties::WriterProperties;
+use object_store::buffered::BufWriter;
+use object_store::path::{Path, PathPart};
+use object_store::ObjectStore;
+use short_uuid::short;
+
+use crate::timeseries::csv_dialect::{columns_schema, CsvColumn, CsvDialect};
+use crate::timeseries::datafusion_session::{new_session, BATCH_SIZE};
+use crate::timeseries::errors::QueryError;
+use crate::timeseries::file_metadata::{FileMetadata, PartitionColumn};
+use crate::timeseries::storage_connection::StorageConnection;
+
+/// What is being ingested and where it's written. The data is checked against `schema` as it's read,
+/// a row that doesn't fit fails the ingestion and nothing is left behind in the store
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct ParquetIngestionOptions {
+  /// The id of the returned FileMetadata
+  pub id: String,
+  /// Folder the Parquet files are written to, relative to the root of the storage connection
+  pub file_path: String,
+  /// `csv` or `json`, which is newline delimited JSON. Defaults to `csv`
+  pub format: Option<String>,
+  pub schema: Vec<CsvColumn>,
+  /// How the incoming CSV is laid out, ignored for JSON. `schema` on the dialect is ignored in favour
+  /// of the schema above and `schemaInferMaxRecords` isn't used
+  pub csv_options: Option<CsvDialect>,
+  /// Columns of the schema to partition the files by, as hive style `name=value` folders in the order
+  /// they're listed. They're left out of the files themselves like DataFusion expects. Values that
+  /// would have to be escaped in a path, like ones with a `/` or `%`, fail the ingestion
+  pub partition_by: Option<Vec<String>>,
+}
+
+/// Each partition keeps a writer open, and a row group in memory, until the ingestion is complete
+const MAX_OPEN_PARTITIONS: usize = 1000;
+/// How much the row groups of all the open writers may hold together before the largest ones are
+/// flushed to the store
+const MAX_BUFFERED_BYTES: usize = 128 * 1024 * 1024;
+
+/// Reads CSV or newline delimited JSON from `reader`, checks it against the declared schema and writes
+/// it into the store as Parquet, one file per partition. Returns the metadata to query what was
+/// written with
+pub async fn ingest_parquet<T: Read>(
+  storage_connection: &StorageConnection,
+  reader: T,
+  options: ParquetIngestionOptions,
+) -> Result<FileMetadata, QueryError> {
+  let schema = Arc::new(columns_schema(&options.schema, "ingestion schema")?);
+  let partition_by = options.partition_by.clone().unwrap_or_default();
+  let partition_columns = partition_columns(&schema, &partition_by)?;
+
+  let ctx = new_session(storage_connection, Arc::new(RuntimeEnv::default()))?;
+  let folder = format!(
+    "{}/{}",
+    storage_connection.root_url().trim_end_matches('/'),
+    options.file_path.trim_matches('/')
+  );
+  let url = ListingTableUrl::parse(folder.as_str())?;
+  let store = ctx.runtime_env().object_store(&url)?;
+
+  let mut writers = PartitionWriters::new(
+    store,
+    url.prefix().clone(),
+    &schema,
+    &partition_by,
+    short!().to_string(),
+  )?;
+  let written = match read_batches(reader, &schema, &options, &mut writers).await {
+    Ok(()) => writers.close().await,
+    Err(e) => {
+      writers.abort().await;
+      Err(e)
+    }
+  }?;
+
+  let file_name = match written.first().and_then(|path| path.filename()) {
+    None => {
+      return Err(QueryError::BadData(
+        "no parquet file was written".to_string(),
+      ))
+    }
+    Some(name) if partition_by.is_empty() => name.to_string(),
+    Some(_) => "**/*.parquet".to_string(),
+  };
+
+  Ok(FileMetadata {
+    id: options.id,
+    file_name,
+    file_path: options.file_path,
+    csv_options: None,
+    glob: Some(!partition_columns.is_empty()),
+    partition_columns: (!partition_columns.is_empty()).then_some(partition_columns),
+  })
+}
+
+/// Partition columns as FileMetadata lists them, only types whose values survive the round trip
+/// through a folder name can be partitioned by
+fn partition_columns(
+  schema: &Schema,
+  partition_by: &[String],
+) -> Result<Vec<PartitionColumn>, QueryError> {
+  let mut columns = vec![];
+  for name in partition_by {
+    let field = schema.field_with_name(name).map_err(|_| {
+      QueryError::BadData(format!(
+        "partition column {name} is not in the ingestion schema"
+      ))
+    })?;
+
+    if !(field.data_type().is_integer()
+      || matches!(
+        field.data_type(),
+        DataType::Utf8 | DataType::Boolean | DataType::Date32
+      ))
+    {
+      return Err(QueryError::BadData(format!(
+        "partition column {name} is {}, only integer, Utf8, Boolean and Date32 columns can be partitioned by",
+        field.data_type()
+      )));
+    }
+
+    columns.push(PartitionColumn {
+      name: name.clone(),
+      data_type: Some(field.data_type().to_string()),
+    });
+  }
+
+  if columns.len() == schema.fields().len() {
+    return Err(QueryError::BadData(
+      "at least one column of the ingestion schema has to be left out of partitionBy".to_string(),
+    ));
+  }
+
+  Ok(columns)
+}
+
+async fn read_batches<T: Read>(
+  reader: T,
+  schema: &SchemaRef,
+  options: &ParquetIngestionOptions,
+  writers: &mut PartitionWriters,
+) -> Result<(), QueryError> {
+  match options.format.as_deref().unwrap_or("csv") {
+    "csv" => {
+      let dialect = options.csv_options.clone().unwrap_or_default();
+      let mut reader = BufReader::new(reader);
+      if dialect.has_header.unwrap_or(true) {
+        check_header(&mut reader, schema, &dialect)?;
+      }
+
+      let batches = datafusion::arrow::csv::ReaderBuilder::new(schema.clone())
+        .with_format(dialect.format()?.with_header(false))
+        .with_batch_size(BATCH_SIZE)
+        .build_buffered(reader)?;
+      for batch in batches {
+        writers.write(batch?).await?;
+      }
+    }
+    "json" => {
+      let batches = datafusion::arrow::json::ReaderBuilder::new(schema.clone())
+        .with_strict_mode(true)
+        .with_batch_size(BATCH_SIZE)
+        .build(BufReader::new(reader))?;
+      for batch in batches {
+        writers.write(batch?).await?;
+      }
+    }
+    format => {
+      return Err(QueryError::BadData(format!(
+        "{format} can't be ingested, expected csv or json"
+      )))
+    }
+  }
+
+  Ok(())
+}
+
+/// Reads the header line and checks that it names the columns of the schema, in order
+fn check_header<T: Read>(
+  reader: &mut BufReader<T>,
+  schema: &Schema,
+  dialect: &CsvDialect,
+) -> Result<(), QueryError> {
+  let mut line = vec![];
+  reader.read_until(b'\n', &mut line)?;
+
+  let format = dialect.format()?;
+  let (header, _) = format.infer_schema(line.as_slice(), Some(1))?;
+  let found: Vec<&String> = header.fields().iter().map(|f| f.name()).collect();
+  let expected: Vec<&String> = schema.fields().iter().map(|f| f.name()).collect();
+
+  if found != expected {
+    return Err(QueryError::BadData(format!(
+      "csv header {found:?} does not match the ingestion schema {expected:?}"
+    )));
+  }
+
+  Ok(())
+}
+
+/// One Parquet writer per partition, each written to `{folder}/{name=value/...}/part-{uuid}.parquet`
+struct PartitionWriters {
+  store: Arc<dyn ObjectStore>,
+  folder: Path,
+  /// the schema without the partition columns, which is what ends up in the files
+  file_schema: SchemaRef,
+  partition_indices: Vec<usize>,
+  partition_names: Vec<String>,
+  file_indices: Vec<usize>,
+  uuid: String,
+  writers: BTreeMap<Vec<String>, (Path, AsyncArrowWriter<BufWriter>)>,
+}
+
+impl PartitionWriters {
+  fn new(
+    store: Arc<dyn ObjectStore>,
+    folder: Path,
+    schema: &SchemaRef,
+    partition_by: &[String],
+    uuid: String,
+  ) -> Result<Self, QueryError> {
+    let mut partition_indices = vec![];
+    for name in partition_by {
+      partition_indices.push(schema.index_of(name)?);
+    }
+    let file_indices: Vec<usize> = (0..schema.fields().len())
+      .filter(|i| !partition_indices.contains(i))
+      .collect();
+
+    Ok(PartitionWriters {
+      store,
+      folder,
+      file_schema: Arc::new(schema.project(&file_indices)?),
+      partition_indices,
+      partition_names: partition_by.to_vec(),
+      file_indices,
+      uuid,
+      writers: BTreeMap::new(),
+    })
+  }
+
+  async fn write(&mut self, batch: RecordBatch) -> Result<(), QueryError> {
+    let file_batch = batch.project(&self.file_indices)?;
+    if self.partition_indices.is_empty() {
+      return self.write_partition(vec![], file_batch).await;
+    }
+
+    let mut values = vec![];
+    for (i, index) in self.partition_indices.iter().enumerate() {
+      let column = batch.column(*index);
+      if column.null_count() > 0 {
+        return Err(QueryError::BadData(format!(
+          "partition column {} can't be null",
+          self.partition_names[i]
+        )));
+      }
+      values.push(cast(column, &DataType::Utf8)?);
+    }
+
+    let mut rows: BTreeMap<Vec<String>, Vec<u32>> = BTreeMap::new();
+    for row in 0..batch.num_rows() {
+      let key = values
+        .iter()
+        .map(|v| v.as_string::<i32>().value(row).to_string())
+        .collect();
+      rows.entry(key).or_default().push(row as u32);
+    }
+
+    for (key, indices) in rows {
+      let partition = take_record_batch(&file_batch, &UInt32Array::from(indices))?;
+      self.write_partition(key, partition).await?;
+    }
+
+    Ok(())
+  }
+
+  async fn write_partition(
+    &mut self,
+    key: Vec<String>,
+    batch: RecordBatch,
+  ) -> Result<(), QueryError> {
+    if !self.writers.contains_key(&key) {
+      if self.writers.len() >= MAX_OPEN_PARTITIONS {
+        return Err(QueryError::BadData(format!(
+          "the data has more than {MAX_OPEN_PARTITIONS} partitions"
+        )));
+      }
+
+      let mut parts: Vec<String> = self
+        .partition_names
+        .iter()
+        .zip(key.iter())
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+      // object_store percent-encodes what it doesn't allow in a path, but DataFusion doesn't decode
+      // partition values when reading them back so they'd come back changed
+      if let Some(part) = parts
+        .iter()
+        .find(|part| PathPart::from(part.as_str()).as_ref() != part.as_str())
+      {
+        return Err(QueryError::BadData(format!(
+          "partition {part} can't be written to a path, partition values can't contain /, %, non ASCII or other characters that have to be escaped in a path"
+        )));
+      }
+      parts.push(format!("part-{}.parquet", self.uuid));
+      let path = parts
+        .iter()
+        .fold(self.folder.clone(), |path, part| path.child(part.as_str()));
+
+      let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+      let writer = AsyncArrowWriter::try_new(
+        BufWriter::new(self.store.clone(), path.clone()),
+        self.file_schema.clone(),
+        Some(properties),
+      )?;
+      self.writers.insert(key.clone(), (path, writer));
+    }
+
+    if let Some((_, writer)) = self.writers.get_mut(&key) {
+      writer.write(&batch).await?;
+    }
+
+    self.flush_buffered().await
+  }
+
+  /// Flushes the row groups of the writers holding the most until they're under MAX_BUFFERED_BYTES
+  /// together, a writer only flushes on its own once its row group is full
+  async fn flush_buffered(&mut self) -> Result<(), QueryError> {
+    let mut buffered: usize = self
+      .writers
+      .values()
+      .map(|(_, writer)| writer.in_progress_size())
+      .sum();
+
+    while buffered > MAX_BUFFERED_BYTES {
+      let largest = self
+        .writers
+        .values_mut()
+        .max_by_key(|(_, writer)| writer.in_progress_size());
+
+      if let Some((_, writer)) = largest {
+        buffered -= writer.in_progress_size();
+        writer.flush().await?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Finishes every file and returns their paths, if any of them can't be finished the others are
+  /// removed again
+  async fn close(mut self) -> Result<Vec<Path>, QueryError> {
+    let mut written = vec![];
+    let mut failed = None;
+    for (_, (path, writer)) in std::mem::take(&mut self.writers) {
+      if failed.is_some() {
+        continue;
+      }
+
+      match writer.close().await {
+        Ok(_) => written.push(path),
+        Err(e) => failed = Some(e),
+      }
+    }
+
+    match failed {
+      None => Ok(written),
+      Some(e) => {
+        for path in written {
+          let _ = self.store.delete(&path).await;
+        }
+        Err(e.into())
+      }
+    }
+  }
+
+  /// Stops the writers without finishing their files, so nothing is left in the store
+  async fn abort(mut self) {
+    for (_, (_, writer)) in std::mem::take(&mut self.writers) {
+      let _ = writer.into_inner().abort().await;
+    }
+  }
+}
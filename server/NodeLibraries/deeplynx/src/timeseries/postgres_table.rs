@@ -0,0 +1,521 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use datafusion::arrow::array::{
+  ArrayRef, BooleanArray, Date32Array, Float64Array, Int32Array, Int64Array, RecordBatch,
+  StringArray, TimestampMicrosecondArray,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::catalog::Session;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::DataFusionError;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::{Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::stream::RecordBatchReceiverStream;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::TryStreamExt;
+use sqlx::postgres::{PgPoolOptions, PgRow};
+use sqlx::{PgPool, Row};
+
+use crate::timeseries::datafusion_session::{record_batch, BATCH_SIZE};
+use crate::timeseries::errors::QueryError;
+
+/// The legacy timeseries tables to make queryable alongside the files, each data source's
+/// `y_{id}` table is registered under that same name so queries written against Postgres still work
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct LegacyTables {
+  pub db_connection_string: String,
+  pub data_source_ids: Vec<String>,
+}
+
+impl LegacyTables {
+  /// The pool the tables are read through. There's one per connection string for the life of the
+  /// process, so concurrent queries share its connections rather than each opening their own
+  pub fn pool(&self) -> Result<PgPool, QueryError> {
+    static POOLS: OnceLock<Mutex<HashMap<String, PgPool>>> = OnceLock::new();
+
+    let mut pools = POOLS
+      .get_or_init(Default::default)
+      .lock()
+      .unwrap_or_else(|e| e.into_inner());
+    if let Some(pool) = pools.get(&self.db_connection_string) {
+      return Ok(pool.clone());
+    }
+
+    // connections are opened as queries need them, the first query reading the schemas
+    let pool = PgPoolOptions::new()
+      .max_connections(10)
+      .connect_lazy(self.db_connection_string.as_str())?;
+    pools.insert(self.db_connection_string.clone(), pool.clone());
+
+    Ok(pool)
+  }
+}
+
+/// PostgresTableProvider reads a Postgres table, legacy bucket tables in particular, without copying
+/// it anywhere first. Only the projected columns are selected, and filters and limits DataFusion
+/// pushes down become the `WHERE` and `LIMIT` of the query sent to Postgres so it can use its
+/// indexes. Rows are streamed back a batch at a time as the query asks for them.
+#[derive(Debug)]
+pub struct PostgresTableProvider {
+  pool: PgPool,
+  table_name: String,
+  schema: SchemaRef,
+  columns: Vec<PgColumn>,
+}
+
+#[derive(Debug, Clone)]
+struct PgColumn {
+  name: String,
+  pg_type: PgType,
+}
+
+/// How a Postgres column is read. Anything without an Arrow equivalent, such as `jsonb`, is read as
+/// text
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PgType {
+  Int32,
+  Int64,
+  Float64,
+  Boolean,
+  Timestamp,
+  TimestampTz,
+  Date,
+  Text,
+}
+
+impl PgType {
+  fn from_information_schema(data_type: &str) -> Self {
+    match data_type {
+      "smallint" | "integer" => PgType::Int32,
+      "bigint" => PgType::Int64,
+      // numeric is arbitrary precision, it's read as a double like everywhere else in DeepLynx
+      "numeric" | "real" | "double precision" => PgType::Float64,
+      "boolean" => PgType::Boolean,
+      "timestamp without time zone" => PgType::Timestamp,
+      "timestamp with time zone" => PgType::TimestampTz,
+      "date" => PgType::Date,
+      _ => PgType::Text,
+    }
+  }
+
+  fn arrow_type(&self) -> DataType {
+    match self {
+      PgType::Int32 => DataType::Int32,
+      PgType::Int64 => DataType::Int64,
+      PgType::Float64 => DataType::Float64,
+      PgType::Boolean => DataType::Boolean,
+      PgType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, None),
+      PgType::TimestampTz => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+      PgType::Date => DataType::Date32,
+      PgType::Text => DataType::Utf8,
+    }
+  }
+
+  /// Whether Postgres compares values of this type the same way DataFusion does, text doesn't since
+  /// Postgres orders it by the database's collation
+  fn orders_like_arrow(&self) -> bool {
+    !matches!(self, PgType::Text)
+  }
+}
+
+impl PgColumn {
+  /// The column as it's selected, cast to the type its values are decoded as
+  fn select_sql(&self) -> String {
+    let name = quote_ident(self.name.as_str());
+    match self.pg_type {
+      PgType::Int32 => format!("{name}::integer"),
+      PgType::Float64 => format!("{name}::double precision"),
+      PgType::Text => format!("{name}::text"),
+      _ => name,
+    }
+  }
+
+  /// The column as it's used in a filter, numeric columns are left alone so their indexes still work
+  fn filter_sql(&self) -> String {
+    let name = quote_ident(self.name.as_str());
+    match self.pg_type {
+      PgType::Text => format!("{name}::text"),
+      _ => name,
+    }
+  }
+}
+
+impl PostgresTableProvider {
+  /// Reads the columns of `table_name` from the information schema, failing if the table doesn't
+  /// exist in the connection's current schema
+  pub async fn try_new(pool: PgPool, table_name: String) -> Result<Self, QueryError> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+      "SELECT column_name::text, data_type::text FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = $1
+        ORDER BY ordinal_position",
+    )
+    .bind(table_name.as_str())
+    .fetch_all(&pool)
+    .await?;
+
+    if rows.is_empty() {
+      return Err(QueryError::BadData(format!(
+        "table {table_name} does not exist"
+      )));
+    }
+
+    let columns: Vec<PgColumn> = rows
+      .into_iter()
+      .map(|(name, data_type)| PgColumn {
+        name,
+        pg_type: PgType::from_information_schema(data_type.as_str()),
+      })
+      .collect();
+    let fields: Vec<Field> = columns
+      .iter()
+      .map(|c| Field::new(c.name.as_str(), c.pg_type.arrow_type(), true))
+      .collect();
+
+    Ok(PostgresTableProvider {
+      pool,
+      table_name,
+      schema: Arc::new(Schema::new(fields)),
+      columns,
+    })
+  }
+
+  fn column(&self, name: &str) -> Option<&PgColumn> {
+    self.columns.iter().find(|c| c.name == name)
+  }
+
+  /// `expr` as Postgres SQL, or None if it can't be translated into something that gives exactly the
+  /// same results
+  fn filter_sql(&self, expr: &Expr) -> Option<String> {
+    match expr {
+      Expr::Column(c) => self.column(c.name.as_str()).map(PgColumn::filter_sql),
+      Expr::Literal(value, _) => literal_sql(value),
+      // integer columns compared to a wider type, Postgres widens them itself
+      Expr::Cast(c) if c.data_type.is_numeric() => match c.expr.as_ref() {
+        Expr::Column(column) => self
+          .column(column.name.as_str())
+          .filter(|col| col.pg_type.arrow_type().is_numeric())
+          .map(PgColumn::filter_sql),
+        _ => None,
+      },
+      Expr::BinaryExpr(binary) => {
+        let op = match binary.op {
+          Operator::And => "AND",
+          Operator::Or => "OR",
+          Operator::Eq => "=",
+          Operator::NotEq => "<>",
+          Operator::Lt => "<",
+          Operator::LtEq => "<=",
+          Operator::Gt => ">",
+          Operator::GtEq => ">=",
+          _ => return None,
+        };
+        if matches!(
+          binary.op,
+          Operator::Lt | Operator::LtEq | Operator::Gt | Operator::GtEq
+        ) && !(self.orders_like_arrow(&binary.left) && self.orders_like_arrow(&binary.right))
+        {
+          return None;
+        }
+
+        Some(format!(
+          "({} {op} {})",
+          self.filter_sql(&binary.left)?,
+          self.filter_sql(&binary.right)?
+        ))
+      }
+      Expr::Not(e) => Some(format!("(NOT {})", self.filter_sql(e)?)),
+      Expr::IsNull(e) => Some(format!("({} IS NULL)", self.filter_sql(e)?)),
+      Expr::IsNotNull(e) => Some(format!("({} IS NOT NULL)", self.filter_sql(e)?)),
+      Expr::Between(between) => {
+        if !self.orders_like_arrow(&between.expr) {
+          return None;
+        }
+        Some(format!(
+          "({} {}BETWEEN {} AND {})",
+          self.filter_sql(&between.expr)?,
+          if between.negated { "NOT " } else { "" },
+          self.filter_sql(&between.low)?,
+          self.filter_sql(&between.high)?
+        ))
+      }
+      Expr::InList(in_list) => {
+        let list = in_list
+          .list
+          .iter()
+          .map(|e| self.filter_sql(e))
+          .collect::<Option<Vec<_>>>()?;
+        Some(format!(
+          "({} {}IN ({}))",
+          self.filter_sql(&in_list.expr)?,
+          if in_list.negated { "NOT " } else { "" },
+          list.join(", ")
+        ))
+      }
+      // both default to a backslash escape
+      Expr::Like(like) if like.escape_char.is_none() => Some(format!(
+        "({} {}{} {})",
+        self.filter_sql(&like.expr)?,
+        if like.negated { "NOT " } else { "" },
+        if like.case_insensitive {
+          "ILIKE"
+        } else {
+          "LIKE"
+        },
+        self.filter_sql(&like.pattern)?
+      )),
+      _ => None,
+    }
+  }
+
+  /// Whether `expr` can be one side of a `<`, `>` or `BETWEEN` pushed down to Postgres
+  fn orders_like_arrow(&self, expr: &Expr) -> bool {
+    match expr {
+      Expr::Column(c) => self
+        .column(c.name.as_str())
+        .is_some_and(|c| c.pg_type.orders_like_arrow()),
+      Expr::Literal(value, _) => !matches!(
+        value,
+        ScalarValue::Utf8(_) | ScalarValue::LargeUtf8(_) | ScalarValue::Utf8View(_)
+      ),
+      _ => true,
+    }
+  }
+}
+
+#[async_trait]
+impl TableProvider for PostgresTableProvider {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn schema(&self) -> SchemaRef {
+    self.schema.clone()
+  }
+
+  fn table_type(&self) -> TableType {
+    TableType::Base
+  }
+
+  fn supports_filters_pushdown(
+    &self,
+    filters: &[&Expr],
+  ) -> datafusion::error::Result<Vec<TableProviderFilterPushDown>> {
+    Ok(
+      filters
+        .iter()
+        .map(|f| match self.filter_sql(f) {
+          Some(_) => TableProviderFilterPushDown::Exact,
+          None => TableProviderFilterPushDown::Unsupported,
+        })
+        .collect(),
+    )
+  }
+
+  async fn scan(
+    &self,
+    _state: &dyn Session,
+    projection: Option<&Vec<usize>>,
+    filters: &[Expr],
+    limit: Option<usize>,
+  ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+    let indices = match projection {
+      None => (0..self.columns.len()).collect(),
+      Some(p) => p.clone(),
+    };
+    let schema = Arc::new(self.schema.project(&indices)?);
+    let columns: Vec<PgColumn> = indices.iter().map(|i| self.columns[*i].clone()).collect();
+
+    let select: Vec<String> = columns.iter().map(PgColumn::select_sql).collect();
+    // an empty select list is valid in Postgres and still returns a row per row, e.g. for COUNT(*)
+    let mut sql = format!(
+      "SELECT {} FROM {}",
+      select.join(", "),
+      quote_ident(self.table_name.as_str())
+    );
+
+    let conditions: Vec<String> = filters.iter().filter_map(|f| self.filter_sql(f)).collect();
+    if !conditions.is_empty() {
+      sql.push_str(format!(" WHERE {}", conditions.join(" AND ")).as_str());
+    }
+    // DataFusion only passes a limit down when every filter was pushed down exactly
+    if let Some(limit) = limit {
+      sql.push_str(format!(" LIMIT {limit}").as_str());
+    }
+
+    let partition = PostgresScan {
+      pool: self.pool.clone(),
+      sql,
+      schema: schema.clone(),
+      columns,
+    };
+
+    Ok(Arc::new(StreamingTableExec::try_new(
+      schema,
+      vec![Arc::new(partition)],
+      None,
+      vec![],
+      false,
+      limit,
+    )?))
+  }
+}
+
+/// A single query against Postgres, run when DataFusion starts reading the table
+#[derive(Debug)]
+struct PostgresScan {
+  pool: PgPool,
+  sql: String,
+  schema: SchemaRef,
+  columns: Vec<PgColumn>,
+}
+
+impl PartitionStream for PostgresScan {
+  fn schema(&self) -> &SchemaRef {
+    &self.schema
+  }
+
+  fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+    let mut builder = RecordBatchReceiverStream::builder(self.schema.clone(), 2);
+    let tx = builder.tx();
+    let (pool, sql, schema, columns) = (
+      self.pool.clone(),
+      self.sql.clone(),
+      self.schema.clone(),
+      self.columns.clone(),
+    );
+
+    // dropping the stream when the query finishes early or is cancelled stops this task, which
+    // drops the row stream and with it the Postgres query
+    builder.spawn(async move {
+      let mut rows = sqlx::query(sql.as_str()).fetch(&pool);
+      let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+      loop {
+        let row = rows
+          .try_next()
+          .await
+          .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let done = row.is_none();
+        batch.extend(row);
+
+        if batch.len() == BATCH_SIZE || (done && !batch.is_empty()) {
+          let record_batch = to_record_batch(&schema, &columns, &batch);
+          batch.clear();
+          if tx.send(record_batch).await.is_err() {
+            return Ok(());
+          }
+        }
+        if done {
+          return Ok(());
+        }
+      }
+    });
+
+    builder.build()
+  }
+}
+
+fn to_record_batch(
+  schema: &SchemaRef,
+  columns: &[PgColumn],
+  rows: &[PgRow],
+) -> datafusion::error::Result<RecordBatch> {
+  let arrays = columns
+    .iter()
+    .enumerate()
+    .map(|(i, column)| to_array(column.pg_type, rows, i))
+    .collect::<Result<Vec<_>, sqlx::Error>>()
+    .map_err(|e| DataFusionError::External(Box::new(e)))?;
+
+  Ok(record_batch(schema.clone(), arrays, rows.len())?)
+}
+
+fn to_array(pg_type: PgType, rows: &[PgRow], index: usize) -> Result<ArrayRef, sqlx::Error> {
+  Ok(match pg_type {
+    PgType::Int32 => Arc::new(Int32Array::from(get::<i32>(rows, index)?)),
+    PgType::Int64 => Arc::new(Int64Array::from(get::<i64>(rows, index)?)),
+    PgType::Float64 => Arc::new(Float64Array::from(get::<f64>(rows, index)?)),
+    PgType::Boolean => Arc::new(BooleanArray::from(get::<bool>(rows, index)?)),
+    PgType::Timestamp => Arc::new(TimestampMicrosecondArray::from(
+      get::<NaiveDateTime>(rows, index)?
+        .into_iter()
+        .map(|t| t.map(|t| t.and_utc().timestamp_micros()))
+        .collect::<Vec<_>>(),
+    )),
+    PgType::TimestampTz => Arc::new(
+      TimestampMicrosecondArray::from(
+        get::<DateTime<Utc>>(rows, index)?
+          .into_iter()
+          .map(|t| t.map(|t| t.timestamp_micros()))
+          .collect::<Vec<_>>(),
+      )
+      .with_timezone("UTC"),
+    ),
+    PgType::Date => Arc::new(Date32Array::from(
+      get::<NaiveDate>(rows, index)?
+        .into_iter()
+        .map(|d| d.map(|d| (d - NaiveDate::default()).num_days() as i32))
+        .collect::<Vec<_>>(),
+    )),
+    PgType::Text => Arc::new(StringArray::from(get::<String>(rows, index)?)),
+  })
+}
+
+fn get<'r, T>(rows: &'r [PgRow], index: usize) -> Result<Vec<Option<T>>, sqlx::Error>
+where
+  T: sqlx::Decode<'r, sqlx::Postgres> + sqlx::Type<sqlx::Postgres>,
+{
+  rows.iter().map(|row| row.try_get(index)).collect()
+}
+
+/// A literal as Postgres SQL, strings are quoted and timestamps cast so they're compared as such
+fn literal_sql(value: &ScalarValue) -> Option<String> {
+  // a value too large to be counted in microseconds isn't pushed down
+  let timestamp = |value: Option<i64>, to_micros: fn(i64) -> Option<i64>, tz: &Option<Arc<str>>| {
+    let time = DateTime::from_timestamp_micros(to_micros(value?)?)?.naive_utc();
+    Some(match tz {
+      None => format!("'{}'::timestamp", time.format("%Y-%m-%d %H:%M:%S%.f")),
+      Some(_) => format!("'{}+00'::timestamptz", time.format("%Y-%m-%d %H:%M:%S%.f")),
+    })
+  };
+
+  match value {
+    ScalarValue::Boolean(Some(v)) => Some(v.to_string().to_uppercase()),
+    ScalarValue::Int8(Some(v)) => Some(v.to_string()),
+    ScalarValue::Int16(Some(v)) => Some(v.to_string()),
+    ScalarValue::Int32(Some(v)) => Some(v.to_string()),
+    ScalarValue::Int64(Some(v)) => Some(v.to_string()),
+    ScalarValue::UInt8(Some(v)) => Some(v.to_string()),
+    ScalarValue::UInt16(Some(v)) => Some(v.to_string()),
+    ScalarValue::UInt32(Some(v)) => Some(v.to_string()),
+    ScalarValue::UInt64(Some(v)) => Some(v.to_string()),
+    ScalarValue::Float32(Some(v)) if v.is_finite() => Some(format!("{v}::real")),
+    ScalarValue::Float64(Some(v)) if v.is_finite() => Some(format!("{v}::double precision")),
+    ScalarValue::Utf8(Some(v))
+    | ScalarValue::LargeUtf8(Some(v))
+    | ScalarValue::Utf8View(Some(v)) => Some(format!("'{}'", v.replace('\'', "''"))),
+    ScalarValue::Date32(Some(v)) => {
+      let date = NaiveDate::default().checked_add_signed(chrono::Duration::days(*v as i64))?;
+      Some(format!("'{date}'::date"))
+    }
+    ScalarValue::TimestampSecond(v, tz) => timestamp(*v, |v| v.checked_mul(1_000_000), tz),
+    ScalarValue::TimestampMillisecond(v, tz) => timestamp(*v, |v| v.checked_mul(1_000), tz),
+    ScalarValue::TimestampMicrosecond(v, tz) => timestamp(*v, Some, tz),
+    // Postgres only keeps microseconds, anything finer would be compared differently
+    ScalarValue::TimestampNanosecond(Some(v), tz) if v % 1_000 == 0 => {
+      timestamp(Some(*v), |v| Some(v / 1_000), tz)
+    }
+    _ => None,
+  }
+}
+
+fn quote_ident(name: &str) -> String {
+  format!("\"{}\"", name.replace('"', "\"\""))
+}
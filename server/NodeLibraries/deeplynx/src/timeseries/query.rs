@@ -1,16 +1,32 @@
 use chrono::Utc;
-use connection_string::AdoNetString;
-use datafusion::{arrow::json::ArrayWriter, config::CsvOptions, dataframe::DataFrameWriteOptions};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::arrow::json::ArrayWriter;
+use datafusion::common::ScalarValue;
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use datafusion::functions_aggregate::expr_fn::{count, max, min};
+use datafusion::prelude::{ident, lit, SessionContext};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::Statement;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
 use serde_json::{json, Value};
 use short_uuid::short;
-use tokio::fs::File;
 
-use crate::timeseries::azure_object_store;
-use crate::timeseries::datafusion_session::populate_session;
+use crate::timeseries::datafusion_session::{
+  populate_session_partial, populate_session_with_runtime, register_graph_snapshot,
+  register_legacy_tables, TableFiles,
+};
+use crate::timeseries::errors::QueryError;
 use crate::timeseries::file_metadata::FileMetadata;
+use crate::timeseries::graph_snapshot::GraphSnapshot;
+use crate::timeseries::postgres_table::LegacyTables;
+use crate::timeseries::query_output::{QueryOutput, QueryOutputOptions};
+use crate::timeseries::query_policy::{QueryLimits, QueryPolicy};
+use crate::timeseries::storage_connection::{file_size, remove_file, StorageConnection};
 
 /// For processing file uploads
-/// Returns the results of a SQL `DESCRIBE` of the file as stringified JSON.
+/// Runs the SQL `DESCRIBE` statements in `query` and returns the description of each file as
+/// stringified JSON. A file that can't be read or described is listed under `errors` rather than
+/// failing the whole report.
 #[napi]
 pub async fn process_upload(
   report_id: String,
@@ -18,70 +34,207 @@ pub async fn process_upload(
   storage_connection: String,
   files: Vec<FileMetadata>,
 ) -> napi::Result<String> {
-  let storage_connection: AdoNetString = storage_connection.parse().map_err(|e| {
+  let storage_connection: StorageConnection = storage_connection.parse().map_err(|e| {
     napi::Error::from_reason(format!(
       "Failed to parse storage_connection string with reason: {e}"
     ))
   })?;
-  let ctx = populate_session(&storage_connection, files.clone()).await?;
-  let queries = query.split(";");
-  let mut file_descriptions: Vec<Value> = Vec::new();
+  let (ctx, table_files, failed_files) =
+    populate_session_partial(&storage_connection, files).await?;
 
-  for q in queries {
-    let results = ctx
-      .sql(q)
-      .await
-      .map_err(|e| {
-        napi::Error::from_reason(format!("Failed to run query {query} with reason: {e}"))
-      })?
-      .collect()
-      .await
-      .map_err(|e| {
-        napi::Error::from_reason(format!("Failed to write results to JSON with reason: {e}"))
-      })?;
-
-    let batches = results.iter().collect::<Vec<&_>>();
-    let buf = Vec::new();
-    let mut writer = ArrayWriter::new(buf);
-
-    writer.write_batches(&batches).map_err(|e| {
-      napi::Error::from_reason(format!(
-        "Failed to write record batches to json with reason: {e}"
-      ))
-    })?;
+  describe(&ctx, report_id, query, &table_files, failed_files).await
+}
 
-    writer.finish().map_err(|e| {
-      napi::Error::from_reason(format!(
-        "Failed to finish writing record batches to json with reason: {e}"
-      ))
+/// Runs the `DESCRIBE` statements in `query` against an already populated session. `table_files` is
+/// the id of the file behind each table. `failed_files` are the files that couldn't be registered
+/// with the session, they're reported as errors and any statements describing them are skipped
+pub(crate) async fn describe(
+  ctx: &SessionContext,
+  report_id: String,
+  query: String,
+  table_files: &TableFiles,
+  failed_files: Vec<(String, QueryError)>,
+) -> napi::Result<String> {
+  let statements =
+    DFParser::parse_sql_with_dialect(query.as_str(), &GenericDialect {}).map_err(|e| {
+      napi::Error::from_reason(format!("Failed to parse query {query} with reason: {e}"))
     })?;
 
-    let description_json = writer.into_inner();
-    let description_string = String::from_utf8(description_json).map_err(|e| {
-      napi::Error::from_reason(format!(
-        "Failed to get json string from bytes with reason: {e}"
-      ))
-    })?;
+  let mut file_descriptions: Vec<Value> = Vec::new();
+  let mut errors: Vec<Value> = failed_files
+    .iter()
+    .map(|(file_id, e)| json!({ "file_id": file_id, "error": e.to_string() }))
+    .collect();
+
+  for statement in statements {
+    let sql = statement.to_string();
 
-    file_descriptions.push(json!({
-      "file_id": q
-        .to_lowercase()
-        .trim_start_matches("describe table_")
-        .to_string(),
-      "description": description_string
-    }))
+    let table = match described_table(ctx, &statement) {
+      Ok(table) => table,
+      Err(e) => {
+        errors.push(json!({ "statement": sql, "error": e.to_string() }));
+        continue;
+      }
+    };
+
+    let file_id = table_files.get(&table);
+    if failed_files.iter().any(|(id, _)| Some(id) == file_id) {
+      continue;
+    }
+
+    match describe_table(ctx, statement, &table).await {
+      Ok(mut description) => {
+        description["file_id"] = json!(file_id);
+        file_descriptions.push(description);
+      }
+      Err(e) => errors.push(json!({
+        "file_id": file_id,
+        "statement": sql,
+        "error": e.to_string()
+      })),
+    }
   }
 
   let describe_report = json!({
     "reportID": report_id,
-    "descriptions": file_descriptions
+    "descriptions": file_descriptions,
+    "errors": errors
   });
 
   Ok(describe_report.to_string())
 }
 
+/// The name of the table a `DESCRIBE` statement describes, any other statement is an error
+fn described_table(ctx: &SessionContext, statement: &DFStatement) -> Result<String, QueryError> {
+  let is_describe = matches!(
+    statement,
+    DFStatement::Statement(s) if matches!(s.as_ref(), Statement::ExplainTable { .. })
+  );
+  if !is_describe {
+    return Err(QueryError::BadData(
+      "only DESCRIBE statements can be run when processing an upload".to_string(),
+    ));
+  }
+
+  ctx
+    .state()
+    .resolve_table_references(statement)?
+    .pop()
+    .map(|table| table.table().to_string())
+    .ok_or_else(|| QueryError::BadData("DESCRIBE statement has no table".to_string()))
+}
+
+/// The `DESCRIBE` rows of a table, as the stringified JSON `description` the file descriptions have
+/// always been stored as, plus each column's Arrow type and statistics taken from a sample of its rows
+async fn describe_table(
+  ctx: &SessionContext,
+  statement: DFStatement,
+  table: &str,
+) -> Result<Value, QueryError> {
+  let plan = ctx.state().statement_to_plan(statement).await?;
+  let batches = ctx.execute_logical_plan(plan).await?.collect().await?;
+
+  let mut writer = ArrayWriter::new(Vec::new());
+  writer.write_batches(&batches.iter().collect::<Vec<_>>())?;
+  writer.finish()?;
+  let description = String::from_utf8(writer.into_inner())
+    .map_err(|e| QueryError::BadData(format!("description is not valid UTF-8: {e}")))?;
+
+  let sample = ctx
+    .table(table)
+    .await?
+    .limit(0, Some(DESCRIBE_SAMPLE_ROWS))?;
+  let fields = sample.schema().fields().clone();
+
+  let mut aggregates = vec![count(lit(1)).alias("sample_rows")];
+  for (i, field) in fields.iter().enumerate() {
+    aggregates.push(count(ident(field.name())).alias(format!("count_{i}")));
+    if has_min_max(field.data_type()) {
+      aggregates.push(min(ident(field.name())).alias(format!("min_{i}")));
+      aggregates.push(max(ident(field.name())).alias(format!("max_{i}")));
+    }
+  }
+
+  let stats = sample.aggregate(vec![], aggregates)?.collect().await?;
+  let stats = stats
+    .first()
+    .ok_or_else(|| QueryError::BadData(format!("no sample statistics for {table}")))?;
+  let stat = |name: &str| -> Result<Option<ScalarValue>, QueryError> {
+    match stats.column_by_name(name) {
+      None => Ok(None),
+      Some(column) => Ok(Some(ScalarValue::try_from_array(column, 0)?)),
+    }
+  };
+  let as_count = |value: Option<ScalarValue>| match value {
+    Some(ScalarValue::Int64(Some(n))) => n,
+    _ => 0,
+  };
+  let as_string = |value: Option<ScalarValue>| match value {
+    Some(v) if !v.is_null() => Some(v.to_string()),
+    _ => None,
+  };
+
+  let sample_rows = as_count(stat("sample_rows")?);
+  let mut columns = vec![];
+  for (i, field) in fields.iter().enumerate() {
+    columns.push(json!({
+      "name": field.name(),
+      "data_type": field.data_type().to_string(),
+      "nullable": field.is_nullable(),
+      "null_count": sample_rows - as_count(stat(format!("count_{i}").as_str())?),
+      "min": as_string(stat(format!("min_{i}").as_str())?),
+      "max": as_string(stat(format!("max_{i}").as_str())?),
+    }));
+  }
+
+  Ok(json!({
+    "table_name": table,
+    "description": description,
+    "columns": columns,
+    "sample_rows": sample_rows
+  }))
+}
+
+/// Column statistics are taken from at most this many rows of each file
+const DESCRIBE_SAMPLE_ROWS: usize = 1000;
+
+fn has_min_max(data_type: &DataType) -> bool {
+  data_type.is_numeric()
+    || matches!(
+      data_type,
+      DataType::Boolean
+        | DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Utf8View
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Time32(_)
+        | DataType::Time64(_)
+        | DataType::Timestamp(_, _)
+        | DataType::Duration(_)
+    )
+}
+
+/// The optional parts of a processQuery call
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct ProcessQueryOptions {
+  /// The format and compression of the results, CSV without compression by default
+  pub output: Option<QueryOutputOptions>,
+  /// How long the query can run and how much memory it can use
+  pub limits: Option<QueryLimits>,
+  /// Legacy `y_{id}` tables the query can read, and join with the files, under those same names
+  pub legacy_tables: Option<LegacyTables>,
+  /// The container's graph, queryable as `nodes` and `edges`
+  pub graph_snapshot: Option<GraphSnapshot>,
+}
+
 /// For processing a query against a set of files.
-/// Uploads results to a location specified in the request object.
+/// Uploads results to a location specified in the request object, as CSV unless the `output` option
+/// asks for another format or compression. Only a single `SELECT` can be run and it's stopped once it
+/// goes past the timeout or memory limit in `limits`, either is reported with the `InvalidArg` code.
+/// The legacy `y_{id}` tables in `legacy_tables` can be queried, and joined with the files, under
+/// those same names, and the container's graph as `nodes` and `edges` when `graph_snapshot` is set.
 /// Returns the metadata of the query results as stringified JSON.
 #[napi]
 pub async fn process_query(
@@ -89,90 +242,92 @@ pub async fn process_query(
   query: String,
   storage_connection: String,
   files: Vec<FileMetadata>,
+  options: Option<ProcessQueryOptions>,
 ) -> napi::Result<String> {
-  let uuid = short!();
-  let now = Utc::now();
+  let options = options.unwrap_or_default();
+  let output = options.output.unwrap_or_default().resolve()?;
+  let policy = options.limits.unwrap_or_default().resolve()?;
 
-  let storage_connection: AdoNetString = storage_connection.parse().map_err(|e| {
+  let storage_connection: StorageConnection = storage_connection.parse().map_err(|e| {
     napi::Error::from_reason(format!(
       "Failed to parse storage_connection string with reason: {e}"
     ))
   })?;
+  // checked before the session is populated so a missing upload path fails fast
+  storage_connection.upload_root()?;
 
-  let ctx = populate_session(&storage_connection, files).await?;
-
-  let query_results = ctx.sql(query.as_str()).await.map_err(|e| {
-    napi::Error::from_reason(format!("Failed to run query {query} with reason: {e}"))
-  })?;
+  let runtime = policy.runtime_env(RuntimeEnvBuilder::new())?;
+  let ctx = populate_session_with_runtime(&storage_connection, files, runtime).await?;
+  if let Some(legacy_tables) = &options.legacy_tables {
+    register_legacy_tables(&ctx, legacy_tables).await?;
+  }
+  if let Some(graph_snapshot) = &options.graph_snapshot {
+    register_graph_snapshot(&ctx, graph_snapshot).await?;
+  }
 
-  let provider = storage_connection.get("provider").ok_or_else(|| {
-    napi::Error::from_reason("provider is not set in connection string".to_string())
-  })?;
+  query_to_file(&ctx, &storage_connection, report_id, query, output, policy).await
+}
 
-  let upload_path = storage_connection.get("uploadpath").ok_or_else(|| {
-    napi::Error::from_reason("uploadPath is not set in connection string".to_string())
-  })?;
+/// Runs `query` against an already populated session and uploads the results
+pub(crate) async fn query_to_file(
+  ctx: &SessionContext,
+  storage_connection: &StorageConnection,
+  report_id: String,
+  query: String,
+  output: QueryOutput,
+  policy: QueryPolicy,
+) -> napi::Result<String> {
+  let uuid = short!();
+  let now = Utc::now();
+  let upload_path = storage_connection.upload_path()?;
+  let root_upload_path = storage_connection.upload_root()?;
 
-  let file_name = format!("{}_report_{}_{}.csv", uuid, report_id, now.timestamp_millis());
+  QueryPolicy::check_read_only(query.as_str())?;
+  let query_results = ctx
+    .sql_with_options(query.as_str(), QueryPolicy::sql_options())
+    .await
+    .map_err(|e| {
+      napi::Error::from_reason(format!("Failed to run query {query} with reason: {e}"))
+    })?;
 
-  let root_upload_path = match provider.as_str() {
-    "filesystem" => {
-      let root_file_path = storage_connection.get("rootfilepath").ok_or_else(|| {
-        napi::Error::from_reason("rootFilePath is not set in connection string".to_string())
-      })?;
-      format!("{root_file_path}{upload_path}/")
-    }
-    "azure_blob" => {
-      let blob_endpoint = storage_connection.get("blobendpoint").ok_or_else(|| {
-        napi::Error::from_reason(
-          "blobEndpoint not set in connection string with provider: azure".to_string(),
-        )
-      })?;
-      format!("{blob_endpoint}/{upload_path}/")
-    }
-    _ => {
-      return Err(napi::Error::from_reason(
-        "Cannot set file upload path: provider is not set in connection string".to_string(),
-      ))
-    }
-  };
+  let file_name = format!(
+    "{}_report_{}_{}.{}",
+    uuid,
+    report_id,
+    now.timestamp_millis(),
+    output.extension()
+  );
 
   let full_upload_path = format!("{root_upload_path}{file_name}");
 
-  query_results
-    .write_csv(
-      &full_upload_path,
-      DataFrameWriteOptions::new(),
-      Some(CsvOptions {
-        has_header: Some(true),
-        ..Default::default()
-      }),
-    )
+  match policy
+    .enforce(output.write(query_results, &full_upload_path))
     .await
-    .map_err(|e| {
-      napi::Error::from_reason(format!("Failed to write results to CSV with reason: {e}"))
-    })?;
-
-  let file_size = match provider.as_str() {
-    "filesystem" => {
-      let res_file = File::open(&full_upload_path).await?;
-      res_file.metadata().await?.len()
+  {
+    Ok(()) => (),
+    Err(e @ QueryError::Limit(_)) => {
+      // whatever was written before the query was stopped is incomplete, there may be nothing there
+      let _ = remove_file(ctx, &full_upload_path).await;
+      return Err(e.into());
     }
-    "azure_blob" => {
-      azure_object_store::get_blob_size(&storage_connection, upload_path, &file_name).await?
+    Err(e) => {
+      return Err(napi::Error::from_reason(format!(
+        "Failed to write results to {} with reason: {e}",
+        output.format
+      )))
     }
-    _ => {
-      return Err(napi::Error::from_reason(
-        "Cannot get file metadata: provider is not set in connection string".to_string(),
-      ))
-    }
-  };
+  }
+
+  let file_size = file_size(ctx, &full_upload_path).await?;
 
   let result_metadata = json!({
     "file_name": file_name,
     "file_size": file_size as f64 / 1000.00,
+    "file_size_bytes": file_size,
+    "file_format": output.format.to_string(),
+    "compression": output.compression.to_string(),
     "file_path": format!("{upload_path}/"),
-    "adapter": provider,
+    "adapter": storage_connection.provider(),
     "uuid": format!("{uuid}_"),
   });
 
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use datafusion::execution::cache::cache_manager::CacheManagerConfig;
+use datafusion::execution::cache::cache_unit::DefaultFileStatisticsCache;
+use datafusion::execution::runtime_env::{RuntimeEnv, RuntimeEnvBuilder};
+use datafusion::prelude::SessionContext;
+use tokio::sync::Mutex;
+
+use crate::timeseries::datafusion_session::{
+  new_session, register_file, register_graph_snapshot, register_legacy_tables, TableFiles,
+};
+use crate::timeseries::errors::QueryError;
+use crate::timeseries::file_metadata::{extract_table_info, FileMetadata};
+use crate::timeseries::query::{describe, query_to_file, ProcessQueryOptions};
+use crate::timeseries::query_policy::{QueryLimits, QueryPolicy};
+use crate::timeseries::query_stream::{
+  register_extra_tables, stream_results, QueryStream, QueryStreamOptions,
+};
+use crate::timeseries::storage_connection::StorageConnection;
+
+/// TimeseriesQueryEngine keeps one DataFusion session alive across queries against the same storage
+/// connection. Files are registered the first time a query includes them and stay registered,
+/// keyed by their id, so their schemas are only inferred once. Each query still only sees the files
+/// it was called with. Parquet statistics are cached across the whole session and are re-read if the
+/// file underneath changes size or modification time.
+/// Queries run through the engine are held to its `limits`, the memory limit is shared by all of the
+/// queries running at once. A query that brings limits of its own gets its own memory limit instead.
+#[napi]
+pub struct TimeseriesQueryEngine {
+  storage_connection: StorageConnection,
+  policy: QueryPolicy,
+  ctx: SessionContext,
+  tables: Mutex<HashMap<String, RegisteredFile>>,
+}
+
+struct RegisteredFile {
+  file: FileMetadata,
+  table_names: Vec<String>,
+}
+
+#[napi]
+impl TimeseriesQueryEngine {
+  #[napi(constructor)]
+  pub fn new(storage_connection: String, limits: Option<QueryLimits>) -> napi::Result<Self> {
+    let storage_connection: StorageConnection = storage_connection.parse().map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to parse storage_connection string with reason: {e}"
+      ))
+    })?;
+    let policy = limits.unwrap_or_default().resolve()?;
+
+    let cache = CacheManagerConfig::default()
+      .with_files_statistics_cache(Some(Arc::new(DefaultFileStatisticsCache::default())));
+    let runtime = policy.runtime_env(RuntimeEnvBuilder::new().with_cache_manager(cache))?;
+
+    Ok(TimeseriesQueryEngine {
+      ctx: new_session(&storage_connection, runtime)?,
+      storage_connection,
+      policy,
+      tables: Mutex::new(HashMap::new()),
+    })
+  }
+
+  /// Same as the processUpload function, using the engine's session
+  #[napi]
+  pub async fn process_upload(
+    &self,
+    report_id: String,
+    query: String,
+    files: Vec<FileMetadata>,
+  ) -> napi::Result<String> {
+    let (ctx, mut table_files, failed_files) = self.session(files, self.ctx.runtime_env()).await?;
+    for (id, _) in &failed_files {
+      table_files
+        .entry(format!("table_{id}"))
+        .or_insert_with(|| id.clone());
+    }
+
+    describe(&ctx, report_id, query, &table_files, failed_files).await
+  }
+
+  /// Same as the processQuery function, using the engine's session. The query is held to the
+  /// engine's limits unless `options` has limits of its own
+  #[napi]
+  pub async fn process_query(
+    &self,
+    report_id: String,
+    query: String,
+    files: Vec<FileMetadata>,
+    options: Option<ProcessQueryOptions>,
+  ) -> napi::Result<String> {
+    let options = options.unwrap_or_default();
+    let output = options.output.unwrap_or_default().resolve()?;
+    let (policy, runtime) = self.policy(options.limits.as_ref())?;
+    self.storage_connection.upload_root()?;
+
+    let ctx = self.session_with_all(files, runtime).await?;
+    if let Some(legacy_tables) = &options.legacy_tables {
+      register_legacy_tables(&ctx, legacy_tables).await?;
+    }
+    if let Some(graph_snapshot) = &options.graph_snapshot {
+      register_graph_snapshot(&ctx, graph_snapshot).await?;
+    }
+
+    query_to_file(
+      &ctx,
+      &self.storage_connection,
+      report_id,
+      query,
+      output,
+      policy,
+    )
+    .await
+  }
+
+  /// Same as the streamQuery function, using the engine's session. The stream is held to the
+  /// engine's limits unless `options` has limits of its own
+  #[napi]
+  pub async fn stream_query(
+    &self,
+    query: String,
+    files: Vec<FileMetadata>,
+    options: Option<QueryStreamOptions>,
+  ) -> napi::Result<QueryStream> {
+    let options = options.unwrap_or_default();
+    let (policy, runtime) = self.policy(options.limits.as_ref())?;
+
+    let ctx = self.session_with_all(files, runtime).await?;
+    register_extra_tables(&ctx, &options).await?;
+
+    stream_results(&ctx, query, options, policy).await
+  }
+
+  /// Drops the tables registered for a file so the next query that includes it registers it again,
+  /// use this when a file has been replaced. Returns false if the file wasn't registered
+  #[napi]
+  pub async fn invalidate(&self, file_id: String) -> napi::Result<bool> {
+    let mut tables = self.tables.lock().await;
+
+    match tables.remove(&file_id) {
+      None => Ok(false),
+      Some(registered) => {
+        self.deregister(&registered)?;
+        Ok(true)
+      }
+    }
+  }
+
+  /// Drops every registered table
+  #[napi]
+  pub async fn clear(&self) -> napi::Result<()> {
+    let mut tables = self.tables.lock().await;
+
+    for (_, registered) in tables.drain() {
+      self.deregister(&registered)?;
+    }
+
+    Ok(())
+  }
+
+  /// The ids of the files currently registered with the engine
+  #[napi]
+  pub async fn registered_files(&self) -> Vec<String> {
+    let mut ids: Vec<String> = self.tables.lock().await.keys().cloned().collect();
+    ids.sort();
+    ids
+  }
+
+  /// A session for a single call, holding only the tables of `files`. Any of them the engine doesn't
+  /// already have are registered first. A file that can't be registered doesn't stop the others, the
+  /// ids of those that failed are returned along with why
+  async fn session(
+    &self,
+    files: Vec<FileMetadata>,
+    runtime: Arc<RuntimeEnv>,
+  ) -> Result<(SessionContext, TableFiles, Vec<(String, QueryError)>), QueryError> {
+    let mut tables = self.tables.lock().await;
+    let ids: Vec<String> = files.iter().map(|file| file.id.clone()).collect();
+    let failed = self.register_files(&mut tables, files).await?;
+
+    let ctx = new_session(&self.storage_connection, runtime)?;
+    let mut table_files = TableFiles::new();
+    for id in ids {
+      if let Some(registered) = tables.get(&id) {
+        for name in &registered.table_names {
+          ctx.register_table(name.as_str(), self.ctx.table_provider(name.as_str()).await?)?;
+          table_files.insert(name.clone(), id.clone());
+        }
+      }
+    }
+
+    Ok((ctx, table_files, failed))
+  }
+
+  /// session for `files`, failing if any of them can't be registered
+  async fn session_with_all(
+    &self,
+    files: Vec<FileMetadata>,
+    runtime: Arc<RuntimeEnv>,
+  ) -> Result<SessionContext, QueryError> {
+    let (ctx, _, failed) = self.session(files, runtime).await?;
+    match failed.into_iter().next() {
+      None => Ok(ctx),
+      Some((_, e)) => Err(e),
+    }
+  }
+
+  /// The policy a call is held to and the runtime that enforces it. Calls without limits of their own
+  /// share the engine's runtime, and with it the memory limit. Calls with limits get a memory pool of
+  /// their own, the Parquet statistics cache is still the engine's
+  fn policy(
+    &self,
+    limits: Option<&QueryLimits>,
+  ) -> Result<(QueryPolicy, Arc<RuntimeEnv>), QueryError> {
+    match limits {
+      None => Ok((self.policy, self.ctx.runtime_env())),
+      Some(limits) => {
+        let policy = limits.resolve()?;
+        let runtime =
+          policy.runtime_env(RuntimeEnvBuilder::from_runtime_env(&self.ctx.runtime_env()))?;
+        Ok((policy, runtime))
+      }
+    }
+  }
+
+  /// Registers any of `files` the engine doesn't already have, returning those that failed
+  async fn register_files(
+    &self,
+    tables: &mut HashMap<String, RegisteredFile>,
+    files: Vec<FileMetadata>,
+  ) -> Result<Vec<(String, QueryError)>, QueryError> {
+    let mut failed = vec![];
+
+    for file in files {
+      match tables.get(&file.id) {
+        Some(registered) if registered.file == file => continue,
+        // the id now points at a different file, its old tables can't be reused
+        Some(registered) => {
+          self.deregister(registered)?;
+          tables.remove(&file.id);
+        }
+        None => (),
+      }
+
+      let registered = async {
+        let mut table_names = vec![];
+        for table in &extract_table_info(vec![file.clone()])? {
+          table_names.extend(register_file(&self.ctx, &self.storage_connection, table).await?);
+        }
+        Ok::<Vec<String>, QueryError>(table_names)
+      }
+      .await;
+
+      match registered {
+        Ok(table_names) => {
+          tables.insert(file.id.clone(), RegisteredFile { file, table_names });
+        }
+        Err(e) => failed.push((file.id, e)),
+      }
+    }
+
+    Ok(failed)
+  }
+
+  fn deregister(&self, registered: &RegisteredFile) -> Result<(), QueryError> {
+    for name in &registered.table_names {
+      self.ctx.deregister_table(name.as_str())?;
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use datafusion::common::parsers::CompressionTypeVariant;
+use datafusion::config::{CsvOptions, JsonOptions, TableParquetOptions};
+use datafusion::dataframe::{DataFrame, DataFrameWriteOptions};
+use datafusion::datasource::file_format::arrow::ArrowFormatFactory;
+use datafusion::datasource::file_format::format_as_file_type;
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::LogicalPlanBuilder;
+
+use crate::timeseries::errors::QueryError;
+
+/// Controls how the results of `process_query` are written out. Both fields are optional, leaving
+/// them out writes an uncompressed CSV with a header row.
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct QueryOutputOptions {
+  /// `csv` (default), `parquet`, `json` (newline delimited) or `arrow` (IPC file format)
+  pub format: Option<String>,
+  /// `none`, `gzip` or `zstd`. CSV and JSON default to `none`, Parquet defaults to `zstd`. Arrow IPC
+  /// files are always written with LZ4 compressed buffers so `lz4` is the only value they accept
+  pub compression: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  Csv,
+  Parquet,
+  Json,
+  Arrow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputCompression {
+  None,
+  Gzip,
+  Zstd,
+  Lz4,
+}
+
+impl fmt::Display for OutputFormat {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      OutputFormat::Csv => "csv",
+      OutputFormat::Parquet => "parquet",
+      OutputFormat::Json => "json",
+      OutputFormat::Arrow => "arrow",
+    })
+  }
+}
+
+impl fmt::Display for OutputCompression {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      OutputCompression::None => "none",
+      OutputCompression::Gzip => "gzip",
+      OutputCompression::Zstd => "zstd",
+      OutputCompression::Lz4 => "lz4",
+    })
+  }
+}
+
+/// The validated combination of format and compression a query's results will be written with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryOutput {
+  pub format: OutputFormat,
+  pub compression: OutputCompression,
+}
+
+impl QueryOutputOptions {
+  /// Validates the requested format and compression, filling in the defaults for anything not set
+  pub fn resolve(&self) -> Result<QueryOutput, QueryError> {
+    let format = match self.format.as_deref().map(str::to_lowercase).as_deref() {
+      None | Some("csv") => OutputFormat::Csv,
+      Some("parquet") => OutputFormat::Parquet,
+      Some("json") | Some("jsonl") | Some("ndjson") => OutputFormat::Json,
+      Some("arrow") | Some("ipc") => OutputFormat::Arrow,
+      Some(f) => {
+        return Err(QueryError::BadData(format!(
+          "unsupported output format {f}, expected one of csv, parquet, json or arrow"
+        )))
+      }
+    };
+
+    let requested = match self
+      .compression
+      .as_deref()
+      .map(str::to_lowercase)
+      .as_deref()
+    {
+      None => None,
+      Some("none") | Some("uncompressed") => Some(OutputCompression::None),
+      Some("gzip") | Some("gz") => Some(OutputCompression::Gzip),
+      Some("zstd") | Some("zst") => Some(OutputCompression::Zstd),
+      Some("lz4") => Some(OutputCompression::Lz4),
+      Some(c) => {
+        return Err(QueryError::BadData(format!(
+          "unsupported output compression {c}, expected one of none, gzip, zstd or lz4"
+        )))
+      }
+    };
+
+    let compression = match (format, requested) {
+      (OutputFormat::Arrow, None | Some(OutputCompression::Lz4)) => OutputCompression::Lz4,
+      (OutputFormat::Arrow, Some(c)) => {
+        return Err(QueryError::BadData(format!(
+          "arrow output is always lz4 compressed, {c} compression is not supported"
+        )))
+      }
+      (_, Some(OutputCompression::Lz4)) => {
+        return Err(QueryError::BadData(format!(
+          "lz4 compression is only supported for arrow output, not {format}"
+        )))
+      }
+      (OutputFormat::Parquet, None) => OutputCompression::Zstd,
+      (_, None) => OutputCompression::None,
+      (_, Some(c)) => c,
+    };
+
+    Ok(QueryOutput {
+      format,
+      compression,
+    })
+  }
+}
+
+impl QueryOutput {
+  /// File extension for the results file. Parquet and Arrow compress internally so only CSV and JSON
+  /// get a compression suffix
+  pub fn extension(&self) -> &'static str {
+    match (self.format, self.compression) {
+      (OutputFormat::Csv, OutputCompression::Gzip) => "csv.gz",
+      (OutputFormat::Csv, OutputCompression::Zstd) => "csv.zst",
+      (OutputFormat::Csv, _) => "csv",
+      (OutputFormat::Json, OutputCompression::Gzip) => "jsonl.gz",
+      (OutputFormat::Json, OutputCompression::Zstd) => "jsonl.zst",
+      (OutputFormat::Json, _) => "jsonl",
+      (OutputFormat::Parquet, _) => "parquet",
+      (OutputFormat::Arrow, _) => "arrow",
+    }
+  }
+
+  /// Executes the query and writes its results as a single file at `path`
+  pub async fn write(&self, results: DataFrame, path: &str) -> Result<(), DataFusionError> {
+    // the report is always one file, never a directory of partitioned parts
+    let options = DataFrameWriteOptions::new().with_single_file_output(true);
+
+    match self.format {
+      OutputFormat::Csv => {
+        results
+          .write_csv(
+            path,
+            options,
+            Some(CsvOptions {
+              has_header: Some(true),
+              compression: self.compression_variant(),
+              ..Default::default()
+            }),
+          )
+          .await?;
+      }
+      OutputFormat::Json => {
+        results
+          .write_json(
+            path,
+            options,
+            Some(JsonOptions {
+              compression: self.compression_variant(),
+              ..Default::default()
+            }),
+          )
+          .await?;
+      }
+      OutputFormat::Parquet => {
+        let mut parquet_options = TableParquetOptions::default();
+        parquet_options.global.compression = Some(
+          match self.compression {
+            OutputCompression::Gzip => "gzip(6)",
+            OutputCompression::Zstd => "zstd(3)",
+            _ => "uncompressed",
+          }
+          .to_string(),
+        );
+        results
+          .write_parquet(path, options, Some(parquet_options))
+          .await?;
+      }
+      OutputFormat::Arrow => {
+        // DataFrame has no write_arrow, so build the same COPY plan the other writers use
+        let (state, plan) = results.into_parts();
+        let plan = LogicalPlanBuilder::copy_to(
+          plan,
+          path.to_string(),
+          format_as_file_type(Arc::new(ArrowFormatFactory::new())),
+          HashMap::new(),
+          vec![],
+        )?
+        .build()?;
+        DataFrame::new(state, plan).collect().await?;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn compression_variant(&self) -> CompressionTypeVariant {
+    match self.compression {
+      OutputCompression::Gzip => CompressionTypeVariant::GZIP,
+      OutputCompression::Zstd => CompressionTypeVariant::ZSTD,
+      _ => CompressionTypeVariant::UNCOMPRESSED,
+    }
+  }
+}
@@ -0,0 +1,148 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datafusion::error::DataFusionError;
+use datafusion::execution::context::SQLOptions;
+use datafusion::execution::runtime_env::{RuntimeEnv, RuntimeEnvBuilder};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::sqlparser::ast::Statement;
+use datafusion::sql::sqlparser::dialect::GenericDialect;
+use tokio::time::Instant;
+
+use crate::timeseries::errors::{QueryError, QueryLimitError};
+
+const DEFAULT_TIMEOUT_MS: u32 = 5 * 60 * 1000;
+const DEFAULT_MEMORY_LIMIT_MB: u32 = 1024;
+
+/// Limits put on the queries run by `process_query` and `stream_query`. Both fields are optional,
+/// leaving them out uses a 5 minute timeout and a 1024 MB memory limit.
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct QueryLimits {
+  /// How long a query may run, including writing out its results, before it's cancelled. The query
+  /// notices the timeout whenever it's waiting on reading or writing data, DataFusion doesn't
+  /// interrupt an operator that's in the middle of a long computation
+  pub timeout_ms: Option<u32>,
+  /// Memory available to sorts, joins and aggregations. Those that can spill to disk do once it's
+  /// reached, a query that still needs more fails
+  pub memory_limit_mb: Option<u32>,
+}
+
+/// The validated limits a query is run with. Queries are also always read only, `process_query`
+/// is for reading files, never for creating tables, copying data around or changing settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryPolicy {
+  pub timeout: Duration,
+  /// in bytes
+  pub memory_limit: usize,
+}
+
+impl QueryLimits {
+  /// Validates the requested limits, filling in the defaults for anything not set
+  pub fn resolve(&self) -> Result<QueryPolicy, QueryError> {
+    let timeout_ms = self.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+    if timeout_ms == 0 {
+      return Err(QueryError::BadData(
+        "timeoutMs must be greater than 0".to_string(),
+      ));
+    }
+
+    let memory_limit_mb = self.memory_limit_mb.unwrap_or(DEFAULT_MEMORY_LIMIT_MB);
+    if memory_limit_mb == 0 {
+      return Err(QueryError::BadData(
+        "memoryLimitMb must be greater than 0".to_string(),
+      ));
+    }
+
+    Ok(QueryPolicy {
+      timeout: Duration::from_millis(timeout_ms as u64),
+      memory_limit: memory_limit_mb as usize * 1024 * 1024,
+    })
+  }
+}
+
+impl QueryPolicy {
+  /// Builds the runtime a session runs its queries with, its memory pool enforces the memory limit
+  pub fn runtime_env(&self, builder: RuntimeEnvBuilder) -> Result<Arc<RuntimeEnv>, QueryError> {
+    Ok(
+      builder
+        .with_memory_limit(self.memory_limit, 1.0)
+        .build_arc()?,
+    )
+  }
+
+  /// Checks `query` is a single `SELECT`, rejecting DDL, DML, `COPY` and everything else before any
+  /// planning happens
+  pub fn check_read_only(query: &str) -> Result<(), QueryError> {
+    let mut statements = DFParser::parse_sql_with_dialect(query, &GenericDialect {})
+      .map_err(|e| QueryError::BadData(format!("failed to parse query {query}: {e}")))?;
+
+    let statement = match (statements.pop_front(), statements.is_empty()) {
+      (Some(statement), true) => statement,
+      (None, _) => return Err(QueryError::BadData("query is empty".to_string())),
+      (Some(_), false) => {
+        return Err(QueryError::BadData(
+          "only one statement can be run per query".to_string(),
+        ))
+      }
+    };
+
+    match &statement {
+      DFStatement::Statement(s) if matches!(s.as_ref(), Statement::Query(_)) => Ok(()),
+      DFStatement::CopyTo(_) => Err(QueryLimitError::ReadOnly("COPY".to_string()).into()),
+      DFStatement::CreateExternalTable(_) => {
+        Err(QueryLimitError::ReadOnly("CREATE EXTERNAL TABLE".to_string()).into())
+      }
+      _ => {
+        let sql = statement.to_string();
+        let keyword = sql.split_whitespace().next().unwrap_or_default();
+        Err(QueryLimitError::ReadOnly(keyword.to_uppercase()).into())
+      }
+    }
+  }
+
+  /// The options queries are planned with, DataFusion refuses anything that modifies the session or
+  /// writes data even if it made it past check_read_only
+  pub fn sql_options() -> SQLOptions {
+    SQLOptions::new()
+      .with_allow_ddl(false)
+      .with_allow_dml(false)
+      .with_allow_statements(false)
+  }
+
+  /// Runs `task`, dropping it if it's still running once the timeout is up which cancels the query.
+  /// DataFusion running out of memory is reported as a limit violation rather than a query error
+  pub async fn enforce<T>(
+    &self,
+    task: impl Future<Output = Result<T, DataFusionError>>,
+  ) -> Result<T, QueryError> {
+    self
+      .enforce_until(Instant::now() + self.timeout, task)
+      .await
+  }
+
+  /// enforce for a query that's run a piece at a time, like a streamed one, where `deadline` is
+  /// when the timeout of the whole query is up
+  pub async fn enforce_until<T>(
+    &self,
+    deadline: Instant,
+    task: impl Future<Output = Result<T, DataFusionError>>,
+  ) -> Result<T, QueryError> {
+    // a task that's ready straight away would otherwise finish however late it is
+    if Instant::now() >= deadline {
+      return Err(QueryLimitError::Timeout(self.timeout.as_millis() as u64).into());
+    }
+
+    match tokio::time::timeout_at(deadline, task).await {
+      Err(_) => Err(QueryLimitError::Timeout(self.timeout.as_millis() as u64).into()),
+      Ok(Err(e)) => match e.find_root() {
+        DataFusionError::ResourcesExhausted(msg) => {
+          Err(QueryLimitError::MemoryLimit(msg.clone()).into())
+        }
+        _ => Err(e.into()),
+      },
+      Ok(Ok(value)) => Ok(value),
+    }
+  }
+}
@@ -0,0 +1,260 @@
+use datafusion::arrow::array::RecordBatch;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::json::ArrayWriter;
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use datafusion::physical_plan::SendableRecordBatchStream;
+use datafusion::prelude::SessionContext;
+use futures::StreamExt;
+use napi::bindgen_prelude::Buffer;
+use tokio::sync::{watch, Mutex};
+use tokio::time::Instant;
+
+use crate::timeseries::datafusion_session::{
+  populate_session_with_runtime, register_graph_snapshot, register_legacy_tables,
+};
+use crate::timeseries::errors::QueryError;
+use crate::timeseries::file_metadata::FileMetadata;
+use crate::timeseries::graph_snapshot::GraphSnapshot;
+use crate::timeseries::postgres_table::LegacyTables;
+use crate::timeseries::query_policy::{QueryLimits, QueryPolicy};
+use crate::timeseries::storage_connection::StorageConnection;
+
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct QueryStreamOptions {
+  /// Stop after this many rows. The limit is part of the query plan so DataFusion stops reading the
+  /// files once it has been reached
+  pub row_limit: Option<u32>,
+  /// The same limits processQuery has. The timeout covers the whole stream, from starting the query
+  /// to handing over its last batch. A TimeseriesQueryEngine uses the limits it was created with when
+  /// these are left out
+  pub limits: Option<QueryLimits>,
+  /// Legacy `y_{id}` tables the query can read, the same as processQuery's
+  pub legacy_tables: Option<LegacyTables>,
+  /// The container's graph, queryable as `nodes` and `edges`
+  pub graph_snapshot: Option<GraphSnapshot>,
+}
+
+/// For running a query against a set of files without uploading the results anywhere.
+/// Returns a QueryStream which hands the results back a record batch at a time.
+#[napi]
+pub async fn stream_query(
+  query: String,
+  storage_connection: String,
+  files: Vec<FileMetadata>,
+  options: Option<QueryStreamOptions>,
+) -> napi::Result<QueryStream> {
+  let storage_connection: StorageConnection = storage_connection.parse().map_err(|e| {
+    napi::Error::from_reason(format!(
+      "Failed to parse storage_connection string with reason: {e}"
+    ))
+  })?;
+
+  let options = options.unwrap_or_default();
+  let policy = options.limits.clone().unwrap_or_default().resolve()?;
+
+  let runtime = policy.runtime_env(RuntimeEnvBuilder::new())?;
+  let ctx = populate_session_with_runtime(&storage_connection, files, runtime).await?;
+  register_extra_tables(&ctx, &options).await?;
+
+  stream_results(&ctx, query, options, policy).await
+}
+
+/// Registers the legacy tables and graph snapshot `options` asks for, next to the files
+pub(crate) async fn register_extra_tables(
+  ctx: &SessionContext,
+  options: &QueryStreamOptions,
+) -> Result<(), QueryError> {
+  if let Some(legacy_tables) = &options.legacy_tables {
+    register_legacy_tables(ctx, legacy_tables).await?;
+  }
+  if let Some(graph_snapshot) = &options.graph_snapshot {
+    register_graph_snapshot(ctx, graph_snapshot).await?;
+  }
+
+  Ok(())
+}
+
+/// Starts `query`, which has to be a single `SELECT`, against an already populated session. The
+/// session's runtime has to be the one `policy` built for its memory limit to hold
+pub(crate) async fn stream_results(
+  ctx: &SessionContext,
+  query: String,
+  options: QueryStreamOptions,
+  policy: QueryPolicy,
+) -> napi::Result<QueryStream> {
+  let deadline = Instant::now() + policy.timeout;
+
+  QueryPolicy::check_read_only(query.as_str())?;
+  let mut query_results = ctx
+    .sql_with_options(query.as_str(), QueryPolicy::sql_options())
+    .await
+    .map_err(|e| {
+      napi::Error::from_reason(format!("Failed to run query {query} with reason: {e}"))
+    })?;
+
+  if let Some(limit) = options.row_limit {
+    query_results = query_results.limit(0, Some(limit as usize)).map_err(|e| {
+      napi::Error::from_reason(format!("Failed to limit query {query} with reason: {e}"))
+    })?;
+  }
+
+  let stream = match policy
+    .enforce_until(deadline, query_results.execute_stream())
+    .await
+  {
+    Ok(stream) => stream,
+    Err(e @ QueryError::Limit(_)) => return Err(e.into()),
+    Err(e) => {
+      return Err(napi::Error::from_reason(format!(
+        "Failed to execute query {query} with reason: {e}"
+      )))
+    }
+  };
+
+  let (cancel, _) = watch::channel(false);
+
+  Ok(QueryStream {
+    stream: Mutex::new(Some(stream)),
+    cancel,
+    policy,
+    deadline,
+  })
+}
+
+/// QueryStream pulls record batches from a running query as Javascript asks for them, so only the
+/// batch currently being handed over is held in memory. Both next functions resolve to null once the
+/// results are exhausted or the stream has been cancelled, e.g.
+/// `while ((batch = await stream.nextBatch()) !== null) { ... }`
+#[napi]
+pub struct QueryStream {
+  stream: Mutex<Option<SendableRecordBatchStream>>,
+  cancel: watch::Sender<bool>,
+  policy: QueryPolicy,
+  deadline: Instant,
+}
+
+#[napi]
+impl QueryStream {
+  /// Returns the next record batch encoded as a self-contained Arrow IPC stream (schema and batch),
+  /// which can be read with `tableFromIPC` from the apache-arrow package
+  #[napi]
+  pub async fn next_batch(&self) -> napi::Result<Option<Buffer>> {
+    Ok(self.next_ipc().await?.map(Buffer::from))
+  }
+
+  /// Returns the rows of the next record batch as a stringified JSON array of objects
+  #[napi]
+  pub async fn next_rows(&self) -> napi::Result<Option<String>> {
+    let batch = match self.next().await? {
+      None => return Ok(None),
+      Some(b) => b,
+    };
+
+    let mut writer = ArrayWriter::new(Vec::new());
+
+    writer.write(&batch).map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to write record batch to json with reason: {e}"
+      ))
+    })?;
+
+    writer.finish().map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to finish writing record batch to json with reason: {e}"
+      ))
+    })?;
+
+    let rows = String::from_utf8(writer.into_inner()).map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to get json string from bytes with reason: {e}"
+      ))
+    })?;
+
+    Ok(Some(rows))
+  }
+
+  /// Stops the query. Any pending or later calls to the next functions resolve to null
+  #[napi]
+  pub fn cancel(&self) {
+    self.cancel.send_replace(true);
+
+    // dropping the stream is what stops DataFusion's tasks, if a next call is holding it then it
+    // drops it itself once it sees the cancellation
+    if let Ok(mut stream) = self.stream.try_lock() {
+      stream.take();
+    }
+  }
+
+  pub(crate) async fn next_ipc(&self) -> napi::Result<Option<Vec<u8>>> {
+    let batch = match self.next().await? {
+      None => return Ok(None),
+      Some(b) => b,
+    };
+
+    let mut writer = StreamWriter::try_new(Vec::new(), &batch.schema()).map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to create arrow ipc writer with reason: {e}"
+      ))
+    })?;
+
+    writer.write(&batch).map_err(|e| {
+      napi::Error::from_reason(format!(
+        "Failed to write record batch to arrow ipc with reason: {e}"
+      ))
+    })?;
+
+    writer
+      .into_inner()
+      .map_err(|e| {
+        napi::Error::from_reason(format!(
+          "Failed to finish writing record batch to arrow ipc with reason: {e}"
+        ))
+      })
+      .map(Some)
+  }
+
+  async fn next(&self) -> napi::Result<Option<RecordBatch>> {
+    let mut cancelled = self.cancel.subscribe();
+    let mut guard = self.stream.lock().await;
+
+    loop {
+      let stream = match guard.as_mut() {
+        None => return Ok(None),
+        Some(s) => s,
+      };
+
+      if *cancelled.borrow_and_update() {
+        guard.take();
+        return Ok(None);
+      }
+
+      let batch = tokio::select! {
+        batch = self
+          .policy
+          .enforce_until(self.deadline, async { stream.next().await.transpose() }) => batch,
+        _ = cancelled.changed() => continue,
+      };
+
+      match batch {
+        Ok(None) => {
+          guard.take();
+          return Ok(None);
+        }
+        // a limit or filter can leave empty batches behind, there's no point handing those over
+        Ok(Some(b)) if b.num_rows() == 0 => continue,
+        Ok(Some(b)) => return Ok(Some(b)),
+        Err(e @ QueryError::Limit(_)) => {
+          guard.take();
+          return Err(e.into());
+        }
+        Err(e) => {
+          guard.take();
+          return Err(napi::Error::from_reason(format!(
+            "Failed to read query results with reason: {e}"
+          )));
+        }
+      }
+    }
+  }
+}
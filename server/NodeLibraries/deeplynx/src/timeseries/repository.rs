@@ -1,10 +1,17 @@
 use crate::config::Configuration;
+use crate::timeseries::bucket_tables::{self, BucketColumnChange, BucketTable, BucketTableOptions};
 use crate::timeseries::errors::TimeseriesError;
-use crate::timeseries::ingestion;
+use crate::timeseries::file_metadata::FileMetadata;
+use crate::timeseries::ingestion::{self, LegacyIngestionOptions, RowError};
+use crate::timeseries::json_ingestion;
+use crate::timeseries::parquet_ingestion::{self, ParquetIngestionOptions};
+use crate::timeseries::storage_connection::StorageConnection;
 use sqlx::postgres::PgPool;
+use std::future::Future;
 use std::io::Read;
 use std::sync::Arc;
-use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Clone, Debug)]
 #[napi(object)]
@@ -19,19 +26,39 @@ pub struct LegacyTimeseriesColumn {
   pub data_type: String,
   #[napi(js_name = "date_conversion_format_string")]
   pub date_conversion_format_string: Option<String>,
+  /// IANA name or fixed offset of the timezone a date column's values are in when they don't have an
+  /// offset of their own, e.g. `America/Denver` or `-07:00`. Defaults to UTC
+  #[napi(js_name = "timezone")]
+  pub timezone: Option<String>,
+}
+
+/// What an ingestion reports back once it's complete
+#[napi(object)]
+#[derive(Debug, Default, Clone)]
+pub struct IngestionReport {
+  /// Metadata of the written files, for ingestions that write files
+  pub file_metadata: Option<FileMetadata>,
+  /// How many rows were left out under the error policy of a legacy CSV ingestion
+  pub rows_rejected: i64,
+  /// The first of the rejected rows and why they were rejected
+  pub errors: Vec<RowError>,
 }
 
 #[derive(Clone)]
 pub struct BucketRepository {
   db: PgPool,
-  // this is the channel that we pass data into once the data pipeline has been initiated
-  stream_reader_channel: Option<Arc<tokio::sync::mpsc::UnboundedSender<StreamMessage>>>,
-  // this is how we receive status updates from the reader
-  reader_status_channel:
-    Option<Arc<tokio::sync::RwLock<tokio::sync::mpsc::Receiver<StreamStatusMessage>>>>,
+  buffer_chunks: usize,
+  buffer_bytes: usize,
 }
 
+/// How many chunks can wait in the pipeline when the configuration doesn't say
+const DEFAULT_BUFFER_CHUNKS: usize = 256;
+/// How many bytes can wait in the pipeline when the configuration doesn't say
+const DEFAULT_BUFFER_BYTES: usize = 64 * 1024 * 1024;
+
 /// BucketRepository contains all interactions with Buckets and the database layer of the application.
+/// Ingestions each get their own Ingestion handle, so one repository and its pool can serve any
+/// number of them at once
 impl BucketRepository {
   /// Create a new BucketRepository, the base for all functions related to Buckets and their
   /// manipulation or data ingestion in the database
@@ -44,134 +71,268 @@ impl BucketRepository {
 
     Ok(BucketRepository {
       db,
-      stream_reader_channel: None,
-      reader_status_channel: None,
+      buffer_chunks: config
+        .ingestion_buffer_chunks
+        .map_or(DEFAULT_BUFFER_CHUNKS, |c| c.max(1) as usize),
+      buffer_bytes: config
+        .ingestion_buffer_bytes
+        .map_or(DEFAULT_BUFFER_BYTES, |b| b.max(1) as usize),
     })
   }
 
+  /// `create_bucket` creates the table of a legacy bucket from its columns, see
+  /// bucket_tables::create_bucket_table for how it's partitioned
+  pub async fn create_bucket(
+    &self,
+    data_source_id: String,
+    columns: Vec<LegacyTimeseriesColumn>,
+    options: BucketTableOptions,
+  ) -> Result<BucketTable, TimeseriesError> {
+    bucket_tables::create_bucket_table(&self.db, &data_source_id, &columns, options).await
+  }
+
+  /// `alter_bucket` adds, renames or changes the type of columns of a legacy bucket's table, all of
+  /// the changes are made or none of them are
+  pub async fn alter_bucket(
+    &self,
+    data_source_id: String,
+    changes: Vec<BucketColumnChange>,
+  ) -> Result<(), TimeseriesError> {
+    bucket_tables::alter_bucket_table(&self.db, &data_source_id, changes).await
+  }
+
+  /// `drop_bucket` drops the table of a legacy bucket and all of its data
+  pub async fn drop_bucket(&self, data_source_id: String) -> Result<(), TimeseriesError> {
+    bucket_tables::drop_bucket_table(&self.db, &data_source_id).await
+  }
+
   /// `begin_legacy_csv_ingestion` intializes a data pipeline and prepares it to receive csv data from a node.js
   /// readable stream. We have to do things this way because there is no stream interopt between Rust
   /// and node.js - so we basically spin up a thread to handle ingestion and then stream the data from
-  /// node.js to it
+  /// node.js to it through the returned Ingestion. Rows that can't be ingested are dealt with as the
+  /// options' error policy says, the ones left out are listed in the report the Ingestion completes with
   pub fn begin_legacy_csv_ingestion(
-    &mut self,
+    &self,
     data_source_id: String,
     columns: Vec<LegacyTimeseriesColumn>,
-  ) -> Result<(), TimeseriesError> {
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<StreamMessage>();
-    let (status_tx, status_rx) = tokio::sync::mpsc::channel::<StreamStatusMessage>(4096);
+    options: LegacyIngestionOptions,
+  ) -> Result<Ingestion, TimeseriesError> {
+    // a bad policy or data source id should fail here, not once the data is already coming in
+    ingestion::ErrorPolicy::try_from(&options)?;
+    ingestion::table_name(&data_source_id)?;
     let db_connection = self.db.clone();
 
-    tokio::spawn(async move {
-      let stream_reader = NodeStreamReader::new(rx);
-
-      match ingestion::ingest_csv_legacy(db_connection, stream_reader, data_source_id, columns)
-        .await
-      {
-        Ok(_) => match status_tx.send(StreamStatusMessage::Complete).await {
-          Ok(_) => Ok(()),
-          Err(e) => Err(TimeseriesError::Thread(e.to_string())),
-        },
-        Err(e) => match status_tx.send(StreamStatusMessage::Error(e)).await {
-          Ok(_) => Ok(()),
-          Err(e) => Err(TimeseriesError::Thread(format!(
-            "ingest csv problem: {}",
-            e
-          ))),
-        },
-      }
+    self.begin_ingestion(move |stream_reader| async move {
+      ingestion::ingest_csv_legacy(
+        db_connection,
+        stream_reader,
+        data_source_id,
+        columns,
+        options,
+      )
+      .await
+    })
+  }
+
+  /// `begin_legacy_json_ingestion` is begin_legacy_csv_ingestion for JSON data, either an array of
+  /// records or newline delimited records. The columns' property names are the keys, or paths for
+  /// nested values, of the records' values
+  pub fn begin_legacy_json_ingestion(
+    &self,
+    data_source_id: String,
+    columns: Vec<LegacyTimeseriesColumn>,
+    options: LegacyIngestionOptions,
+  ) -> Result<Ingestion, TimeseriesError> {
+    ingestion::ErrorPolicy::try_from(&options)?;
+    ingestion::table_name(&data_source_id)?;
+    let db_connection = self.db.clone();
+
+    self.begin_ingestion(move |stream_reader| async move {
+      json_ingestion::ingest_json_legacy(
+        db_connection,
+        stream_reader,
+        data_source_id,
+        columns,
+        options,
+      )
+      .await
+    })
+  }
+
+  /// `begin_parquet_ingestion` intializes a data pipeline that receives CSV or JSON data from a node.js
+  /// readable stream and writes it as Parquet files into the storage the connection points to. The
+  /// metadata of the written files is returned when the Ingestion completes so they can be queried
+  pub fn begin_parquet_ingestion(
+    &self,
+    storage_connection: StorageConnection,
+    options: ParquetIngestionOptions,
+  ) -> Result<Ingestion, TimeseriesError> {
+    self.begin_ingestion(move |stream_reader| async move {
+      let metadata =
+        parquet_ingestion::ingest_parquet(&storage_connection, stream_reader, options).await?;
+      Ok(IngestionReport {
+        file_metadata: Some(metadata),
+        ..Default::default()
+      })
+    })
+  }
+
+  /// spins up the thread that runs `ingest` over the data passed in through `read_data` and reports
+  /// back how it went through the returned Ingestion
+  fn begin_ingestion<F, Fut>(&self, ingest: F) -> Result<Ingestion, TimeseriesError>
+  where
+    F: FnOnce(NodeStreamReader) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<IngestionReport, TimeseriesError>> + Send,
+  {
+    let (tx, rx) = tokio::sync::mpsc::channel::<StreamMessage>(self.buffer_chunks);
+    let (status_tx, status_rx) = tokio::sync::watch::channel::<Option<IngestionStatus>>(None);
+    let aborted = CancellationToken::new();
+    let reader_aborted = aborted.clone();
+    let task_aborted = aborted.clone();
+
+    // the reader blocks until Node.js passes in more data, so the ingestion gets a thread of its own
+    // rather than holding up one of the runtime's workers
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+      runtime.block_on(async move {
+        let stream_reader = NodeStreamReader::new(rx, reader_aborted);
+
+        // an abort normally reaches the ingestion through the reader, which lets it clean up after
+        // itself. Dropping the ingestion is for when it's stuck waiting on something other than data
+        let result = tokio::select! {
+          biased;
+          result = ingest(stream_reader) => result,
+          _ = task_aborted.cancelled() => Err(TimeseriesError::Aborted),
+        };
+
+        let status = match result {
+          Err(_) if task_aborted.is_cancelled() => IngestionStatus::Aborted,
+          Ok(report) => IngestionStatus::Complete(report),
+          Err(e) => IngestionStatus::Error(e),
+        };
+        status_tx.send_replace(Some(status));
+      })
     });
 
-    // set that status message receiver so that the complete ingestion can wait on it
-    self.stream_reader_channel = Some(Arc::new(tx));
-    self.reader_status_channel = Some(Arc::new(tokio::sync::RwLock::new(status_rx)));
-    Ok(())
+    Ok(Ingestion {
+      stream_reader_channel: Arc::new(tx),
+      status: status_rx,
+      buffered_bytes: Arc::new(Semaphore::new(self.buffer_bytes)),
+      buffer_bytes: self.buffer_bytes,
+      aborted,
+    })
   }
+}
+
+/// Ingestion is a single data pipeline started by one of the `begin_*_ingestion` functions of
+/// BucketRepository. Data is passed in with `read_data` and the pipeline is finished with either
+/// `complete` or `abort`
+#[derive(Clone)]
+pub struct Ingestion {
+  // this is the channel that we pass data into once the data pipeline has been initiated
+  stream_reader_channel: Arc<tokio::sync::mpsc::Sender<StreamMessage>>,
+  // how the ingestion ended, set once when it does so every call that looks at it sees the same
+  status: tokio::sync::watch::Receiver<Option<IngestionStatus>>,
+  // one permit per byte that hasn't been read yet, they're handed back as the reader gets through it
+  buffered_bytes: Arc<Semaphore>,
+  buffer_bytes: usize,
+  aborted: CancellationToken,
+}
 
+impl Ingestion {
   /// `read_data` is called by the stream to pass data into the previously configured multithreaded
-  /// reader. Call this function regardless of what starting method you called to ingest the data
-  pub fn read_data(&mut self, bytes: Vec<u8>) -> Result<(), TimeseriesError> {
-    let channel = self
-      .reader_status_channel
-      .as_mut()
-      .ok_or(TimeseriesError::Unwrap(
-        "no stream status channel".to_string(),
-      ))?;
-
-    let mut channel = futures::executor::block_on(channel.write());
-
-    match channel.try_recv() {
-      Ok(m) => match m {
-        StreamStatusMessage::Error(e) => return Err(e),
-        StreamStatusMessage::Complete => return Ok(()),
-      },
-      Err(e) => match e {
-        tokio::sync::mpsc::error::TryRecvError::Empty => {}
-        tokio::sync::mpsc::error::TryRecvError::Disconnected => {
-          return Err(TimeseriesError::Thread(
-            "status thread disconnected".to_string(),
-          ))
-        }
-      },
+  /// reader. Call this function regardless of what starting method you called to ingest the data.
+  /// It waits until the pipeline has room for `bytes`, so waiting on it before passing in the next
+  /// chunk keeps a fast stream from getting further ahead of the ingestion than the buffer allows
+  pub async fn read_data(&self, bytes: Vec<u8>) -> Result<(), TimeseriesError> {
+    if self.aborted.is_cancelled() {
+      return Err(TimeseriesError::Aborted);
     }
+    self.check_status()?;
 
-    let channel = self
-      .stream_reader_channel
+    // a chunk bigger than the whole buffer waits for the buffer to empty instead of forever
+    let permits = bytes.len().min(self.buffer_bytes) as u32;
+    let permit = self
+      .buffered_bytes
       .clone()
-      .ok_or(TimeseriesError::Unwrap("no reader channel".to_string()))?;
+      .acquire_many_owned(permits)
+      .await
+      .map_err(|e| TimeseriesError::Thread(e.to_string()))?;
 
-    match channel.send(StreamMessage::Write(bytes)) {
-      Ok(_) => Ok(()),
-      Err(e) => {
-        if channel.send(StreamMessage::Close).is_err() {
-          eprintln!("cannot send close message on stream message channel")
-        }
-        Err(TimeseriesError::Thread(e.to_string()))
-      }
+    if self
+      .stream_reader_channel
+      .send(StreamMessage::Write(bytes, permit))
+      .await
+      .is_err()
+    {
+      // the reader only goes away once the ingestion has stopped, its status says why
+      self.check_status()?;
+      return Err(TimeseriesError::Thread(
+        "ingestion stopped before the data could be read".to_string(),
+      ));
     }
+
+    Ok(())
   }
 
-  /// `complete ingestion` waits for either the first error message or complete status from the
-  /// stream thread - this is how we can let users wait for the ingestion to be completed and how
-  /// we can eventually send data back - this is called regardless of what starting method you called
-  /// to ingest data
-  pub async fn complete_ingestion(&mut self) -> Result<(), TimeseriesError> {
-    let reader_channel = self
-      .stream_reader_channel
-      .as_mut()
-      .ok_or(TimeseriesError::Unwrap(
-        "no stream status channel".to_string(),
-      ))?;
+  /// How many bytes passed in through `read_data` are still waiting to be ingested
+  pub fn buffered_bytes(&self) -> usize {
+    self.buffer_bytes - self.buffered_bytes.available_permits()
+  }
 
-    {
-      match reader_channel.send(StreamMessage::Close) {
-        Ok(_) => {}
-        Err(e) => {
-          return Err(TimeseriesError::Thread(format!(
-            "unable to send close message to reader {}",
-            e
-          )))
-        }
-      }
+  /// returns the error the ingestion stopped with, if it has
+  fn check_status(&self) -> Result<(), TimeseriesError> {
+    match &*self.status.borrow() {
+      None | Some(IngestionStatus::Complete(_)) => Ok(()),
+      Some(status) => status.result().map(|_| ()),
     }
+  }
 
-    let channel = self
-      .reader_status_channel
-      .as_mut()
-      .ok_or(TimeseriesError::Unwrap(
-        "no stream status channel".to_string(),
-      ))?;
+  /// waits for the ingestion to stop and returns how it did
+  async fn stopped(&self) -> Result<IngestionReport, TimeseriesError> {
+    let mut status = self.status.clone();
+    // only fails when the ingestion's thread went away without setting the status
+    let status = status.wait_for(Option::is_some).await.ok();
 
-    let mut channel = channel.write().await;
+    match status.as_deref() {
+      Some(Some(status)) => status.result(),
+      _ => Err(TimeseriesError::Thread(
+        "ingestion stopped without a status".to_string(),
+      )),
+    }
+  }
+
+  /// `complete` waits for either the first error message or complete status from the stream thread -
+  /// this is how we can let users wait for the ingestion to be completed and how we can eventually
+  /// send data back - this is called regardless of what starting method you called to ingest data.
+  /// Resolves to the report of the ingestion, or the error it stopped with
+  pub async fn complete(&self) -> Result<IngestionReport, TimeseriesError> {
+    if self.aborted.is_cancelled() {
+      return Err(TimeseriesError::Aborted);
+    }
+
+    // the reader is only gone once the ingestion has stopped, its status says how
+    let _ = self.stream_reader_channel.send(StreamMessage::Close).await;
+
+    self.stopped().await
+  }
+
+  /// `abort` stops the ingestion without waiting for the rest of the data, whatever it had read so far
+  /// is thrown away - a legacy COPY is aborted so none of its rows are committed. Waits for the
+  /// ingestion to report that it stopped, an ingestion that had already finished is left as it was
+  pub async fn abort(&self) -> Result<(), TimeseriesError> {
+    let finished = self.status.borrow().is_some();
+    self.aborted.cancel();
+    if finished {
+      return Ok(());
+    }
 
-    match channel.recv().await {
-      None => Err(TimeseriesError::Thread(
-        "channel closed before message could be received".to_string(),
+    match self.stopped().await {
+      Ok(_) => Err(TimeseriesError::Thread(
+        "ingestion completed before it could be aborted".to_string(),
       )),
-      Some(m) => match m {
-        StreamStatusMessage::Error(e) => Err(e),
-        StreamStatusMessage::Complete => Ok(()),
-      },
+      // failed on its own before the abort reached it, there's nothing left to throw away
+      Err(_) => Ok(()),
     }
   }
 }
@@ -180,95 +341,114 @@ impl BucketRepository {
 This entire section is our async reader for pulling in from node.js streams. This allows us to
 setup an async reader to pass into things like the CSV parser and allows us to async ingest data
  */
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 enum StreamMessage {
-  Write(Vec<u8>),
+  // the permit counts the bytes against the buffer until the reader has read all of them
+  Write(Vec<u8>, OwnedSemaphorePermit),
   Close,
 }
 
-enum StreamStatusMessage {
+/// How an ingestion ended
+enum IngestionStatus {
   Error(TimeseriesError),
-  Complete,
+  Aborted,
+  Complete(IngestionReport),
+}
+
+impl IngestionStatus {
+  fn result(&self) -> Result<IngestionReport, TimeseriesError> {
+    match self {
+      IngestionStatus::Error(e) => Err(e.duplicate()),
+      IngestionStatus::Aborted => Err(TimeseriesError::Aborted),
+      IngestionStatus::Complete(report) => Ok(report.clone()),
+    }
+  }
 }
 
 pub struct NodeStreamReader {
-  rx: tokio::sync::mpsc::UnboundedReceiver<StreamMessage>,
-  buffer: Vec<u8>,
+  rx: tokio::sync::mpsc::Receiver<StreamMessage>,
+  aborted: CancellationToken,
+  chunk: Option<BufferedChunk>,
   is_closed: bool,
 }
 
+/// A chunk passed in through `read_data` that's only been read part of the way, its permit keeps
+/// counting it against the buffer until all of it has been read
+struct BufferedChunk {
+  bytes: Vec<u8>,
+  read: usize,
+  _permit: OwnedSemaphorePermit,
+}
+
 impl NodeStreamReader {
-  fn new(rx: tokio::sync::mpsc::UnboundedReceiver<StreamMessage>) -> Self {
+  fn new(rx: tokio::sync::mpsc::Receiver<StreamMessage>, aborted: CancellationToken) -> Self {
     NodeStreamReader {
       rx,
-      buffer: vec![],
+      aborted,
+      chunk: None,
       is_closed: false,
     }
   }
 }
 
+impl NodeStreamReader {
+  /// waits for the next message from node.js, None once the sending side is gone
+  fn next_message(&mut self) -> std::io::Result<Option<StreamMessage>> {
+    // unconstrained because the reader never yields back to the runtime, so the budget tokio gives
+    // a task between yields would run out part way through the data
+    futures::executor::block_on(tokio::task::unconstrained(async {
+      tokio::select! {
+        _ = self.aborted.cancelled() => Err(std::io::Error::new(
+          std::io::ErrorKind::ConnectionAborted,
+          "ingestion was aborted",
+        )),
+        message = self.rx.recv() => Ok(message),
+      }
+    }))
+  }
+}
+
 impl Read for NodeStreamReader {
   fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-    if self.is_closed {
-      return Ok(0);
+    if self.aborted.is_cancelled() {
+      return Err(std::io::Error::new(
+        std::io::ErrorKind::ConnectionAborted,
+        "ingestion was aborted",
+      ));
     }
-
-    if self.buffer.len() >= buf.len() {
-      let send = self.buffer.clone();
-      self.buffer = vec![];
-
-      return if send.len() > buf.len() {
-        let (dest, overflow) = send.split_at(buf.len());
-
-        buf.copy_from_slice(dest);
-        self.buffer.extend_from_slice(overflow);
-
-        Ok(dest.len())
-      } else {
-        buf.copy_from_slice(send.as_slice());
-        Ok(buf.len())
-      };
+    if buf.is_empty() {
+      return Ok(0);
     }
 
-    loop {
-      let message = match self.rx.try_recv() {
-        Ok(m) => m,
-        Err(e) => match e {
-          TryRecvError::Empty => continue,
-          TryRecvError::Disconnected => break,
-        },
-      };
-
-      match message {
-        StreamMessage::Write(bytes) => self.buffer.extend_from_slice(bytes.as_slice()),
-        StreamMessage::Close => {
-          self.is_closed = true;
-          buf[..self.buffer.len()].copy_from_slice(self.buffer.as_slice());
-          let len = self.buffer.len();
-          self.buffer = vec![];
-
-          return Ok(len);
+    // only waits on node.js when there's nothing left to hand out, a read doesn't have to fill buf
+    while self.chunk.is_none() && !self.is_closed {
+      match self.next_message()? {
+        None | Some(StreamMessage::Close) => self.is_closed = true,
+        Some(StreamMessage::Write(bytes, permit)) if !bytes.is_empty() => {
+          self.chunk = Some(BufferedChunk {
+            bytes,
+            read: 0,
+            _permit: permit,
+          })
         }
-      }
-
-      if self.buffer.len() >= buf.len() {
-        break;
+        Some(StreamMessage::Write(..)) => {}
       }
     }
 
-    let send = self.buffer.clone();
-    self.buffer = vec![];
-
-    if send.len() > buf.len() {
-      let (dest, overflow) = send.split_at(buf.len());
-
-      buf.copy_from_slice(dest);
-      self.buffer.extend_from_slice(overflow);
-
-      Ok(dest.len())
-    } else {
-      buf.copy_from_slice(send.as_slice());
-      Ok(buf.len())
+    let chunk = match &mut self.chunk {
+      None => return Ok(0),
+      Some(chunk) => chunk,
+    };
+    let rest = &chunk.bytes[chunk.read..];
+    let len = rest.len().min(buf.len());
+    buf[..len].copy_from_slice(&rest[..len]);
+    chunk.read += len;
+
+    // the permit goes back with the chunk once all of it has been read
+    if chunk.read == chunk.bytes.len() {
+      self.chunk = None;
     }
+
+    Ok(len)
   }
 }
@@ -0,0 +1,24 @@
+use object_store::aws::AmazonS3;
+use object_store::aws::AmazonS3Builder;
+
+use crate::timeseries::errors::QueryError;
+use crate::timeseries::storage_connection::S3Connection;
+
+/// Builds an S3 store from a `provider=aws_s3` connection. Plain http is only allowed when the endpoint
+/// asks for it, which S3 compatible services such as MinIO often do on internal networks.
+pub fn register_s3_store(connection: &S3Connection) -> Result<AmazonS3, QueryError> {
+  let mut builder = AmazonS3Builder::new()
+    .with_bucket_name(connection.bucket_name.clone())
+    .with_access_key_id(connection.access_key_id.clone())
+    .with_secret_access_key(connection.secret_access_key.clone())
+    .with_region(connection.region.clone())
+    .with_virtual_hosted_style_request(!connection.path_style);
+
+  if let Some(endpoint) = &connection.endpoint {
+    builder = builder
+      .with_allow_http(endpoint.starts_with("http://"))
+      .with_endpoint(endpoint.clone());
+  }
+
+  Ok(builder.build()?)
+}
@@ -0,0 +1,239 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use connection_string::AdoNetString;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::prelude::SessionContext;
+use object_store::ObjectStore;
+use url::Url;
+
+use crate::timeseries::azure_object_store::register_azure_store;
+use crate::timeseries::errors::QueryError;
+use crate::timeseries::s3_object_store::register_s3_store;
+
+/// StorageConnection is the validated form of the `storage_connection` string passed in from Node.js.
+/// It's parsed once per request so that everything after that (session population, uploading results
+/// and reading back their size) works from the same settings, and a bad connection string is
+/// reported before any query work starts.
+#[derive(Clone, PartialEq)]
+pub enum StorageConnection {
+  Filesystem(FilesystemConnection),
+  AzureBlob(AzureBlobConnection),
+  AwsS3(S3Connection),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct FilesystemConnection {
+  pub root_file_path: String,
+  pub upload_path: Option<String>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct AzureBlobConnection {
+  pub blob_endpoint: String,
+  pub account_name: String,
+  pub container_name: String,
+  pub auth: AzureAuth,
+  pub upload_path: Option<String>,
+}
+
+/// How requests to Azure Blob Storage are authorized. Exactly one of `accountKey`, `sasToken` or
+/// `managedIdentity=true` has to be set in the connection string.
+#[derive(Clone, PartialEq)]
+pub enum AzureAuth {
+  AccountKey(String),
+  /// the token's query string, with or without the leading `?`
+  SasToken(String),
+  /// the identity assigned to the host, `managedIdentityClientId` picks a user assigned identity
+  ManagedIdentity {
+    client_id: Option<String>,
+  },
+}
+
+#[derive(Clone, PartialEq)]
+pub struct S3Connection {
+  /// only needed for S3 compatible services such as MinIO
+  pub endpoint: Option<String>,
+  pub bucket_name: String,
+  pub access_key_id: String,
+  pub secret_access_key: String,
+  pub region: String,
+  /// `{endpoint}/{bucket}/{key}` requests rather than `{bucket}.{endpoint}/{key}`, defaults to true
+  pub path_style: bool,
+  pub upload_path: Option<String>,
+}
+
+impl FromStr for StorageConnection {
+  type Err = QueryError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let connection: AdoNetString = s.parse()?;
+    let provider =
+      connection
+        .get("provider")
+        .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
+          msg: "provider is not set in connection string".to_string(),
+        })?;
+
+    let required = |key: &str, name: &str| {
+      connection
+        .get(key)
+        .cloned()
+        .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
+          msg: format!("{name} is not set in connection string with provider: {provider}"),
+        })
+    };
+    let upload_path = connection.get("uploadpath").cloned();
+
+    match provider.as_str() {
+      "filesystem" => Ok(StorageConnection::Filesystem(FilesystemConnection {
+        root_file_path: required("rootfilepath", "rootFilePath")?,
+        upload_path,
+      })),
+      "azure_blob" => {
+        let blob_endpoint = required("blobendpoint", "blobEndpoint")?;
+        Url::parse(blob_endpoint.as_str())?;
+
+        Ok(StorageConnection::AzureBlob(AzureBlobConnection {
+          blob_endpoint,
+          account_name: required("accountname", "accountName")?,
+          container_name: required("containername", "containerName")?,
+          auth: azure_auth(&connection)?,
+          upload_path,
+        }))
+      }
+      "aws_s3" => {
+        let endpoint = connection.get("endpoint").cloned();
+        if let Some(endpoint) = &endpoint {
+          Url::parse(endpoint.as_str())?;
+        }
+
+        Ok(StorageConnection::AwsS3(S3Connection {
+          endpoint,
+          bucket_name: required("bucketname", "bucketName")?,
+          access_key_id: required("accesskeyid", "accessKeyId")?,
+          secret_access_key: required("secretaccesskey", "secretAccessKey")?,
+          region: connection
+            .get("region")
+            .cloned()
+            .unwrap_or("us-east-1".to_string()),
+          path_style: flag(&connection, "pathstyle", "pathStyle")?.unwrap_or(true),
+          upload_path,
+        }))
+      }
+      _ => Err(QueryError::BadData(format!(
+        "{provider} is not supported as a storage provider, expected one of filesystem, azure_blob or aws_s3"
+      ))),
+    }
+  }
+}
+
+impl StorageConnection {
+  pub fn provider(&self) -> &'static str {
+    match self {
+      StorageConnection::Filesystem(_) => "filesystem",
+      StorageConnection::AzureBlob(_) => "azure_blob",
+      StorageConnection::AwsS3(_) => "aws_s3",
+    }
+  }
+
+  /// The location every file path is relative to. For the object stores this is also the url
+  /// DataFusion resolves the store by
+  pub fn root_url(&self) -> String {
+    match self {
+      StorageConnection::Filesystem(c) => c.root_file_path.clone(),
+      StorageConnection::AzureBlob(c) => c.blob_endpoint.clone(),
+      StorageConnection::AwsS3(c) => format!("s3://{}", c.bucket_name),
+    }
+  }
+
+  pub fn upload_path(&self) -> Result<&str, QueryError> {
+    let upload_path = match self {
+      StorageConnection::Filesystem(c) => &c.upload_path,
+      StorageConnection::AzureBlob(c) => &c.upload_path,
+      StorageConnection::AwsS3(c) => &c.upload_path,
+    };
+
+    upload_path
+      .as_deref()
+      .ok_or_else(|| QueryError::ReceivedNullDataInRequest {
+        msg: format!(
+          "uploadPath is not set in connection string with provider: {}",
+          self.provider()
+        ),
+      })
+  }
+
+  /// Full path of the folder query results are uploaded to, ending in a `/`
+  pub fn upload_root(&self) -> Result<String, QueryError> {
+    Ok(format!(
+      "{}/{}/",
+      self.root_url().trim_end_matches('/'),
+      self.upload_path()?
+    ))
+  }
+
+  /// The object store files are read from and results written to, registered with DataFusion under
+  /// `root_url`. The filesystem doesn't need one, DataFusion already handles local paths
+  pub fn object_store(&self) -> Result<Option<Arc<dyn ObjectStore>>, QueryError> {
+    match self {
+      StorageConnection::Filesystem(_) => Ok(None),
+      StorageConnection::AzureBlob(c) => Ok(Some(Arc::new(register_azure_store(c)?))),
+      StorageConnection::AwsS3(c) => Ok(Some(Arc::new(register_s3_store(c)?))),
+    }
+  }
+}
+
+/// Size in bytes of a file written through a session, whichever store the session put it in
+pub async fn file_size(ctx: &SessionContext, path: &str) -> Result<u64, QueryError> {
+  let url = ListingTableUrl::parse(path)?;
+  let store = ctx.runtime_env().object_store(&url)?;
+
+  Ok(store.head(url.prefix()).await?.size)
+}
+
+/// Deletes a file written through a session, whichever store the session put it in
+pub async fn remove_file(ctx: &SessionContext, path: &str) -> Result<(), QueryError> {
+  let url = ListingTableUrl::parse(path)?;
+  let store = ctx.runtime_env().object_store(&url)?;
+
+  Ok(store.delete(url.prefix()).await?)
+}
+
+fn azure_auth(connection: &AdoNetString) -> Result<AzureAuth, QueryError> {
+  let client_id = connection.get("managedidentityclientid").cloned();
+  let managed_identity =
+    flag(connection, "managedidentity", "managedIdentity")?.unwrap_or(client_id.is_some());
+
+  let mut auth = vec![];
+  if let Some(key) = connection.get("accountkey") {
+    auth.push(AzureAuth::AccountKey(key.clone()));
+  }
+  if let Some(token) = connection.get("sastoken") {
+    auth.push(AzureAuth::SasToken(token.clone()));
+  }
+  if managed_identity {
+    auth.push(AzureAuth::ManagedIdentity { client_id });
+  }
+
+  match auth.len() {
+    0 => Err(QueryError::ReceivedNullDataInRequest {
+      msg: "one of accountKey, sasToken or managedIdentity must be set in connection string with provider: azure_blob".to_string(),
+    }),
+    1 => Ok(auth.remove(0)),
+    _ => Err(QueryError::BadData(
+      "only one of accountKey, sasToken or managedIdentity can be set in connection string with provider: azure_blob".to_string(),
+    )),
+  }
+}
+
+fn flag(connection: &AdoNetString, key: &str, name: &str) -> Result<Option<bool>, QueryError> {
+  match connection.get(key) {
+    None => Ok(None),
+    Some(v) if v.eq_ignore_ascii_case("true") => Ok(Some(true)),
+    Some(v) if v.eq_ignore_ascii_case("false") => Ok(Some(false)),
+    Some(v) => Err(QueryError::BadData(format!(
+      "{name} must be true or false in connection string, got {v}"
+    ))),
+  }
+}
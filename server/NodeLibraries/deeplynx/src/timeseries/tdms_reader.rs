@@ -0,0 +1,729 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::DateTime;
+use datafusion::arrow::array::{
+  ArrayRef, ArrowPrimitiveType, BooleanBuilder, PrimitiveBuilder, StringBuilder,
+};
+use datafusion::arrow::datatypes::{
+  DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, TimeUnit,
+  TimestampNanosecondType, UInt16Type, UInt32Type, UInt64Type, UInt8Type,
+};
+
+use crate::timeseries::errors::QueryError;
+
+/*
+This is a reader for the NI TDMS 2.0 file format (https://www.ni.com/en/support/documentation/supplemental/07/tdms-file-format-internal-structure.html).
+A TDMS file is a list of segments, each with a lead in, optional metadata describing the objects
+(root, groups and channels) and their properties, and optional raw data for the channels. Parsing
+only walks the lead ins and metadata to build an index of where each channel's values live, the
+values themselves are decoded later and only for the channels a query asks for.
+ */
+const LEAD_IN_LEN: usize = 28;
+const TOC_META_DATA: u32 = 1 << 1;
+const TOC_NEW_OBJ_LIST: u32 = 1 << 2;
+const TOC_RAW_DATA: u32 = 1 << 3;
+const TOC_INTERLEAVED_DATA: u32 = 1 << 5;
+const TOC_BIG_ENDIAN: u32 = 1 << 6;
+const TOC_DAQMX_RAW_DATA: u32 = 1 << 7;
+
+const NO_RAW_DATA: u32 = 0xFFFFFFFF;
+const RAW_DATA_SAME_AS_PREVIOUS: u32 = 0x00000000;
+const INCOMPLETE_SEGMENT: u64 = 0xFFFFFFFFFFFFFFFF;
+
+// seconds between the TDMS epoch (1904-01-01 UTC) and the unix epoch
+const TDMS_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TdmsDataType {
+  Void,
+  I8,
+  I16,
+  I32,
+  I64,
+  U8,
+  U16,
+  U32,
+  U64,
+  SingleFloat,
+  DoubleFloat,
+  String,
+  Boolean,
+  TimeStamp,
+}
+
+impl TdmsDataType {
+  fn from_code(code: u32) -> Result<Self, QueryError> {
+    match code {
+      0x00 => Ok(TdmsDataType::Void),
+      0x01 => Ok(TdmsDataType::I8),
+      0x02 => Ok(TdmsDataType::I16),
+      0x03 => Ok(TdmsDataType::I32),
+      0x04 => Ok(TdmsDataType::I64),
+      0x05 => Ok(TdmsDataType::U8),
+      0x06 => Ok(TdmsDataType::U16),
+      0x07 => Ok(TdmsDataType::U32),
+      0x08 => Ok(TdmsDataType::U64),
+      // the "with unit" float variants are stored exactly like the plain ones
+      0x09 | 0x19 => Ok(TdmsDataType::SingleFloat),
+      0x0A | 0x1A => Ok(TdmsDataType::DoubleFloat),
+      0x20 => Ok(TdmsDataType::String),
+      0x21 => Ok(TdmsDataType::Boolean),
+      0x44 => Ok(TdmsDataType::TimeStamp),
+      _ => Err(QueryError::Tdms(format!(
+        "data type {code:#x} is not supported"
+      ))),
+    }
+  }
+
+  // the width of a single value, strings are the only variable width type
+  fn size(&self) -> Option<usize> {
+    match self {
+      TdmsDataType::Void => Some(0),
+      TdmsDataType::I8 | TdmsDataType::U8 | TdmsDataType::Boolean => Some(1),
+      TdmsDataType::I16 | TdmsDataType::U16 => Some(2),
+      TdmsDataType::I32 | TdmsDataType::U32 | TdmsDataType::SingleFloat => Some(4),
+      TdmsDataType::I64 | TdmsDataType::U64 | TdmsDataType::DoubleFloat => Some(8),
+      TdmsDataType::TimeStamp => Some(16),
+      TdmsDataType::String => None,
+    }
+  }
+
+  pub fn arrow_type(&self) -> Option<DataType> {
+    match self {
+      TdmsDataType::Void => None,
+      TdmsDataType::I8 => Some(DataType::Int8),
+      TdmsDataType::I16 => Some(DataType::Int16),
+      TdmsDataType::I32 => Some(DataType::Int32),
+      TdmsDataType::I64 => Some(DataType::Int64),
+      TdmsDataType::U8 => Some(DataType::UInt8),
+      TdmsDataType::U16 => Some(DataType::UInt16),
+      TdmsDataType::U32 => Some(DataType::UInt32),
+      TdmsDataType::U64 => Some(DataType::UInt64),
+      TdmsDataType::SingleFloat => Some(DataType::Float32),
+      TdmsDataType::DoubleFloat => Some(DataType::Float64),
+      TdmsDataType::String => Some(DataType::Utf8),
+      TdmsDataType::Boolean => Some(DataType::Boolean),
+      TdmsDataType::TimeStamp => Some(DataType::Timestamp(
+        TimeUnit::Nanosecond,
+        Some("UTC".into()),
+      )),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct TdmsFile {
+  pub properties: Vec<(String, String)>,
+  pub groups: Vec<TdmsGroup>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TdmsGroup {
+  pub name: String,
+  pub properties: Vec<(String, String)>,
+  pub channels: Vec<TdmsChannel>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TdmsChannel {
+  pub name: String,
+  pub properties: Vec<(String, String)>,
+  pub data_type: TdmsDataType,
+  pub len: usize,
+  chunks: Vec<DataChunk>,
+}
+
+// a run of values for a single channel, value `i` of the run starts at `offset + i * stride`
+#[derive(Debug, Clone)]
+struct DataChunk {
+  offset: usize,
+  count: usize,
+  stride: usize,
+  big_endian: bool,
+}
+
+// the raw data index of an object, how many values (and bytes) it writes into each chunk
+#[derive(Debug, Clone)]
+struct RawDataLayout {
+  data_type: TdmsDataType,
+  count: usize,
+  bytes: usize,
+}
+
+#[derive(Debug)]
+struct TdmsObject {
+  path: String,
+  properties: Vec<(String, String)>,
+  layout: Option<RawDataLayout>,
+  has_data: bool,
+  chunks: Vec<DataChunk>,
+}
+
+impl TdmsObject {
+  fn set_property(&mut self, name: String, value: String) {
+    match self.properties.iter_mut().find(|(n, _)| *n == name) {
+      None => self.properties.push((name, value)),
+      Some(existing) => existing.1 = value,
+    }
+  }
+}
+
+impl TdmsFile {
+  pub fn parse(bytes: &[u8]) -> Result<Self, QueryError> {
+    let mut objects: Vec<TdmsObject> = vec![];
+    let mut lookup: HashMap<String, usize> = HashMap::new();
+    // the objects, in order, that have their data written in the current segment
+    let mut segment_objects: Vec<usize> = vec![];
+
+    let mut position = 0;
+    while position + LEAD_IN_LEN <= bytes.len() {
+      let lead_in = &bytes[position..position + LEAD_IN_LEN];
+      if &lead_in[0..4] != b"TDSm" {
+        return Err(QueryError::Tdms(format!(
+          "missing segment tag at byte {position}"
+        )));
+      }
+
+      // the table of contents is always little endian, it tells us the endianness of everything else
+      let toc = u32::from_bytes(&lead_in[4..8], false);
+      let big_endian = toc & TOC_BIG_ENDIAN != 0;
+      let next_segment_offset = u64::from_bytes(&lead_in[12..20], big_endian);
+      let raw_data_offset = u64::from_bytes(&lead_in[20..28], big_endian) as usize;
+
+      if toc & TOC_DAQMX_RAW_DATA != 0 {
+        return Err(QueryError::Tdms(
+          "DAQmx raw data is not supported".to_string(),
+        ));
+      }
+
+      let metadata_start = position + LEAD_IN_LEN;
+      // a crashed writer leaves the last segment's offset unset, its data runs to the end of the file
+      let segment_end = match next_segment_offset {
+        INCOMPLETE_SEGMENT => bytes.len(),
+        offset => (metadata_start as u64)
+          .saturating_add(offset)
+          .min(bytes.len() as u64) as usize,
+      };
+      let data_start = metadata_start.saturating_add(raw_data_offset);
+      if data_start > segment_end {
+        return Err(QueryError::Tdms(format!(
+          "segment at byte {position} has a raw data offset past the end of the segment"
+        )));
+      }
+
+      if toc & TOC_META_DATA != 0 {
+        if toc & TOC_NEW_OBJ_LIST != 0 {
+          segment_objects.clear();
+        }
+
+        let mut cursor = Cursor {
+          bytes: &bytes[metadata_start..data_start],
+          position: 0,
+          big_endian,
+        };
+
+        let object_count = cursor.read::<u32>()?;
+        for _ in 0..object_count {
+          let path = cursor.read_string()?;
+          let index = match lookup.get(&path) {
+            Some(i) => *i,
+            None => {
+              objects.push(TdmsObject {
+                path: path.clone(),
+                properties: vec![],
+                layout: None,
+                has_data: false,
+                chunks: vec![],
+              });
+              lookup.insert(path, objects.len() - 1);
+              objects.len() - 1
+            }
+          };
+          let object = &mut objects[index];
+
+          match cursor.read::<u32>()? {
+            NO_RAW_DATA => object.has_data = false,
+            RAW_DATA_SAME_AS_PREVIOUS => {
+              if object.layout.is_none() {
+                return Err(QueryError::Tdms(format!(
+                  "{} reuses a raw data index it never had",
+                  object.path
+                )));
+              }
+              object.has_data = true;
+            }
+            0x1269 | 0x126A | 0x1369 | 0x136A => {
+              return Err(QueryError::Tdms(
+                "DAQmx raw data is not supported".to_string(),
+              ))
+            }
+            _ => {
+              let data_type = TdmsDataType::from_code(cursor.read::<u32>()?)?;
+              let _dimension = cursor.read::<u32>()?;
+              let count = cursor.read::<u64>()? as usize;
+              let bytes = match data_type.size() {
+                Some(size) => count.checked_mul(size).ok_or_else(|| {
+                  QueryError::Tdms(format!(
+                    "{} has more values than can be addressed",
+                    object.path
+                  ))
+                })?,
+                None => cursor.read::<u64>()? as usize,
+              };
+
+              if let Some(previous) = &object.layout {
+                if previous.data_type != data_type {
+                  return Err(QueryError::Tdms(format!(
+                    "{} changes data type between segments",
+                    object.path
+                  )));
+                }
+              }
+
+              object.layout = Some(RawDataLayout {
+                data_type,
+                count,
+                bytes,
+              });
+              object.has_data = true;
+            }
+          }
+
+          let property_count = cursor.read::<u32>()?;
+          for _ in 0..property_count {
+            let name = cursor.read_string()?;
+            let value = cursor.read_property()?;
+            object.set_property(name, value);
+          }
+
+          if !segment_objects.contains(&index) {
+            segment_objects.push(index);
+          }
+        }
+      }
+
+      if toc & TOC_RAW_DATA != 0 {
+        index_raw_data(
+          &mut objects,
+          &segment_objects,
+          data_start,
+          segment_end,
+          toc & TOC_INTERLEAVED_DATA != 0,
+          big_endian,
+        )?;
+      }
+
+      if next_segment_offset == INCOMPLETE_SEGMENT {
+        break;
+      }
+      position = segment_end;
+    }
+
+    Ok(build_hierarchy(objects))
+  }
+}
+
+fn index_raw_data(
+  objects: &mut [TdmsObject],
+  segment_objects: &[usize],
+  data_start: usize,
+  segment_end: usize,
+  interleaved: bool,
+  big_endian: bool,
+) -> Result<(), QueryError> {
+  let data_objects: Vec<usize> = segment_objects
+    .iter()
+    .filter(|i| objects[**i].has_data)
+    .copied()
+    .collect();
+
+  let mut layouts = vec![];
+  for i in &data_objects {
+    let layout = objects[*i].layout.clone().ok_or_else(|| {
+      QueryError::Tdms(format!(
+        "{} has data but no raw data index",
+        objects[*i].path
+      ))
+    })?;
+    layouts.push(layout);
+  }
+
+  if interleaved {
+    // interleaved data is written a row at a time, one value from each channel per row
+    let mut row_size = 0;
+    for (i, layout) in data_objects.iter().zip(layouts.iter()) {
+      row_size += layout.data_type.size().ok_or_else(|| {
+        QueryError::Tdms(format!(
+          "{} is a string channel in interleaved data",
+          objects[*i].path
+        ))
+      })?;
+    }
+    if row_size == 0 {
+      return Ok(());
+    }
+
+    let rows = (segment_end - data_start) / row_size;
+    let mut offset = data_start;
+    for (i, layout) in data_objects.iter().zip(layouts.iter()) {
+      objects[*i].chunks.push(DataChunk {
+        offset,
+        count: rows,
+        stride: row_size,
+        big_endian,
+      });
+      offset += layout.data_type.size().unwrap_or(0);
+    }
+
+    return Ok(());
+  }
+
+  // contiguous data repeats the same chunk layout until the end of the segment
+  let chunk_size = layouts
+    .iter()
+    .try_fold(0_usize, |size, l| size.checked_add(l.bytes))
+    .ok_or_else(|| {
+      QueryError::Tdms("raw data chunk is larger than can be addressed".to_string())
+    })?;
+  if chunk_size == 0 {
+    return Ok(());
+  }
+
+  let mut chunk_start = data_start;
+  while chunk_start < segment_end {
+    let mut offset = chunk_start;
+    for (i, layout) in data_objects.iter().zip(layouts.iter()) {
+      let available = segment_end.saturating_sub(offset);
+      let count = match layout.data_type.size() {
+        // the last chunk of a crashed writer may be cut short
+        Some(size) if size > 0 => layout.count.min(available / size),
+        Some(_) => 0,
+        None if layout.bytes <= available => layout.count,
+        None => 0,
+      };
+
+      if count > 0 {
+        objects[*i].chunks.push(DataChunk {
+          offset,
+          count,
+          stride: layout.data_type.size().unwrap_or(0),
+          big_endian,
+        });
+      }
+      offset = offset.saturating_add(layout.bytes);
+    }
+    chunk_start = chunk_start.saturating_add(chunk_size);
+  }
+
+  Ok(())
+}
+
+// turns the flat list of object paths into the root, group and channel hierarchy
+fn build_hierarchy(objects: Vec<TdmsObject>) -> TdmsFile {
+  let mut file = TdmsFile {
+    properties: vec![],
+    groups: vec![],
+  };
+
+  for object in objects {
+    let names = split_path(&object.path);
+    match names.as_slice() {
+      [] => file.properties = object.properties,
+      [group] => {
+        let group = find_group(&mut file.groups, group);
+        group.properties = object.properties;
+      }
+      [group, channel, ..] => {
+        let layout = match object.layout {
+          None => continue, // a channel that never wrote any data
+          Some(l) => l,
+        };
+
+        let group = find_group(&mut file.groups, group);
+        group.channels.push(TdmsChannel {
+          name: channel.clone(),
+          properties: object.properties,
+          data_type: layout.data_type,
+          len: object.chunks.iter().map(|c| c.count).sum(),
+          chunks: object.chunks,
+        });
+      }
+    }
+  }
+
+  file
+}
+
+fn find_group<'a>(groups: &'a mut Vec<TdmsGroup>, name: &str) -> &'a mut TdmsGroup {
+  let index = match groups.iter().position(|g| g.name == name) {
+    Some(i) => i,
+    None => {
+      groups.push(TdmsGroup {
+        name: name.to_string(),
+        properties: vec![],
+        channels: vec![],
+      });
+      groups.len() - 1
+    }
+  };
+
+  &mut groups[index]
+}
+
+// object paths look like /'group'/'channel' with single quotes in names escaped as ''
+fn split_path(path: &str) -> Vec<String> {
+  let mut names = vec![];
+  let mut current = String::new();
+  let mut in_name = false;
+  let mut chars = path.chars().peekable();
+
+  while let Some(c) = chars.next() {
+    match (c, in_name) {
+      ('\'', true) if chars.peek() == Some(&'\'') => {
+        current.push('\'');
+        chars.next();
+      }
+      ('\'', true) => {
+        names.push(std::mem::take(&mut current));
+        in_name = false;
+      }
+      ('\'', false) => in_name = true,
+      (c, true) => current.push(c),
+      _ => {}
+    }
+  }
+
+  names
+}
+
+/// Decodes up to `limit` values of a channel into an Arrow array
+pub fn read_channel(
+  bytes: &[u8],
+  channel: &TdmsChannel,
+  limit: usize,
+) -> Result<ArrayRef, QueryError> {
+  let array = match channel.data_type {
+    TdmsDataType::I8 => read_primitive::<Int8Type>(bytes, channel, limit)?,
+    TdmsDataType::I16 => read_primitive::<Int16Type>(bytes, channel, limit)?,
+    TdmsDataType::I32 => read_primitive::<Int32Type>(bytes, channel, limit)?,
+    TdmsDataType::I64 => read_primitive::<Int64Type>(bytes, channel, limit)?,
+    TdmsDataType::U8 => read_primitive::<UInt8Type>(bytes, channel, limit)?,
+    TdmsDataType::U16 => read_primitive::<UInt16Type>(bytes, channel, limit)?,
+    TdmsDataType::U32 => read_primitive::<UInt32Type>(bytes, channel, limit)?,
+    TdmsDataType::U64 => read_primitive::<UInt64Type>(bytes, channel, limit)?,
+    TdmsDataType::SingleFloat => read_primitive::<Float32Type>(bytes, channel, limit)?,
+    TdmsDataType::DoubleFloat => read_primitive::<Float64Type>(bytes, channel, limit)?,
+    TdmsDataType::Boolean => {
+      let mut builder = BooleanBuilder::with_capacity(limit.min(channel.len));
+      for_each_value(bytes, channel, limit, 1, |value, _| {
+        builder.append_value(value[0] != 0)
+      })?;
+      Arc::new(builder.finish()) as ArrayRef
+    }
+    TdmsDataType::TimeStamp => {
+      let mut builder =
+        PrimitiveBuilder::<TimestampNanosecondType>::with_capacity(limit.min(channel.len));
+      for_each_value(bytes, channel, limit, 16, |value, big_endian| {
+        builder.append_value(timestamp_nanos(value, big_endian))
+      })?;
+      Arc::new(builder.finish().with_timezone("UTC")) as ArrayRef
+    }
+    TdmsDataType::String => Arc::new(read_strings(bytes, channel, limit)?) as ArrayRef,
+    TdmsDataType::Void => {
+      return Err(QueryError::Tdms(format!(
+        "channel {} has no values",
+        channel.name
+      )))
+    }
+  };
+
+  Ok(array)
+}
+
+fn read_primitive<T>(
+  bytes: &[u8],
+  channel: &TdmsChannel,
+  limit: usize,
+) -> Result<ArrayRef, QueryError>
+where
+  T: ArrowPrimitiveType,
+  T::Native: TdmsNative,
+{
+  let mut builder = PrimitiveBuilder::<T>::with_capacity(limit.min(channel.len));
+  for_each_value(
+    bytes,
+    channel,
+    limit,
+    T::Native::SIZE,
+    |value, big_endian| builder.append_value(T::Native::from_bytes(value, big_endian)),
+  )?;
+
+  Ok(Arc::new(builder.finish()))
+}
+
+fn for_each_value<F: FnMut(&[u8], bool)>(
+  bytes: &[u8],
+  channel: &TdmsChannel,
+  limit: usize,
+  size: usize,
+  mut f: F,
+) -> Result<(), QueryError> {
+  let mut read = 0;
+  for chunk in &channel.chunks {
+    for i in 0..chunk.count {
+      if read == limit {
+        return Ok(());
+      }
+
+      let start = chunk.offset + i * chunk.stride;
+      let value = bytes
+        .get(start..start + size)
+        .ok_or_else(|| QueryError::Tdms(format!("channel {} is truncated", channel.name)))?;
+      f(value, chunk.big_endian);
+      read += 1;
+    }
+  }
+
+  Ok(())
+}
+
+// string chunks hold an array of end offsets, one per value, followed by the concatenated strings
+fn read_strings(
+  bytes: &[u8],
+  channel: &TdmsChannel,
+  limit: usize,
+) -> Result<datafusion::arrow::array::StringArray, QueryError> {
+  let truncated = || QueryError::Tdms(format!("channel {} is truncated", channel.name));
+  let mut builder = StringBuilder::new();
+  let mut read = 0;
+
+  for chunk in &channel.chunks {
+    let strings_start = chunk
+      .count
+      .checked_mul(4)
+      .and_then(|offsets| offsets.checked_add(chunk.offset))
+      .ok_or_else(truncated)?;
+    let mut start = 0;
+
+    for i in 0..chunk.count {
+      if read == limit {
+        return Ok(builder.finish());
+      }
+
+      let offset_at = chunk.offset + i * 4;
+      let end = u32::from_bytes(
+        bytes.get(offset_at..offset_at + 4).ok_or_else(truncated)?,
+        chunk.big_endian,
+      ) as usize;
+      let value = bytes
+        .get(strings_start.saturating_add(start)..strings_start.saturating_add(end))
+        .ok_or_else(truncated)?;
+
+      builder.append_value(String::from_utf8_lossy(value));
+      start = end;
+      read += 1;
+    }
+  }
+
+  Ok(builder.finish())
+}
+
+// timestamps are a signed count of seconds since 1904 plus an unsigned count of 2^-64 fractions
+fn timestamp_nanos(value: &[u8], big_endian: bool) -> i64 {
+  let (seconds, fractions) = match big_endian {
+    true => (
+      i64::from_bytes(&value[0..8], true),
+      u64::from_bytes(&value[8..16], true),
+    ),
+    false => (
+      i64::from_bytes(&value[8..16], false),
+      u64::from_bytes(&value[0..8], false),
+    ),
+  };
+
+  // round to the nearest nanosecond, writers rarely produce fractions that land exactly on one
+  let nanos = ((fractions as u128 * 1_000_000_000 + (1 << 63)) >> 64) as i64;
+  seconds
+    .saturating_sub(TDMS_EPOCH_OFFSET)
+    .saturating_mul(1_000_000_000)
+    .saturating_add(nanos)
+}
+
+struct Cursor<'a> {
+  bytes: &'a [u8],
+  position: usize,
+  big_endian: bool,
+}
+
+impl<'a> Cursor<'a> {
+  fn take(&mut self, len: usize) -> Result<&'a [u8], QueryError> {
+    let slice = self
+      .bytes
+      .get(self.position..self.position + len)
+      .ok_or_else(|| QueryError::Tdms("metadata is truncated".to_string()))?;
+    self.position += len;
+    Ok(slice)
+  }
+
+  fn read<T: TdmsNative>(&mut self) -> Result<T, QueryError> {
+    let big_endian = self.big_endian;
+    Ok(T::from_bytes(self.take(T::SIZE)?, big_endian))
+  }
+
+  fn read_string(&mut self) -> Result<String, QueryError> {
+    let len = self.read::<u32>()? as usize;
+    Ok(String::from_utf8_lossy(self.take(len)?).to_string())
+  }
+
+  // property values are kept as strings, they end up as Arrow schema metadata
+  fn read_property(&mut self) -> Result<String, QueryError> {
+    let value = match TdmsDataType::from_code(self.read::<u32>()?)? {
+      TdmsDataType::Void => String::new(),
+      TdmsDataType::I8 => self.read::<i8>()?.to_string(),
+      TdmsDataType::I16 => self.read::<i16>()?.to_string(),
+      TdmsDataType::I32 => self.read::<i32>()?.to_string(),
+      TdmsDataType::I64 => self.read::<i64>()?.to_string(),
+      TdmsDataType::U8 => self.read::<u8>()?.to_string(),
+      TdmsDataType::U16 => self.read::<u16>()?.to_string(),
+      TdmsDataType::U32 => self.read::<u32>()?.to_string(),
+      TdmsDataType::U64 => self.read::<u64>()?.to_string(),
+      TdmsDataType::SingleFloat => self.read::<f32>()?.to_string(),
+      TdmsDataType::DoubleFloat => self.read::<f64>()?.to_string(),
+      TdmsDataType::String => self.read_string()?,
+      TdmsDataType::Boolean => (self.take(1)?[0] != 0).to_string(),
+      TdmsDataType::TimeStamp => {
+        let big_endian = self.big_endian;
+        DateTime::from_timestamp_nanos(timestamp_nanos(self.take(16)?, big_endian)).to_rfc3339()
+      }
+    };
+
+    Ok(value)
+  }
+}
+
+trait TdmsNative: Sized {
+  const SIZE: usize;
+  fn from_bytes(bytes: &[u8], big_endian: bool) -> Self;
+}
+
+macro_rules! tdms_native {
+  ($($t:ty),*) => {
+    $(
+      impl TdmsNative for $t {
+        const SIZE: usize = std::mem::size_of::<$t>();
+
+        fn from_bytes(bytes: &[u8], big_endian: bool) -> Self {
+          let mut raw = [0u8; std::mem::size_of::<$t>()];
+          raw.copy_from_slice(&bytes[..Self::SIZE]);
+          match big_endian {
+            true => <$t>::from_be_bytes(raw),
+            false => <$t>::from_le_bytes(raw),
+          }
+        }
+      }
+    )*
+  };
+}
+
+tdms_native!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
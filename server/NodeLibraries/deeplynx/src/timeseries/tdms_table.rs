@@ -0,0 +1,205 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use datafusion::arrow::array::{new_null_array, ArrayRef};
+use datafusion::arrow::compute::concat;
+use datafusion::arrow::datatypes::{Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::DataFusionError;
+use datafusion::execution::memory_pool::{MemoryConsumer, MemoryReservation};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::StreamExt;
+
+use crate::timeseries::datafusion_session::{record_batch, BATCH_SIZE};
+use crate::timeseries::errors::QueryError;
+use crate::timeseries::tdms_reader::{read_channel, TdmsChannel, TdmsFile};
+
+/// TdmsTableProvider exposes every channel of a TDMS file as a column. Files with a single group use
+/// the channel names as column names, files with more than one group use `{group}/{channel}` so that
+/// channels with the same name in different groups don't collide. Channel properties are attached to
+/// their field's metadata, file and group properties to the schema's.
+#[derive(Debug)]
+pub struct TdmsTableProvider {
+  bytes: Bytes,
+  schema: SchemaRef,
+  channels: Vec<TdmsChannel>,
+  num_rows: usize,
+}
+
+impl TdmsTableProvider {
+  pub fn try_new(bytes: Bytes) -> Result<Self, QueryError> {
+    let file = TdmsFile::parse(&bytes)?;
+    let qualify_names = file.groups.len() > 1;
+
+    let mut metadata: HashMap<String, String> = file.properties.into_iter().collect();
+    let mut fields = vec![];
+    let mut channels = vec![];
+
+    for group in file.groups {
+      for (name, value) in group.properties {
+        metadata.insert(format!("{}/{name}", group.name), value);
+      }
+
+      for channel in group.channels {
+        let data_type = match channel.data_type.arrow_type() {
+          None => continue,
+          Some(t) => t,
+        };
+        let name = match qualify_names {
+          true => format!("{}/{}", group.name, channel.name),
+          false => channel.name.clone(),
+        };
+
+        fields.push(
+          Field::new(name, data_type, true)
+            .with_metadata(channel.properties.iter().cloned().collect()),
+        );
+        channels.push(channel);
+      }
+    }
+
+    if channels.is_empty() {
+      return Err(QueryError::Tdms(
+        "file does not contain any channels with data".to_string(),
+      ));
+    }
+
+    // channels don't have to be the same length, shorter ones are padded with nulls
+    let num_rows = channels.iter().map(|c| c.len).max().unwrap_or(0);
+
+    Ok(TdmsTableProvider {
+      bytes,
+      schema: Arc::new(Schema::new_with_metadata(fields, metadata)),
+      channels,
+      num_rows,
+    })
+  }
+}
+
+#[async_trait]
+impl TableProvider for TdmsTableProvider {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn schema(&self) -> SchemaRef {
+    self.schema.clone()
+  }
+
+  fn table_type(&self) -> TableType {
+    TableType::Base
+  }
+
+  async fn scan(
+    &self,
+    _state: &dyn Session,
+    projection: Option<&Vec<usize>>,
+    _filters: &[Expr],
+    limit: Option<usize>,
+  ) -> datafusion::error::Result<Arc<dyn ExecutionPlan>> {
+    let indices = match projection {
+      None => (0..self.channels.len()).collect(),
+      Some(p) => p.clone(),
+    };
+    let schema = Arc::new(self.schema.project(&indices)?);
+    let num_rows = limit.map_or(self.num_rows, |l| l.min(self.num_rows));
+
+    let partition = TdmsScan {
+      bytes: self.bytes.clone(),
+      schema: schema.clone(),
+      channels: indices.iter().map(|i| self.channels[*i].clone()).collect(),
+      num_rows,
+    };
+
+    Ok(Arc::new(StreamingTableExec::try_new(
+      schema,
+      vec![Arc::new(partition)],
+      None,
+      vec![],
+      false,
+      limit,
+    )?))
+  }
+}
+
+/// The channels a query reads, decoded when DataFusion starts reading the table. The decoded
+/// values are reserved against the query's memory pool until DataFusion is done with them
+#[derive(Debug)]
+struct TdmsScan {
+  bytes: Bytes,
+  schema: SchemaRef,
+  channels: Vec<TdmsChannel>,
+  num_rows: usize,
+}
+
+impl TdmsScan {
+  fn read(
+    &self,
+    reservation: &mut MemoryReservation,
+  ) -> datafusion::error::Result<Vec<RecordBatch>> {
+    let mut columns = vec![];
+    let mut used = 0;
+    for (channel, field) in self.channels.iter().zip(self.schema.fields()) {
+      // strings and booleans aren't a whole number of bytes wide, they're reserved once they're read
+      let width = field.data_type().primitive_width().unwrap_or(0);
+      reservation.try_grow(width * self.num_rows)?;
+
+      let values = read_channel(&self.bytes, channel, self.num_rows)
+        .map_err(|e| DataFusionError::Execution(e.to_string()))?;
+      let column = match values.len() < self.num_rows {
+        true => concat(&[
+          values.as_ref(),
+          new_null_array(values.data_type(), self.num_rows - values.len()).as_ref(),
+        ])?,
+        false => values,
+      };
+
+      used += column.get_array_memory_size();
+      reservation.try_resize(used)?;
+      columns.push(column);
+    }
+
+    let mut batches = vec![];
+    let mut start = 0;
+    while start < self.num_rows {
+      let len = BATCH_SIZE.min(self.num_rows - start);
+      let arrays: Vec<ArrayRef> = columns.iter().map(|c| c.slice(start, len)).collect();
+      batches.push(record_batch(self.schema.clone(), arrays, len)?);
+      start += len;
+    }
+
+    Ok(batches)
+  }
+}
+
+impl PartitionStream for TdmsScan {
+  fn schema(&self) -> &SchemaRef {
+    &self.schema
+  }
+
+  fn execute(&self, ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+    let mut reservation = MemoryConsumer::new("TdmsScan").register(ctx.memory_pool());
+    let batches = match self.read(&mut reservation) {
+      Ok(batches) => batches.into_iter().map(Ok).collect(),
+      Err(e) => vec![Err(e)],
+    };
+
+    // the batches are slices of the decoded columns, so the reservation is only given back once
+    // the stream is dropped
+    let stream = futures::stream::iter(batches).map(move |batch| {
+      let _reserved = reservation.size();
+      batch
+    });
+
+    Box::pin(RecordBatchStreamAdapter::new(self.schema.clone(), stream))
+  }
+}
@@ -2,7 +2,15 @@
 mod legacy_tests {
   
   
+  use crate::config::Configuration;
+  use crate::timeseries::csv_dialect::CsvColumn;
+  use crate::timeseries::datafusion_session::populate_session;
   use crate::timeseries::errors::{TestError, TimeseriesError};
+  use crate::timeseries::ingestion::{LegacyIngestionOptions, RowError};
+  use crate::timeseries::parquet_ingestion::ParquetIngestionOptions;
+  use crate::timeseries::repository::{BucketRepository, Ingestion, LegacyTimeseriesColumn};
+  use crate::timeseries::storage_connection::StorageConnection;
+  use datafusion::arrow::util::pretty::pretty_format_batches;
   
   
   use sqlx::{FromRow, PgPool};
@@ -37,14 +45,1234 @@ mod legacy_tests {
 
     Ok(())
   }
+
+  #[tokio::test]
+  async fn buffered_parquet_ingestion_with_filesystem() {
+    let mut config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    config.ingestion_buffer_chunks = Some(2);
+    config.ingestion_buffer_bytes = Some(16);
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    let root = std::env::temp_dir().join(format!("deeplynx_buffered_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let storage_connection: StorageConnection =
+      format!("provider=filesystem;rootFilePath={}", root.display())
+        .parse()
+        .unwrap();
+
+    let ingestion = repository
+      .begin_parquet_ingestion(
+        storage_connection.clone(),
+        ParquetIngestionOptions {
+          id: "1".to_string(),
+          file_path: "ingested".to_string(),
+          schema: vec![CsvColumn {
+            name: "reading".to_string(),
+            data_type: "Int64".to_string(),
+            nullable: None,
+          }],
+          ..Default::default()
+        },
+      )
+      .unwrap();
+
+    ingestion.read_data(b"reading\n".to_vec()).await.unwrap();
+    for i in 0..1000 {
+      // bigger than the whole buffer, it waits for the buffer to empty
+      let chunk = if i == 500 { "1\n".repeat(20) } else { format!("{i}\n") };
+      ingestion.read_data(chunk.into_bytes()).await.unwrap();
+      assert!(ingestion.buffered_bytes() <= 16);
+    }
+
+    let metadata = ingestion.complete().await.unwrap().file_metadata.unwrap();
+    let ctx = populate_session(&storage_connection, vec![metadata])
+      .await
+      .unwrap();
+    let results = ctx
+      .sql("SELECT COUNT(*), SUM(reading) FROM table_1")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    assert!(results.contains("| 1019 "), "{results}");
+    assert!(results.contains("| 499020 "), "{results}");
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[tokio::test]
+  async fn abort_legacy_csv_ingestion() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9019")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query("CREATE TABLE y_9019 (\"measured_at\" timestamp, \"reading\" numeric)")
+      .execute(&pool)
+      .await
+      .unwrap();
+
+    let columns = vec![
+      LegacyTimeseriesColumn {
+        column_name: "measured_at".to_string(),
+        property_name: "Timestamp".to_string(),
+        is_primary_timestamp: true,
+        data_type: "date".to_string(),
+        date_conversion_format_string: None,
+        timezone: None,
+      },
+      LegacyTimeseriesColumn {
+        column_name: "reading".to_string(),
+        property_name: "Reading".to_string(),
+        is_primary_timestamp: false,
+        data_type: "number".to_string(),
+        date_conversion_format_string: None,
+        timezone: None,
+      },
+    ];
+    let rows = |ingestion: Ingestion| async move {
+      ingestion
+        .read_data(b"Timestamp,Reading\n".to_vec())
+        .await
+        .unwrap();
+      for i in 0..2000 {
+        ingestion
+          .read_data(format!("2024-07-30 07:32:27,{i}\n").into_bytes())
+          .await
+          .unwrap();
+      }
+      ingestion
+    };
+    let count = || async {
+      sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM y_9019")
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+    };
+
+    // rows already sent to the COPY aren't committed
+    let aborted = rows(
+      repository
+        .begin_legacy_csv_ingestion(
+          "9019".to_string(),
+          columns.clone(),
+          LegacyIngestionOptions::default(),
+        )
+        .unwrap(),
+    )
+    .await;
+    aborted.abort().await.unwrap();
+    assert!(matches!(
+      aborted.read_data(b"2024-07-30 07:32:27,1\n".to_vec()).await,
+      Err(TimeseriesError::Aborted)
+    ));
+    assert!(matches!(
+      aborted.complete().await,
+      Err(TimeseriesError::Aborted)
+    ));
+    assert_eq!(count().await, 0);
+
+    let completed = rows(
+      repository
+        .begin_legacy_csv_ingestion(
+          "9019".to_string(),
+          columns,
+          LegacyIngestionOptions::default(),
+        )
+        .unwrap(),
+    )
+    .await;
+    completed.complete().await.unwrap();
+    assert_eq!(count().await, 2000);
+    // aborting after the fact doesn't undo anything
+    assert!(completed.abort().await.is_ok());
+    assert_eq!(count().await, 2000);
+
+    sqlx::query("DROP TABLE y_9019")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_csv_ingestion_reports_its_error_to_every_call() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9018")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query("CREATE TABLE y_9018 (\"reading\" integer)")
+      .execute(&pool)
+      .await
+      .unwrap();
+
+    let ingestion = repository
+      .begin_legacy_csv_ingestion(
+        "9018".to_string(),
+        vec![LegacyTimeseriesColumn {
+          column_name: "reading".to_string(),
+          property_name: "Reading".to_string(),
+          is_primary_timestamp: false,
+          data_type: "number".to_string(),
+          date_conversion_format_string: None,
+          timezone: None,
+        }],
+        LegacyIngestionOptions::default(),
+      )
+      .unwrap();
+    ingestion
+      .read_data(format!("Reading\nnot a number\n{}", "1\n".repeat(10_000)).into_bytes())
+      .await
+      .unwrap();
+
+    let mut stopped = None;
+    for _ in 0..500 {
+      tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+      if let Err(e) = ingestion.read_data(b"1\n".to_vec()).await {
+        stopped = Some(e);
+        break;
+      }
+    }
+
+    // read_data seeing the error first doesn't leave complete without it
+    assert!(matches!(stopped, Some(TimeseriesError::RejectedRow(_))));
+    assert!(matches!(
+      ingestion.complete().await,
+      Err(TimeseriesError::RejectedRow(_))
+    ));
+    assert!(matches!(
+      ingestion.complete().await,
+      Err(TimeseriesError::RejectedRow(_))
+    ));
+    assert!(ingestion.abort().await.is_ok());
+
+    sqlx::query("DROP TABLE y_9018")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_csv_ingestion_fails_before_all_data_is_in() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9017")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query("CREATE TABLE y_9017 (\"reading\" integer)")
+      .execute(&pool)
+      .await
+      .unwrap();
+
+    let ingestion = repository
+      .begin_legacy_csv_ingestion(
+        "9017".to_string(),
+        vec![LegacyTimeseriesColumn {
+          column_name: "reading".to_string(),
+          property_name: "Reading".to_string(),
+          is_primary_timestamp: false,
+          data_type: "number".to_string(),
+          date_conversion_format_string: None,
+          timezone: None,
+        }],
+        LegacyIngestionOptions::default(),
+      )
+      .unwrap();
+    ingestion
+      .read_data(b"Reading\nnot a number\n".to_vec())
+      .await
+      .unwrap();
+
+    // the reader hands over what it has rather than waiting for a full buffer, so the bad row fails
+    // the ingestion while the rest of the data, far less than a buffer's worth, is still coming in
+    let mut failed = false;
+    for _ in 0..500 {
+      tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+      if ingestion.read_data(b"1\n".to_vec()).await.is_err() {
+        failed = true;
+        break;
+      }
+    }
+    assert!(failed);
+
+    sqlx::query("DROP TABLE y_9017")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_csv_ingestion_error_policies() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9020")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query("CREATE TABLE y_9020 (\"measured_at\" timestamp, \"reading\" numeric)")
+      .execute(&pool)
+      .await
+      .unwrap();
+
+    let columns = vec![
+      LegacyTimeseriesColumn {
+        column_name: "measured_at".to_string(),
+        property_name: "Timestamp".to_string(),
+        is_primary_timestamp: true,
+        data_type: "date".to_string(),
+        date_conversion_format_string: None,
+        timezone: None,
+      },
+      LegacyTimeseriesColumn {
+        column_name: "reading".to_string(),
+        property_name: "Reading".to_string(),
+        is_primary_timestamp: false,
+        data_type: "number".to_string(),
+        date_conversion_format_string: None,
+        timezone: None,
+      },
+    ];
+    let csv = "Timestamp,Reading\n\
+      2024-07-30 07:32:27,1\n\
+      2024-07-30 25:00:00,2\n\
+      2024-07-30 07:32:29\n\
+      2024-07-30 07:32:30,4\n";
+    let ingest = |options: LegacyIngestionOptions| {
+      let ingestion = repository
+        .begin_legacy_csv_ingestion("9020".to_string(), columns.clone(), options)
+        .unwrap();
+      async move {
+        ingestion.read_data(csv.as_bytes().to_vec()).await.unwrap();
+        ingestion.complete().await
+      }
+    };
+    let count = || async {
+      sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM y_9020")
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+    };
+
+    // the default fails on the first bad row and commits nothing
+    match ingest(LegacyIngestionOptions::default()).await {
+      Err(TimeseriesError::RejectedRow(e)) => assert_eq!(e.row, 3),
+      other => panic!("expected a rejected row, got {other:?}"),
+    }
+    assert_eq!(count().await, 0);
+
+    let dead_letter = std::env::temp_dir().join("legacy_dead_letter_9020.csv");
+    let report = ingest(LegacyIngestionOptions {
+      error_policy: Some("skip".to_string()),
+      dead_letter_path: Some(dead_letter.to_string_lossy().to_string()),
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(count().await, 2);
+    assert_eq!(report.rows_rejected, 2);
+    assert_eq!(report.errors[0].row, 3);
+    assert_eq!(report.errors[0].column, Some("Timestamp".to_string()));
+    assert_eq!(
+      report.errors[0].value,
+      Some("2024-07-30 25:00:00".to_string())
+    );
+    assert_eq!(
+      report.errors[1],
+      RowError {
+        row: 4,
+        column: Some("Reading".to_string()),
+        value: None,
+        reason: "record is missing this field".to_string(),
+      }
+    );
+
+    let rejected = std::fs::read_to_string(&dead_letter).unwrap();
+    let mut lines = rejected.lines();
+    assert_eq!(lines.next(), Some("Timestamp,Reading,_row,_error"));
+    assert!(lines.next().unwrap().starts_with("2024-07-30 25:00:00,2,3,"));
+    assert_eq!(
+      lines.next(),
+      Some("2024-07-30 07:32:29,4,record is missing this field")
+    );
+    std::fs::remove_file(&dead_letter).unwrap();
+
+    // collect fails once more than max_errors rows are rejected
+    assert!(matches!(
+      ingest(LegacyIngestionOptions {
+        error_policy: Some("collect".to_string()),
+        max_errors: Some(1),
+        ..Default::default()
+      })
+      .await,
+      Err(TimeseriesError::TooManyRejectedRows(1))
+    ));
+    let report = ingest(LegacyIngestionOptions {
+      error_policy: Some("collect".to_string()),
+      max_errors: Some(2),
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert_eq!(report.rows_rejected, 2);
+    assert_eq!(count().await, 4);
+
+    assert!(matches!(
+      repository.begin_legacy_csv_ingestion(
+        "9020".to_string(),
+        columns.clone(),
+        LegacyIngestionOptions {
+          error_policy: Some("ignore".to_string()),
+          ..Default::default()
+        },
+      ),
+      Err(TimeseriesError::InvalidOptions(_))
+    ));
+
+    sqlx::query("DROP TABLE y_9020")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_csv_ingestion_converts_every_type() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9022")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query(
+      "CREATE TABLE y_9022 (\"n\" integer, \"n64\" bigint, \"f\" numeric, \"b\" boolean, \
+       \"j\" jsonb, \"s\" text)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let column = |name: &str, data_type: &str| LegacyTimeseriesColumn {
+      column_name: name.to_string(),
+      property_name: name.to_uppercase(),
+      is_primary_timestamp: false,
+      data_type: data_type.to_string(),
+      date_conversion_format_string: None,
+      timezone: None,
+    };
+    let columns = vec![
+      column("n", "number"),
+      column("n64", "number64"),
+      column("f", "float64"),
+      column("b", "boolean"),
+      column("j", "json"),
+      column("s", "string"),
+    ];
+    let ingestion = repository
+      .begin_legacy_csv_ingestion(
+        "9022".to_string(),
+        columns,
+        LegacyIngestionOptions {
+          error_policy: Some("skip".to_string()),
+          null_values: Some(vec!["N/A".to_string()]),
+          ..Default::default()
+        },
+      )
+      .unwrap();
+    ingestion
+      .read_data(
+        "N,N64,F,B,J,S\n\
+         \"1,024\",9223372036854775807,1.00000000000001,yes,\"{\"\"a\"\": [1, 2]}\",\"a, \"\"quoted\"\" string\"\n\
+         N/A, N/A ,N/A,0,N/A,\n\
+         12.5,1,1,1,{},s\n\
+         1,1,1,maybe,{},s\n\
+         1,1,1,1,{,s\n\
+         1,1,inf,1,{},s\n\
+         \"1,5\",1,1,1,{},s\n\
+         1,1,\"2,5\",1,{},s\n"
+          .as_bytes()
+          .to_vec(),
+      )
+      .await
+      .unwrap();
+    let report = ingestion.complete().await.unwrap();
+
+    let reasons: Vec<(u32, Option<String>, String)> = report
+      .errors
+      .iter()
+      .map(|e| (e.row, e.column.clone(), e.reason.clone()))
+      .collect();
+    assert_eq!(reasons.len(), 6);
+    assert_eq!(
+      reasons[0],
+      (
+        4,
+        Some("N".to_string()),
+        "not a whole number that fits in 32 bits".to_string()
+      )
+    );
+    assert_eq!(reasons[1].1, Some("B".to_string()));
+    assert_eq!(reasons[2].1, Some("J".to_string()));
+    assert!(reasons[2].2.starts_with("not valid JSON"));
+    assert_eq!(
+      reasons[3],
+      (7, Some("F".to_string()), "not a finite number".to_string())
+    );
+    // only thousands separators are stripped, decimal commas would make for a different number
+    let comma = "not a number, commas can only separate thousands".to_string();
+    assert_eq!(reasons[4], (8, Some("N".to_string()), comma.clone()));
+    assert_eq!(reasons[5], (9, Some("F".to_string()), comma));
+
+    #[derive(Debug, PartialEq, FromRow)]
+    struct Converted {
+      n: Option<i32>,
+      n64: Option<i64>,
+      f: Option<String>,
+      b: Option<bool>,
+      j: Option<String>,
+      s: Option<String>,
+    }
+    let rows: Vec<Converted> = sqlx::query_as(
+      "SELECT n, n64, f::text AS f, b, j::text AS j, s FROM y_9022 ORDER BY n NULLS LAST",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+      rows,
+      vec![
+        Converted {
+          n: Some(1024),
+          n64: Some(i64::MAX),
+          f: Some("1.00000000000001".to_string()),
+          b: Some(true),
+          j: Some("{\"a\": [1, 2]}".to_string()),
+          s: Some("a, \"quoted\" string".to_string()),
+        },
+        Converted {
+          n: None,
+          n64: None,
+          f: None,
+          b: Some(false),
+          j: None,
+          s: None,
+        },
+      ]
+    );
+
+    sqlx::query("DROP TABLE y_9022")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_csv_ingestion_timestamps() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9023")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query(
+      "CREATE TABLE y_9023 (\"id\" integer, \"detected\" timestamptz, \"formatted\" timestamptz, \
+       \"epoch\" timestamptz)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let column = |name: &str, data_type: &str, format: Option<&str>, timezone: Option<&str>| {
+      LegacyTimeseriesColumn {
+        column_name: name.to_string(),
+        property_name: name.to_string(),
+        is_primary_timestamp: false,
+        data_type: data_type.to_string(),
+        date_conversion_format_string: format.map(|f| f.to_string()),
+        timezone: timezone.map(|t| t.to_string()),
+      }
+    };
+    let columns = vec![
+      column("id", "number", None, None),
+      column("detected", "date", None, Some("America/Denver")),
+      column("formatted", "date", Some("%d/%m/%Y %H:%M"), Some("+02:00")),
+      column("epoch", "date", Some("epoch_ms"), Some("America/Denver")),
+    ];
+    let ingestion = repository
+      .begin_legacy_csv_ingestion(
+        "9023".to_string(),
+        columns.clone(),
+        LegacyIngestionOptions::default(),
+      )
+      .unwrap();
+    ingestion
+      .read_data(
+        "id,detected,formatted,epoch\n\
+         1,2024-07-30T07:32:27-06:00,30/07/2024 09:00,1722324747000\n\
+         2,1722324747,,1722324747500\n\
+         3,45503.5,,\n\
+         4,2024-07-30 07:32:27,,\n\
+         5,2024-07-30T07:32:27.5Z,,\n"
+          .as_bytes()
+          .to_vec(),
+      )
+      .await
+      .unwrap();
+    ingestion.complete().await.unwrap();
+
+    type Row = (i32, Option<String>, Option<String>, Option<String>);
+    let rows: Vec<Row> = sqlx::query_as(
+      "SELECT id, \
+         to_char(detected AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS.MS'), \
+         to_char(formatted AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS.MS'), \
+         to_char(epoch AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS.MS') \
+       FROM y_9023 ORDER BY id",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let utc = |s: &str| Some(s.to_string());
+    assert_eq!(
+      rows,
+      vec![
+        (
+          1,
+          utc("2024-07-30 13:32:27.000"),
+          utc("2024-07-30 07:00:00.000"),
+          utc("2024-07-30 07:32:27.000")
+        ),
+        // epoch times are UTC whatever the column's timezone
+        (2, utc("2024-07-30 07:32:27.000"), None, utc("2024-07-30 07:32:27.500")),
+        // an Excel serial date is noon in Denver
+        (3, utc("2024-07-30 18:00:00.000"), None, None),
+        (4, utc("2024-07-30 13:32:27.000"), None, None),
+        (5, utc("2024-07-30 07:32:27.500"), None, None),
+      ]
+    );
+
+    let mut bad_timezone = columns;
+    bad_timezone[1].timezone = Some("Mars/Olympus_Mons".to_string());
+    let ingestion = repository
+      .begin_legacy_csv_ingestion(
+        "9023".to_string(),
+        bad_timezone,
+        LegacyIngestionOptions::default(),
+      )
+      .unwrap();
+    let _ = ingestion
+      .read_data(b"id,detected,formatted,epoch\n".to_vec())
+      .await;
+    assert!(matches!(
+      ingestion.complete().await,
+      Err(TimeseriesError::InvalidOptions(_))
+    ));
+
+    sqlx::query("DROP TABLE y_9023")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_json_ingestion() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9024")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query(
+      "CREATE TABLE y_9024 (\"measured_at\" timestamptz, \"reading\" numeric, \"unit\" text, \
+       \"tags\" jsonb)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let column = |name: &str, property_name: &str, data_type: &str| LegacyTimeseriesColumn {
+      column_name: name.to_string(),
+      property_name: property_name.to_string(),
+      is_primary_timestamp: false,
+      data_type: data_type.to_string(),
+      date_conversion_format_string: None,
+      timezone: None,
+    };
+    let columns = vec![
+      column("measured_at", "Timestamp", "date"),
+      column("reading", "$.readings[0].value", "float"),
+      column("unit", "sensor.unit", "string"),
+      column("tags", "tags", "json"),
+    ];
+    let ingest = |json: &'static str, options: LegacyIngestionOptions| {
+      let ingestion = repository
+        .begin_legacy_json_ingestion("9024".to_string(), columns.clone(), options)
+        .unwrap();
+      async move {
+        // split part way through a record, and a string, like the chunks of a stream would be
+        let (first, second) = json.split_at(json.len() / 2);
+        ingestion.read_data(first.as_bytes().to_vec()).await.unwrap();
+        ingestion.read_data(second.as_bytes().to_vec()).await.unwrap();
+        ingestion.complete().await
+      }
+    };
+    let rows = || async {
+      sqlx::query_as::<_, (String, Option<String>, Option<String>, Option<String>)>(
+        "SELECT to_char(measured_at AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'), \
+           reading::text, unit, tags::text FROM y_9024 ORDER BY measured_at",
+      )
+      .fetch_all(&pool)
+      .await
+      .unwrap()
+    };
+
+    // an array of records, with brackets and commas in the strings
+    ingest(
+      r#"[
+        {"Timestamp": "2024-07-30T07:32:27Z", "readings": [{"value": 1.5}, {"value": 2}],
+         "sensor": {"unit": "[psi], \"gauge\""}, "tags": ["a", {"b": "]"}]},
+        {"Timestamp": "2024-07-30T07:32:28Z", "sensor": {}, "tags": "plain"}
+      ]"#,
+      LegacyIngestionOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+      rows().await,
+      vec![
+        (
+          "2024-07-30 07:32:27".to_string(),
+          Some("1.5".to_string()),
+          Some("[psi], \"gauge\"".to_string()),
+          Some(r#"["a", {"b": "]"}]"#.to_string())
+        ),
+        (
+          "2024-07-30 07:32:28".to_string(),
+          None,
+          None,
+          Some(r#""plain""#.to_string())
+        ),
+      ]
+    );
+
+    // newline delimited, a record with a flat key made of the whole path wins over the path
+    let dead_letter = std::env::temp_dir().join("legacy_dead_letter_9024.ndjson");
+    let report = ingest(
+      "{\"Timestamp\": 1722324749, \"sensor.unit\": \"kPa\", \"sensor\": {\"unit\": \"psi\"}}\n\
+       {\"Timestamp\": \"yesterday\"}\n\
+       [1, 2]\n",
+      LegacyIngestionOptions {
+        error_policy: Some("skip".to_string()),
+        dead_letter_path: Some(dead_letter.to_string_lossy().to_string()),
+        ..Default::default()
+      },
+    )
+    .await
+    .unwrap();
+    assert_eq!(rows().await.len(), 3);
+    assert_eq!(rows().await[2].2, Some("kPa".to_string()));
+    assert_eq!(report.rows_rejected, 2);
+    assert_eq!(report.errors[0].row, 2);
+    assert_eq!(report.errors[0].column, Some("Timestamp".to_string()));
+    assert_eq!(report.errors[1].reason, "record is not an object");
+
+    let rejected: Vec<serde_json::Value> = std::fs::read_to_string(&dead_letter)
+      .unwrap()
+      .lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect();
+    assert_eq!(rejected[0]["Timestamp"], "yesterday");
+    assert_eq!(rejected[0]["_row"], 2);
+    assert_eq!(rejected[1]["_record"], serde_json::json!([1, 2]));
+    std::fs::remove_file(&dead_letter).unwrap();
+
+    // an array that's cut off fails rather than ingesting what came before
+    assert!(ingest(
+      r#"[{"Timestamp": "2024-07-30T07:32:30Z"}"#,
+      LegacyIngestionOptions::default()
+    )
+    .await
+    .is_err());
+    assert_eq!(rows().await.len(), 3);
+
+    // and so does one that isn't valid JSON, even if every record in it is
+    for json in [
+      r#"[{"Timestamp": "2024-07-30T07:32:30Z"} {"Timestamp": "2024-07-30T07:32:31Z"}]"#,
+      r#"[{"Timestamp": "2024-07-30T07:32:30Z"},,{"Timestamp": "2024-07-30T07:32:31Z"}]"#,
+      r#"[{"Timestamp": "2024-07-30T07:32:30Z"},]"#,
+      r#"[{"Timestamp": "2024-07-30T07:32:30Z"}] {"Timestamp": "2024-07-30T07:32:31Z"}"#,
+    ] {
+      assert!(ingest(json, LegacyIngestionOptions::default()).await.is_err());
+    }
+    assert_eq!(rows().await.len(), 3);
+
+    // column names are quoted however odd they are, and the id has to be one
+    sqlx::query("ALTER TABLE y_9024 ADD COLUMN \"say \"\"hi\"\"\" text")
+      .execute(&pool)
+      .await
+      .unwrap();
+    let quoted = vec![column("say \"hi\"", "greeting", "string")];
+    let ingestion = repository
+      .begin_legacy_json_ingestion(
+        "9024".to_string(),
+        quoted.clone(),
+        LegacyIngestionOptions::default(),
+      )
+      .unwrap();
+    ingestion
+      .read_data(br#"{"greeting": "hello"}"#.to_vec())
+      .await
+      .unwrap();
+    ingestion.complete().await.unwrap();
+    let greeting: String =
+      sqlx::query_scalar("SELECT \"say \"\"hi\"\"\" FROM y_9024 WHERE measured_at IS NULL")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(greeting, "hello");
+
+    assert!(repository
+      .begin_legacy_json_ingestion(
+        "9024(x) FROM STDIN; DROP TABLE y_9024; --".to_string(),
+        quoted.clone(),
+        LegacyIngestionOptions::default(),
+      )
+      .is_err());
+    assert!(repository
+      .begin_legacy_csv_ingestion(
+        "9024(x) FROM STDIN; DROP TABLE y_9024; --".to_string(),
+        quoted,
+        LegacyIngestionOptions::default(),
+      )
+      .is_err());
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM y_9024")
+      .fetch_one(&pool)
+      .await
+      .unwrap();
+    assert_eq!(count, 4);
+
+    sqlx::query("DROP TABLE y_9024")
+      .execute(&pool)
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn legacy_bucket_table_lifecycle() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let pool = PgPool::connect(config.db_connection_string.clone().unwrap().as_str())
+      .await
+      .unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    for table in ["y_9025", "y_9025_flat", "y_9025_mixed"] {
+      sqlx::query(format!("DROP TABLE IF EXISTS {table}").as_str())
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    let timescale: bool =
+      sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb')")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+    let column = |name: &str, data_type: &str, is_primary_timestamp: bool| LegacyTimeseriesColumn {
+      column_name: name.to_string(),
+      property_name: name.to_string(),
+      is_primary_timestamp,
+      data_type: data_type.to_string(),
+      date_conversion_format_string: None,
+      timezone: None,
+    };
+    let columns = vec![
+      column("measured_at", "date", true),
+      column("reading", "number", false),
+      column("unit", "string", false),
+    ];
+    let table_columns = |table: &'static str| {
+      let pool = pool.clone();
+      async move {
+        sqlx::query_as::<_, (String, String)>(
+          "SELECT column_name::text, data_type::text FROM information_schema.columns \
+           WHERE table_name = $1 ORDER BY ordinal_position",
+        )
+        .bind(table)
+        .fetch_all(&pool)
+        .await
+        .unwrap()
+      }
+    };
+
+    let table = repository
+      .create_bucket(
+        "9025".to_string(),
+        columns.clone(),
+        crate::timeseries::bucket_tables::BucketTableOptions::default(),
+      )
+      .await
+      .unwrap();
+    assert_eq!(table.table_name, "y_9025");
+    assert_eq!(
+      table.partitioning,
+      if timescale { "hypertable" } else { "range" }
+    );
+    assert_eq!(
+      table_columns("y_9025").await,
+      vec![
+        ("measured_at".to_string(), "timestamp with time zone".to_string()),
+        ("reading".to_string(), "integer".to_string()),
+        ("unit".to_string(), "text".to_string()),
+        ("_metadata".to_string(), "jsonb".to_string()),
+        ("_import_id".to_string(), "bigint".to_string()),
+      ]
+    );
+
+    // the legacy ingestions write into it as they would a table the DeepLynx server created
+    let ingestion = repository
+      .begin_legacy_csv_ingestion(
+        "9025".to_string(),
+        columns.clone(),
+        LegacyIngestionOptions::default(),
+      )
+      .unwrap();
+    ingestion
+      .read_data(
+        b"measured_at,reading,unit\n2024-07-30T07:32:27Z,1,psi\n1999-01-01T00:00:00Z,2,kPa\n".to_vec(),
+      )
+      .await
+      .unwrap();
+    ingestion.complete().await.unwrap();
+
+    let change = |action: &str, column: LegacyTimeseriesColumn, from: Option<&str>| {
+      crate::timeseries::bucket_tables::BucketColumnChange {
+        action: action.to_string(),
+        column,
+        from: from.map(|f| f.to_string()),
+      }
+    };
+    repository
+      .alter_bucket(
+        "9025".to_string(),
+        vec![
+          change("add", column("tags", "json", false), None),
+          change("rename", column("units", "string", false), Some("unit")),
+          change("change_type", column("reading", "float", false), None),
+        ],
+      )
+      .await
+      .unwrap();
+    assert_eq!(
+      table_columns("y_9025").await[1..4],
+      [
+        ("reading".to_string(), "numeric".to_string()),
+        ("units".to_string(), "text".to_string()),
+        ("_metadata".to_string(), "jsonb".to_string()),
+      ]
+    );
+    assert_eq!(table_columns("y_9025").await[5].0, "tags");
+    let readings: Vec<(String, String)> =
+      sqlx::query_as("SELECT reading::text, units FROM y_9025 ORDER BY measured_at")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+      readings,
+      vec![
+        ("2".to_string(), "kPa".to_string()),
+        ("1".to_string(), "psi".to_string())
+      ]
+    );
+
+    // the changes are made together, a bad one leaves the table as it was
+    assert!(repository
+      .alter_bucket(
+        "9025".to_string(),
+        vec![
+          change("add", column("extra", "string", false), None),
+          change("drop", column("units", "string", false), None),
+        ],
+      )
+      .await
+      .is_err());
+    assert!(repository
+      .alter_bucket(
+        "9025".to_string(),
+        vec![
+          change("add", column("extra", "string", false), None),
+          change("change_type", column("units", "number", false), None),
+        ],
+      )
+      .await
+      .is_err());
+    assert_eq!(table_columns("y_9025").await.len(), 6);
+
+    // without a primary timestamp there's nothing to partition by
+    let table = repository
+      .create_bucket(
+        "9025_flat".to_string(),
+        vec![column("reading", "float64", false)],
+        crate::timeseries::bucket_tables::BucketTableOptions::default(),
+      )
+      .await
+      .unwrap();
+    assert_eq!(table.partitioning, "none");
+
+    // Postgres folds the unquoted table name to lower case, the id is lowercased so the name and
+    // its default partition's name match what's in the database
+    let table = repository
+      .create_bucket(
+        "9025_Mixed".to_string(),
+        columns.clone(),
+        crate::timeseries::bucket_tables::BucketTableOptions::default(),
+      )
+      .await
+      .unwrap();
+    assert_eq!(table.table_name, "y_9025_mixed");
+    assert_eq!(table_columns("y_9025_mixed").await.len(), 5);
+    if !timescale {
+      assert_eq!(table_columns("y_9025_mixed_default").await.len(), 5);
+    }
+
+    for (id, columns) in [
+      ("9025; DROP TABLE y_9025", columns.clone()),
+      ("9025_bad", vec![column("unit", "string", true)]),
+      (
+        "9025_bad",
+        vec![column("a", "date", true), column("b", "number", true)],
+      ),
+      ("9025_bad", vec![]),
+    ] {
+      assert!(repository
+        .create_bucket(
+          id.to_string(),
+          columns,
+          crate::timeseries::bucket_tables::BucketTableOptions::default(),
+        )
+        .await
+        .is_err());
+    }
+
+    repository.drop_bucket("9025".to_string()).await.unwrap();
+    repository
+      .drop_bucket("9025_flat".to_string())
+      .await
+      .unwrap();
+    repository
+      .drop_bucket("9025_Mixed".to_string())
+      .await
+      .unwrap();
+    assert!(table_columns("y_9025").await.is_empty());
+    assert!(table_columns("y_9025_default").await.is_empty());
+    assert!(table_columns("y_9025_flat").await.is_empty());
+    assert!(table_columns("y_9025_mixed").await.is_empty());
+    assert!(table_columns("y_9025_mixed_default").await.is_empty());
+  }
+
+  #[test]
+  fn infer_legacy_columns() {
+    let sample = std::fs::read("test_files/timeseries/inference.csv").unwrap();
+    let columns = crate::timeseries::column_inference::infer(&sample).unwrap();
+    let proposed: Vec<(&str, &str, Option<&str>, bool, f64)> = columns
+      .iter()
+      .map(|c| {
+        (
+          c.column.column_name.as_str(),
+          c.column.data_type.as_str(),
+          c.column.date_conversion_format_string.as_deref(),
+          c.column.is_primary_timestamp,
+          c.confidence,
+        )
+      })
+      .collect();
+
+    assert_eq!(
+      proposed,
+      vec![
+        // the first row leaves it empty, which doesn't count against it
+        ("string", "string", None, false, 1.0),
+        ("timestamp", "date", Some("%Y-%m-%d:%H:%M"), true, 1.0),
+        ("int", "number", None, false, 1.0),
+        ("bigint", "number64", None, false, 1.0),
+        ("float", "float", None, false, 1.0),
+        ("float64", "float64", None, false, 1.0),
+        // its quotes are typographic ones, which isn't JSON
+        ("json", "string", None, false, 1.0),
+        ("boolean", "boolean", None, false, 1.0),
+        ("booleancase", "boolean", None, false, 1.0),
+      ]
+    );
+
+    // a sample cut off part way through a row leaves that row out, and a value that doesn't fit the
+    // rest lowers the confidence
+    let columns = crate::timeseries::column_inference::infer(
+      b"Measured At,Reading\n\
+        07/30/2024 07:32:27,1\n\
+        07/30/2024 07:32:28,2\n\
+        07/30/2024 07:32:29,3\n\
+        not a date,4.5\n\
+        07/30/2024 07:32:30,40",
+    )
+    .unwrap();
+    assert_eq!(columns[0].column.column_name, "measured_at");
+    assert_eq!(columns[0].column.property_name, "Measured At");
+    assert_eq!(
+      columns[0].column.date_conversion_format_string.as_deref(),
+      Some("%m/%d/%Y %H:%M:%S")
+    );
+    assert_eq!(columns[0].confidence, 0.75);
+    assert_eq!(columns[1].column.data_type, "float");
+    assert_eq!(columns[1].confidence, 1.0);
+
+    // decimal commas aren't mistaken for thousands separators
+    let columns =
+      crate::timeseries::column_inference::infer(b"Count,Reading\n\"1,024\",\"2,5\"\n").unwrap();
+    assert_eq!(columns[0].column.data_type, "number");
+    assert_eq!(columns[1].column.data_type, "string");
+
+    // too few numbers to make a number column leaves a string, which every value fits, and headers
+    // that make the same column name are numbered
+    let columns =
+      crate::timeseries::column_inference::infer(b"A b,a_b,a_b_2
+x,1,1
+y,2,2
+3,3,3
+").unwrap();
+    let names: Vec<&str> = columns
+      .iter()
+      .map(|c| c.column.column_name.as_str())
+      .collect();
+    assert_eq!(names, vec!["a_b", "a_b_2", "a_b_2_2"]);
+    assert_eq!(columns[0].column.data_type, "string");
+    assert_eq!(columns[0].confidence, 1.0);
+  }
+
+  #[tokio::test]
+  async fn concurrent_parquet_ingestions_with_filesystem() {
+    let config = Configuration::from_path(Some(String::from(".config.yml"))).unwrap();
+    let repository = BucketRepository::new(config).await.unwrap();
+
+    let root = std::env::temp_dir().join(format!("deeplynx_concurrent_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let storage_connection: StorageConnection =
+      format!("provider=filesystem;rootFilePath={}", root.display())
+        .parse()
+        .unwrap();
+
+    let options = |id: &str| ParquetIngestionOptions {
+      id: id.to_string(),
+      file_path: format!("ingested_{id}"),
+      schema: vec![CsvColumn {
+        name: "reading".to_string(),
+        data_type: "Int64".to_string(),
+        nullable: None,
+      }],
+      ..Default::default()
+    };
+    let ingestions: Vec<_> = ["1", "2", "3"]
+      .iter()
+      .map(|id| {
+        repository
+          .begin_parquet_ingestion(storage_connection.clone(), options(id))
+          .unwrap()
+      })
+      .collect();
+
+    for ingestion in &ingestions {
+      ingestion.read_data(b"reading\n".to_vec()).await.unwrap();
+    }
+    for i in 0..100 {
+      for (n, ingestion) in ingestions.iter().enumerate() {
+        let reading = i * (n + 1);
+        ingestion
+          .read_data(format!("{reading}\n").into_bytes())
+          .await
+          .unwrap();
+      }
+    }
+
+    // the second one is thrown away, the others finish as if it was never there
+    ingestions[1].abort().await.unwrap();
+    assert!(ingestions[1].read_data(b"1\n".to_vec()).await.is_err());
+    let first = ingestions[0].complete().await.unwrap().file_metadata.unwrap();
+    let third = ingestions[2].complete().await.unwrap().file_metadata.unwrap();
+    assert!(!root.join("ingested_2").exists());
+
+    let ctx = populate_session(&storage_connection, vec![first, third])
+      .await
+      .unwrap();
+    let results = ctx
+      .sql("SELECT (SELECT SUM(reading) FROM table_1) AS first, (SELECT SUM(reading) FROM table_3) AS third")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    assert!(results.contains("| 4950  | 14850 |"), "{results}");
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
 }
 
 #[cfg(test)]
 mod query_tests {
+  use crate::config::Configuration;
+  use crate::snapshot::generator::to_record_batch;
   use crate::timeseries::{
-    file_metadata::FileMetadata,
-    query::{process_query, process_upload},
+    csv_dialect::{CsvColumn, CsvDialect},
+    datafusion_session::{populate_session, register_legacy_tables, register_snapshot_tables},
+    file_metadata::{FileMetadata, PartitionColumn},
+    parquet_ingestion::{ingest_parquet, ParquetIngestionOptions},
+    postgres_table::LegacyTables,
+    query::{process_query, process_upload, ProcessQueryOptions},
+    query_engine::TimeseriesQueryEngine,
+    query_output::QueryOutputOptions,
+    query_policy::QueryLimits,
+    query_stream::{stream_query, QueryStreamOptions},
+    storage_connection::{AzureAuth, StorageConnection},
+  };
+  use datafusion::arrow::ipc::reader::StreamReader;
+  use datafusion::arrow::util::pretty::pretty_format_batches;
+  use datafusion::datasource::file_format::{
+    file_compression_type::FileCompressionType, options::ArrowReadOptions,
+  };
+  use datafusion::prelude::{
+    CsvReadOptions, NdJsonReadOptions, ParquetReadOptions, SessionContext,
   };
+  use polars::frame::DataFrame;
+  use polars::prelude::{NamedFrom, Series};
+  use sqlx::PgPool;
 
   #[tokio::test]
   async fn describe_with_azure() {
@@ -57,6 +1285,9 @@ mod query_tests {
           id: "1".to_string(),
           file_name: "ten-entries.csv".to_string(),
           file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
         },
       ]
     ).await {
@@ -80,6 +1311,9 @@ mod query_tests {
         id: "1".to_string(),
         file_name: "ten-entries.csv".to_string(),
         file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
       }],
     )
     .await
@@ -93,6 +1327,85 @@ mod query_tests {
     };
   }
 
+  #[tokio::test]
+  async fn describe_reports_partial_failures_with_filesystem() {
+    let res = process_upload(
+      "14".to_string(),
+      "DESCRIBE table_1;; describe table_3; SELECT * FROM table_1;".to_string(),
+      "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+        .to_string(),
+      vec![
+        FileMetadata {
+          id: "1".to_string(),
+          file_name: "ten-entries.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        },
+        FileMetadata {
+          id: "3".to_string(),
+          file_name: "missing.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        },
+      ],
+    )
+    .await
+    .unwrap();
+    let report: serde_json::Value = serde_json::from_str(res.as_str()).unwrap();
+
+    let descriptions = report["descriptions"].as_array().unwrap();
+    assert_eq!(descriptions.len(), 1);
+    assert_eq!(descriptions[0]["file_id"], "1");
+    assert_eq!(descriptions[0]["sample_rows"], 10);
+
+    // the DESCRIBE rows are still returned as a JSON string for the existing file descriptions
+    let rows: serde_json::Value =
+      serde_json::from_str(descriptions[0]["description"].as_str().unwrap()).unwrap();
+    assert_eq!(rows.as_array().unwrap().len(), 7);
+
+    let temperature = &descriptions[0]["columns"][1];
+    assert_eq!(temperature["name"], "Temperature (K)");
+    assert_eq!(temperature["data_type"], "Int64");
+    assert_eq!(temperature["nullable"], true);
+    assert_eq!(temperature["null_count"], 0);
+    assert_eq!(temperature["min"], "0");
+    assert_eq!(temperature["max"], "9");
+
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["file_id"], "3");
+    assert!(errors[1]["statement"].as_str().unwrap().starts_with("SELECT"));
+
+    // ids can have underscores in them, like the HDF5 group suffixes of table names do
+    let file = |id: &str, file_name: &str| FileMetadata {
+      id: id.to_string(),
+      file_name: file_name.to_string(),
+      file_path: "containers/1/datasources/1".to_string(),
+      partition_columns: None,
+      csv_options: None,
+      glob: None,
+    };
+    let res = process_upload(
+      "14".to_string(),
+      "DESCRIBE table_1_a; DESCRIBE table_1_b".to_string(),
+      "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+        .to_string(),
+      vec![file("1_a", "ten-entries.csv"), file("1_b", "missing.csv")],
+    )
+    .await
+    .unwrap();
+    let report: serde_json::Value = serde_json::from_str(res.as_str()).unwrap();
+
+    assert_eq!(report["descriptions"][0]["file_id"], "1_a");
+    let errors = report["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["file_id"], "1_b");
+  }
+
   #[tokio::test]
   async fn multi_file_describe_with_azure() {
     match process_upload(
@@ -104,11 +1417,17 @@ mod query_tests {
           id: "1".to_string(),
           file_name: "ten-entries.csv".to_string(),
           file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
         },
         FileMetadata {
           id: "2".to_string(),
           file_name: "ten-entries-2.csv".to_string(),
           file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
         },
       ]
     ).await {
@@ -133,11 +1452,17 @@ mod query_tests {
           id: "1".to_string(),
           file_name: "ten-entries.csv".to_string(),
           file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
         },
         FileMetadata {
           id: "2".to_string(),
           file_name: "ten-entries-2.csv".to_string(),
           file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
         },
       ],
     )
@@ -163,8 +1488,12 @@ mod query_tests {
           id: "1".to_string(),
           file_name: "ten-entries.csv".to_string(),
           file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
         },
-      ]
+      ],
+      None,
     ).await {
       Ok(res) => {
         dbg!(res);
@@ -186,7 +1515,11 @@ mod query_tests {
         id: "1".to_string(),
         file_name: "ten-entries.csv".to_string(),
         file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
       }],
+      None,
     )
     .await
     {
@@ -198,4 +1531,1718 @@ mod query_tests {
       }
     };
   }
+
+  // expects a MinIO server on 127.0.0.1:9000 with the deep-lynx bucket holding the timeseries2 test files
+  #[tokio::test]
+  async fn describe_with_s3() {
+    match process_upload(
+      "11".to_string(),
+      "DESCRIBE table_1".to_string(),
+      "provider=aws_s3;uploadPath=containers/1/datasources/1;endpoint=http://127.0.0.1:9000;bucketName=deep-lynx;accessKeyId=minioadmin;secretAccessKey=minioadmin".to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    {
+      Ok(res) => {
+        let report: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert_eq!(report["errors"], serde_json::json!([]));
+        assert_eq!(report["descriptions"][0]["file_id"], "1");
+        assert!(res.contains("Temperature (K)"));
+        assert!(res.contains("X (m)"));
+      }
+      Err(e) => {
+        panic!("{}", e.reason);
+      }
+    };
+  }
+
+  #[tokio::test]
+  async fn query_with_s3() {
+    match process_query(
+      "12".to_string(),
+      "SELECT * FROM table_1".to_string(),
+      "provider=aws_s3;uploadPath=containers/1/datasources/1;endpoint=http://127.0.0.1:9000;bucketName=deep-lynx;accessKeyId=minioadmin;secretAccessKey=minioadmin".to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+      None,
+    )
+    .await
+    {
+      Ok(res) => {
+        let metadata: serde_json::Value = serde_json::from_str(&res).unwrap();
+        assert_eq!(metadata["adapter"], "aws_s3");
+        assert!(metadata["file_size_bytes"].as_u64().unwrap() > 0);
+      }
+      Err(e) => {
+        panic!("{}", e.reason);
+      }
+    };
+  }
+
+  #[tokio::test]
+  async fn s3_requires_bucket_name() {
+    let res = process_upload(
+      "13".to_string(),
+      "DESCRIBE table_1".to_string(),
+      "provider=aws_s3;endpoint=http://127.0.0.1:9000;accessKeyId=minioadmin;secretAccessKey=minioadmin".to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await;
+
+    assert!(res.unwrap_err().reason.contains("bucketName is not set"));
+  }
+
+  #[test]
+  fn storage_connection_validation() {
+    let azure = "provider=azure_blob;blobEndpoint=https://account.blob.core.windows.net;accountName=account;containerName=deep-lynx";
+
+    let parsed: StorageConnection = format!("{azure};sasToken='?sv=2022-11-02&sig=abc'")
+      .parse()
+      .unwrap();
+    match parsed {
+      StorageConnection::AzureBlob(c) => {
+        assert!(matches!(c.auth, AzureAuth::SasToken(t) if t == "?sv=2022-11-02&sig=abc"))
+      }
+      _ => panic!("expected an azure connection"),
+    }
+
+    let parsed: StorageConnection = format!("{azure};managedIdentityClientId=1234")
+      .parse()
+      .unwrap();
+    match parsed {
+      StorageConnection::AzureBlob(c) => assert!(matches!(
+        c.auth,
+        AzureAuth::ManagedIdentity { client_id: Some(id) } if id == "1234"
+      )),
+      _ => panic!("expected an azure connection"),
+    }
+
+    let errors = [
+      (azure.to_string(), "one of accountKey, sasToken or managedIdentity"),
+      (
+        format!("{azure};accountKey=key;managedIdentity=true"),
+        "only one of accountKey, sasToken or managedIdentity",
+      ),
+      (
+        format!("{azure};managedIdentity=yes"),
+        "managedIdentity must be true or false",
+      ),
+      (
+        "provider=filesystem;uploadPath=containers/1".to_string(),
+        "rootFilePath is not set",
+      ),
+      ("provider=ftp".to_string(), "ftp is not supported"),
+    ];
+    for (connection, message) in errors {
+      match connection.parse::<StorageConnection>() {
+        Ok(_) => panic!("{connection} should not parse"),
+        Err(e) => assert!(e.to_string().contains(message), "{e}"),
+      }
+    }
+
+    // uploadPath is only needed by process_query, so it's checked when it's asked for
+    let parsed: StorageConnection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+    assert!(parsed.upload_path().is_err());
+  }
+
+  #[tokio::test]
+  async fn describe_parquet_with_filesystem() {
+    match process_upload(
+      "7".to_string(),
+      "DESCRIBE table_1".to_string(),
+      "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+        .to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.parquet".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    {
+      Ok(res) => {
+        assert!(res.contains("Temperature (K)"));
+      }
+      Err(e) => {
+        panic!("{}", e.reason);
+      }
+    };
+  }
+
+  #[tokio::test]
+  async fn query_parquet_and_csv_with_filesystem() {
+    let res = process_query(
+      "8".to_string(),
+      "SELECT p.\"Timestamp\", c.\"X (m)\" FROM table_1 p JOIN table_2 c ON p.\"Timestamp\" = c.\"Timestamp\" WHERE p.\"Temperature (K)\" > 5".to_string(),
+      "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+        .to_string(),
+      vec![
+        FileMetadata {
+          id: "1".to_string(),
+          file_name: "ten-entries.parquet".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        },
+        FileMetadata {
+          id: "2".to_string(),
+          file_name: "ten-entries.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        },
+      ],
+      None,
+    )
+    .await
+    .unwrap_or_else(|e| panic!("{}", e.reason));
+
+    let metadata: serde_json::Value = serde_json::from_str(&res).unwrap();
+    let path = format!(
+      "./test_files/timeseries2/containers/1/datasources/1/{}",
+      metadata["file_name"].as_str().unwrap()
+    );
+
+    // the parquet and csv files hold the same rows, so the join matches the 4 above 5 K
+    let ctx = SessionContext::new();
+    ctx
+      .register_csv("results", &path, CsvReadOptions::new())
+      .await
+      .unwrap();
+    let results = ctx
+      .sql("SELECT COUNT(*), SUM(\"X (m)\") FROM results")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    assert!(results.contains("| 4 "), "{results}");
+    assert!(results.contains("| 30 "), "{results}");
+
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn query_output_formats_with_filesystem() {
+    let outputs = [
+      ("csv", "gzip", "csv.gz"),
+      ("json", "zstd", "jsonl.zst"),
+      ("parquet", "gzip", "parquet"),
+      ("arrow", "lz4", "arrow"),
+    ];
+
+    for (format, compression, extension) in outputs {
+      let res = process_query(
+        "9".to_string(),
+        "SELECT * FROM table_1".to_string(),
+        "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+          .to_string(),
+        vec![FileMetadata {
+          id: "1".to_string(),
+          file_name: "ten-entries.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        }],
+        Some(ProcessQueryOptions {
+          output: Some(QueryOutputOptions {
+            format: Some(format.to_string()),
+            compression: Some(compression.to_string()),
+          }),
+          ..Default::default()
+        }),
+      )
+      .await
+      .unwrap_or_else(|e| panic!("{}", e.reason));
+
+      let metadata: serde_json::Value = serde_json::from_str(&res).unwrap();
+      assert_eq!(metadata["file_format"], format);
+      assert_eq!(metadata["compression"], compression);
+
+      let file_name = metadata["file_name"].as_str().unwrap();
+      assert!(file_name.ends_with(&format!(".{extension}")));
+
+      let path = format!("./test_files/timeseries2/containers/1/datasources/1/{file_name}");
+      let file_size = std::fs::metadata(&path).unwrap().len();
+      assert_eq!(metadata["file_size_bytes"], file_size);
+
+      // the file has to read back with the same rows it was written with
+      let ctx = SessionContext::new();
+      let extension = format!(".{extension}");
+      let results = match format {
+        "csv" => ctx
+          .read_csv(
+            &path,
+            CsvReadOptions::new()
+              .file_extension(&extension)
+              .file_compression_type(FileCompressionType::GZIP),
+          )
+          .await
+          .unwrap(),
+        "json" => ctx
+          .read_json(
+            &path,
+            NdJsonReadOptions::default()
+              .file_extension(&extension)
+              .file_compression_type(FileCompressionType::ZSTD),
+          )
+          .await
+          .unwrap(),
+        "parquet" => ctx
+          .read_parquet(&path, ParquetReadOptions::default())
+          .await
+          .unwrap(),
+        _ => ctx
+          .read_arrow(&path, ArrowReadOptions::default())
+          .await
+          .unwrap(),
+      };
+      assert_eq!(results.count().await.unwrap(), 10);
+
+      std::fs::remove_file(&path).unwrap();
+    }
+  }
+
+  #[tokio::test]
+  async fn query_output_rejects_unsupported_options() {
+    let options = [
+      ("xlsx", None),
+      ("csv", Some("lz4")),
+      ("arrow", Some("gzip")),
+    ];
+
+    for (format, compression) in options {
+      let res = process_query(
+        "10".to_string(),
+        "SELECT * FROM table_1".to_string(),
+        "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+          .to_string(),
+        vec![FileMetadata {
+          id: "1".to_string(),
+          file_name: "ten-entries.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        }],
+        Some(ProcessQueryOptions {
+          output: Some(QueryOutputOptions {
+            format: Some(format.to_string()),
+            compression: compression.map(str::to_string),
+          }),
+          ..Default::default()
+        }),
+      )
+      .await;
+
+      assert!(res.is_err(), "{format} with {compression:?} should be rejected");
+    }
+  }
+
+  #[tokio::test]
+  async fn stream_query_with_filesystem() {
+    let stream = stream_query(
+      "SELECT \"Temperature (K)\" FROM table_1 ORDER BY \"Temperature (K)\"".to_string(),
+      "provider=filesystem;rootFilePath=./test_files/timeseries2/".to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.parquet".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+      Some(QueryStreamOptions {
+        row_limit: Some(4),
+        ..Default::default()
+      }),
+    )
+    .await
+    .unwrap_or_else(|e| panic!("{}", e.reason));
+
+    let mut rows = 0;
+    while let Some(batch) = stream.next_ipc().await.unwrap() {
+      let reader = StreamReader::try_new(batch.as_slice(), None).unwrap();
+      for batch in reader {
+        rows += batch.unwrap().num_rows();
+      }
+    }
+    assert_eq!(rows, 4);
+
+    // an exhausted stream keeps returning nothing
+    assert!(stream.next_rows().await.unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn stream_query_rows_and_cancel_with_filesystem() {
+    let stream = stream_query(
+      "SELECT \"Temperature (K)\" FROM table_1 WHERE \"Temperature (K)\" < 2 ORDER BY \"Temperature (K)\""
+        .to_string(),
+      "provider=filesystem;rootFilePath=./test_files/timeseries2/".to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+      None,
+    )
+    .await
+    .unwrap_or_else(|e| panic!("{}", e.reason));
+
+    let rows: serde_json::Value =
+      serde_json::from_str(&stream.next_rows().await.unwrap().unwrap()).unwrap();
+    assert_eq!(rows.as_array().unwrap().len(), 2);
+    assert!(rows[0].get("Temperature (K)").is_some());
+
+    stream.cancel();
+    assert!(stream.next_ipc().await.unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn query_guardrails_with_filesystem() {
+    let run = |query: &str, limits: Option<QueryLimits>| {
+      process_query(
+        "15".to_string(),
+        query.to_string(),
+        "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+          .to_string(),
+        vec![FileMetadata {
+          id: "1".to_string(),
+          file_name: "ten-entries.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        }],
+        Some(ProcessQueryOptions {
+          limits,
+          ..Default::default()
+        }),
+      )
+    };
+
+    let rejected = [
+      ("COPY table_1 TO 'copied.csv'", "COPY"),
+      (
+        "CREATE EXTERNAL TABLE t STORED AS CSV LOCATION 'ten-entries.csv'",
+        "CREATE EXTERNAL TABLE",
+      ),
+      ("INSERT INTO table_1 VALUES (1)", "INSERT"),
+      ("DROP TABLE table_1", "DROP"),
+      ("SET datafusion.execution.batch_size = 1", "SET"),
+    ];
+    for (query, keyword) in rejected {
+      let e = run(query, None).await.unwrap_err();
+      assert_eq!(e.status, napi::Status::InvalidArg, "{query}");
+      assert!(e.reason.contains(keyword), "{}", e.reason);
+    }
+
+    let e = run(
+      "SELECT array_agg(value) FROM generate_series(1, 10000000)",
+      Some(QueryLimits {
+        timeout_ms: None,
+        memory_limit_mb: Some(1),
+      }),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.status, napi::Status::InvalidArg);
+    assert!(e.reason.contains("memory"), "{}", e.reason);
+
+    let e = run(
+      "SELECT * FROM generate_series(1, 100000000)",
+      Some(QueryLimits {
+        timeout_ms: Some(50),
+        memory_limit_mb: None,
+      }),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.status, napi::Status::InvalidArg);
+    assert!(e.reason.contains("50ms timeout"), "{}", e.reason);
+
+    assert!(run("SELECT * FROM table_1", None).await.is_ok());
+
+    // streamed queries are held to the same limits, however far into the results they get
+    let stream = |query: &str, limits: QueryLimits| {
+      stream_query(
+        query.to_string(),
+        "provider=filesystem;rootFilePath=./test_files/timeseries2/".to_string(),
+        vec![],
+        Some(QueryStreamOptions {
+          limits: Some(limits),
+          ..Default::default()
+        }),
+      )
+    };
+    let next = |query: &'static str, limits: QueryLimits| async move {
+      match stream(query, limits).await {
+        Ok(stream) => stream.next_rows().await.map(|_| ()),
+        Err(e) => Err(e),
+      }
+    };
+
+    let e = next(
+      "SELECT array_agg(value) FROM generate_series(1, 10000000)",
+      QueryLimits {
+        timeout_ms: None,
+        memory_limit_mb: Some(1),
+      },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.status, napi::Status::InvalidArg);
+    assert!(e.reason.contains("memory"), "{}", e.reason);
+
+    let rows = stream(
+      "SELECT * FROM generate_series(1, 100000000)",
+      QueryLimits {
+        timeout_ms: Some(50),
+        memory_limit_mb: None,
+      },
+    )
+    .await
+    .unwrap();
+    let e = loop {
+      match rows.next_rows().await {
+        Ok(Some(_)) => continue,
+        Ok(None) => panic!("the query finished within its timeout"),
+        Err(e) => break e,
+      }
+    };
+    assert_eq!(e.status, napi::Status::InvalidArg);
+    assert!(e.reason.contains("50ms timeout"), "{}", e.reason);
+  }
+
+  #[tokio::test]
+  async fn query_engine_reuses_and_invalidates_tables() {
+    let engine = TimeseriesQueryEngine::new(
+      "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+        .to_string(),
+      None,
+    )
+    .unwrap_or_else(|e| panic!("{}", e.reason));
+
+    let csv = FileMetadata {
+      id: "1".to_string(),
+      file_name: "ten-entries.csv".to_string(),
+      file_path: "containers/1/datasources/1".to_string(),
+      partition_columns: None,
+      csv_options: None,
+      glob: None,
+    };
+    let parquet = FileMetadata {
+      id: "2".to_string(),
+      file_name: "ten-entries.parquet".to_string(),
+      file_path: "containers/1/datasources/1".to_string(),
+      partition_columns: None,
+      csv_options: None,
+      glob: None,
+    };
+
+    let description = engine
+      .process_upload(
+        "14".to_string(),
+        "DESCRIBE table_1".to_string(),
+        vec![csv.clone()],
+      )
+      .await
+      .unwrap_or_else(|e| panic!("{}", e.reason));
+    assert!(description.contains("Temperature (K)"));
+
+    // table_1 is still registered from the first call, only table_2 is new
+    let stream = engine
+      .stream_query(
+        "SELECT c.\"X (m)\" FROM table_1 c JOIN table_2 p ON c.\"Temperature (K)\" = p.\"Temperature (K)\"".to_string(),
+        vec![csv.clone(), parquet.clone()],
+        None,
+      )
+      .await
+      .unwrap_or_else(|e| panic!("{}", e.reason));
+    let mut rows = 0;
+    while let Some(batch) = stream.next_ipc().await.unwrap() {
+      for batch in StreamReader::try_new(batch.as_slice(), None).unwrap() {
+        rows += batch.unwrap().num_rows();
+      }
+    }
+    assert_eq!(rows, 10);
+    assert_eq!(engine.registered_files().await, vec!["1", "2"]);
+
+    // a call only sees the files it was given, even if the engine has others registered
+    assert!(engine
+      .stream_query("SELECT * FROM table_2".to_string(), vec![csv.clone()], None)
+      .await
+      .is_err());
+    let description = engine
+      .process_upload(
+        "16".to_string(),
+        "DESCRIBE table_1; DESCRIBE table_2".to_string(),
+        vec![csv.clone()],
+      )
+      .await
+      .unwrap_or_else(|e| panic!("{}", e.reason));
+    let description: serde_json::Value = serde_json::from_str(&description).unwrap();
+    assert_eq!(description["descriptions"].as_array().unwrap().len(), 1);
+    assert_eq!(description["descriptions"][0]["file_id"], "1");
+    assert_eq!(description["errors"].as_array().unwrap().len(), 1);
+
+    // a call can bring limits of its own
+    let e = engine
+      .process_query(
+        "17".to_string(),
+        "SELECT array_agg(value) FROM generate_series(1, 10000000)".to_string(),
+        vec![],
+        Some(ProcessQueryOptions {
+          limits: Some(QueryLimits {
+            timeout_ms: None,
+            memory_limit_mb: Some(1),
+          }),
+          ..Default::default()
+        }),
+      )
+      .await
+      .unwrap_err();
+    assert_eq!(e.status, napi::Status::InvalidArg);
+    assert!(e.reason.contains("memory"), "{}", e.reason);
+
+    // the same id pointing at a different file replaces the old table
+    let replaced = FileMetadata {
+      file_name: "ten-entries.tdms".to_string(),
+      ..csv.clone()
+    };
+    let description = engine
+      .process_upload(
+        "15".to_string(),
+        "DESCRIBE table_1".to_string(),
+        vec![replaced],
+      )
+      .await
+      .unwrap_or_else(|e| panic!("{}", e.reason));
+    assert!(description.contains("Status") && !description.contains("Velocity[i]"));
+
+    assert!(engine.invalidate("2".to_string()).await.unwrap());
+    assert!(!engine.invalidate("2".to_string()).await.unwrap());
+    assert!(engine
+      .stream_query("SELECT * FROM table_2".to_string(), vec![], None)
+      .await
+      .is_err());
+
+    engine.clear().await.unwrap();
+    assert!(engine.registered_files().await.is_empty());
+  }
+
+  #[tokio::test]
+  async fn parquet_pushdown_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.parquet".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    .unwrap();
+
+    let plan = ctx
+      .sql("EXPLAIN SELECT \"X (m)\" FROM table_1 WHERE \"Temperature (K)\" > 5")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let plan = pretty_format_batches(&plan).unwrap().to_string();
+
+    // the filter should be evaluated inside the parquet scan, only reading the projected column back
+    assert!(plan.contains("pruning_predicate="));
+    assert!(plan.contains("projection=[X (m)], file_type=parquet"));
+    assert!(!plan.contains("FilterExec"));
+  }
+
+  #[tokio::test]
+  async fn describe_tdms_with_filesystem() {
+    match process_upload(
+      "9".to_string(),
+      "DESCRIBE table_1".to_string(),
+      "provider=filesystem;uploadPath=containers/1/datasources/1;rootFilePath=./test_files/timeseries2/"
+        .to_string(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.tdms".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    {
+      Ok(res) => {
+        assert!(res.contains("Temperature (K)"));
+        assert!(res.contains("Timestamp(Nanosecond"));
+      }
+      Err(e) => {
+        panic!("{}", e.reason);
+      }
+    };
+  }
+
+  #[tokio::test]
+  async fn query_tdms_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![
+        FileMetadata {
+          id: "1".to_string(),
+          file_name: "ten-entries.tdms".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        },
+        FileMetadata {
+          id: "2".to_string(),
+          file_name: "ten-entries.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          csv_options: None,
+          glob: None,
+        },
+      ],
+    )
+    .await
+    .unwrap();
+
+    // channel properties are carried along as field metadata
+    let schema = ctx.table("table_1").await.unwrap().schema().as_arrow().clone();
+    let temperature = schema.field_with_name("Temperature (K)").unwrap();
+    assert_eq!(temperature.metadata().get("unit_string").unwrap(), "K");
+    assert_eq!(schema.metadata().get("author").unwrap(), "test stand 4");
+
+    // the tdms file holds the same values as the csv, just split over two segments
+    let results = ctx
+      .sql(
+        "SELECT t.\"Status\", t.\"Timestamp\" FROM table_1 t JOIN table_2 c ON t.\"X (m)\" = c.\"X (m)\" WHERE c.\"Temperature (K)\" = 7",
+      )
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("ok-7"));
+    assert!(results.contains("2024-07-30T07:32:27.000000700Z"));
+
+    let results = ctx
+      .sql("SELECT COUNT(*), SUM(\"Temperature (K)\") FROM table_1")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("| 10 "));
+    assert!(results.contains("| 45.0 "));
+  }
+
+  #[tokio::test]
+  async fn query_tdms_over_memory_limit_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    // too little memory for even the ten decoded timestamps
+    let runtime = datafusion::execution::runtime_env::RuntimeEnvBuilder::new()
+      .with_memory_limit(64, 1.0)
+      .build_arc()
+      .unwrap();
+    let ctx = crate::timeseries::datafusion_session::populate_session_with_runtime(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.tdms".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+      runtime,
+    )
+    .await
+    .unwrap();
+
+    let error = ctx
+      .sql("SELECT \"Timestamp\" FROM table_1")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap_err();
+    assert!(matches!(
+      error.find_root(),
+      datafusion::error::DataFusionError::ResourcesExhausted(_)
+    ));
+  }
+
+  #[tokio::test]
+  async fn query_csv_dialects_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    let column = |name: &str, data_type: &str| CsvColumn {
+      name: name.to_string(),
+      data_type: data_type.to_string(),
+      nullable: None,
+    };
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![
+        FileMetadata {
+          id: "1".to_string(),
+          file_name: "lab-export.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          glob: None,
+          csv_options: Some(CsvDialect {
+            delimiter: Some(";".to_string()),
+            has_header: Some(false),
+            comment: Some("#".to_string()),
+            null_value: Some("N/A".to_string()),
+            schema: Some(vec![
+              column("id", "Int64"),
+              column("timestamp", "Timestamp(Nanosecond, None)"),
+              column("reading", "Float64"),
+            ]),
+            ..Default::default()
+          }),
+        },
+        FileMetadata {
+          id: "2".to_string(),
+          file_name: "lab-export-tabs.csv".to_string(),
+          file_path: "containers/1/datasources/1".to_string(),
+          partition_columns: None,
+          glob: None,
+          csv_options: Some(CsvDialect {
+            delimiter: Some("\t".to_string()),
+            schema_infer_max_records: Some(10),
+            ..Default::default()
+          }),
+        },
+      ],
+    )
+    .await
+    .unwrap();
+
+    let results = ctx
+      .sql("SELECT COUNT(*), COUNT(reading), SUM(reading), MAX(timestamp) FROM table_1")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("| 3 "), "{results}");
+    assert!(results.contains("| 2 "), "{results}");
+    assert!(results.contains("| 41.75 "), "{results}");
+    assert!(results.contains("2024-07-30T07:32:29"), "{results}");
+    // the text the view reads the file as isn't a table of its own
+    assert_eq!(
+      ctx.catalog("datafusion").unwrap().schema("public").unwrap().table_names().len(),
+      2
+    );
+
+    let results = ctx
+      .sql("SELECT label FROM table_2 WHERE id = 1")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("a\tb"), "{results}");
+
+    let bad = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "lab-export.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        glob: None,
+        csv_options: Some(CsvDialect {
+          delimiter: Some(";;".to_string()),
+          ..Default::default()
+        }),
+      }],
+    )
+    .await;
+    match bad {
+      Ok(_) => panic!("a two character delimiter should be rejected"),
+      Err(e) => assert!(e.to_string().contains("single ASCII character")),
+    }
+  }
+
+  #[tokio::test]
+  async fn query_partitioned_glob_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    let partition = |name: &str, data_type: &str| PartitionColumn {
+      name: name.to_string(),
+      data_type: Some(data_type.to_string()),
+    };
+    let hourly = FileMetadata {
+      id: "1".to_string(),
+      file_name: "**/*.csv".to_string(),
+      file_path: "containers/1/datasources/1/hourly".to_string(),
+      partition_columns: Some(vec![
+        partition("year", "Int32"),
+        // zero padded in the directory names
+        partition("month", "Utf8"),
+        partition("day", "Int32"),
+      ]),
+      csv_options: None,
+      glob: Some(true),
+    };
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![
+        hourly.clone(),
+        FileMetadata {
+          id: "2".to_string(),
+          partition_columns: None,
+          ..hourly.clone()
+        },
+      ],
+    )
+    .await
+    .unwrap();
+
+    let results = ctx
+      .sql("SELECT COUNT(*), SUM(reading) FROM table_1 WHERE year = 2024 AND month = '07' AND day = 30")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("| 4 "), "{results}");
+    assert!(results.contains("| 121.5 "), "{results}");
+
+    // left out, the partition columns are still picked up from the directory names
+    let results = ctx
+      .sql("SELECT day, COUNT(*) FROM table_2 GROUP BY day ORDER BY day")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("| 30  | 4 "), "{results}");
+    assert!(results.contains("| 31  | 2 "), "{results}");
+
+    let tdms = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        file_name: "*.tdms".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        ..hourly
+      }],
+    )
+    .await;
+    assert!(tdms.is_err());
+  }
+
+  #[tokio::test]
+  async fn query_file_names_with_glob_characters_with_filesystem() {
+    let root = std::env::temp_dir().join(format!("deeplynx_glob_names_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("run[1].csv"), "reading\n1\n2\n").unwrap();
+    std::fs::write(root.join("run1.csv"), "reading\n10\n").unwrap();
+    std::fs::write(root.join("data?.csv"), "reading\n100\n").unwrap();
+    std::fs::write(root.join("dataX.csv"), "reading\n1000\n").unwrap();
+
+    let storage_connection = format!("provider=filesystem;rootFilePath={}", root.display())
+      .parse()
+      .unwrap();
+    let file = |id: &str, file_name: &str, glob: Option<bool>| FileMetadata {
+      id: id.to_string(),
+      file_name: file_name.to_string(),
+      file_path: ".".to_string(),
+      csv_options: None,
+      partition_columns: None,
+      glob,
+    };
+
+    // without glob the names are read as they are, with it they match other files
+    let ctx = populate_session(
+      &storage_connection,
+      vec![
+        file("1", "run[1].csv", None),
+        file("2", "data?.csv", None),
+        file("3", "data?.csv", Some(true)),
+      ],
+    )
+    .await
+    .unwrap();
+    let sum = |table: &'static str| {
+      let ctx = ctx.clone();
+      async move {
+        let results = ctx
+          .sql(format!("SELECT SUM(reading) FROM {table}").as_str())
+          .await
+          .unwrap()
+          .collect()
+          .await
+          .unwrap();
+        pretty_format_batches(&results).unwrap().to_string()
+      }
+    };
+    assert!(sum("table_1").await.contains("| 3 "));
+    assert!(sum("table_2").await.contains("| 100 "));
+    assert!(sum("table_3").await.contains("| 1100 "));
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[tokio::test]
+  async fn query_time_functions_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    let column = |name: &str, data_type: &str| CsvColumn {
+      name: name.to_string(),
+      data_type: data_type.to_string(),
+      nullable: None,
+    };
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "lab-export.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        glob: None,
+        csv_options: Some(CsvDialect {
+          delimiter: Some(";".to_string()),
+          has_header: Some(false),
+          comment: Some("#".to_string()),
+          null_value: Some("N/A".to_string()),
+          schema: Some(vec![
+            column("id", "Int64"),
+            column("timestamp", "Timestamp(Nanosecond, None)"),
+            column("reading", "Float64"),
+          ]),
+          ..Default::default()
+        }),
+      }],
+    )
+    .await
+    .unwrap();
+
+    let query = |sql: &'static str| {
+      let ctx = ctx.clone();
+      async move {
+        let results = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        pretty_format_batches(&results).unwrap().to_string()
+      }
+    };
+
+    // the missing reading at 07:32:28 sits halfway between 20.5 and 21.25
+    let results = query(
+      "SELECT id, locf(reading) OVER (ORDER BY timestamp) AS carried,
+          interpolate(reading, timestamp) OVER (ORDER BY timestamp) AS interpolated,
+          moving_average(reading, 2) OVER (ORDER BY timestamp) AS averaged
+        FROM table_1 ORDER BY id",
+    )
+    .await;
+
+    assert!(results.contains("| 2  | 20.5    | 20.875       | 20.5     |"), "{results}");
+    assert!(results.contains("| 3  | 21.25   | 21.25        | 21.25    |"), "{results}");
+
+    // nothing was read between 07:32:30 and 07:32:31, the left join leaves a null there
+    let results = query(
+      "SELECT b.bucket, r.readings FROM time_buckets('2024-07-30T07:32:26', '2024-07-30T07:32:32', INTERVAL '2 seconds') b
+        LEFT JOIN (
+          SELECT time_bucket(INTERVAL '2 seconds', timestamp) AS bucket, COUNT(reading) AS readings
+          FROM table_1 GROUP BY 1
+        ) r ON b.bucket = r.bucket
+        ORDER BY b.bucket",
+    )
+    .await;
+
+    assert!(results.contains("| 2024-07-30T07:32:26 | 1        |"), "{results}");
+    assert!(results.contains("| 2024-07-30T07:32:28 | 1        |"), "{results}");
+    assert!(results.contains("| 2024-07-30T07:32:30 |          |"), "{results}");
+
+    // a spike in the middle of an otherwise flat series survives the downsampling
+    let results = query(
+      "SELECT p['time'] AS time, p['value'] AS value FROM (
+          SELECT unnest(lttb(t, v, 3)) AS p FROM (
+            VALUES (1, 0.0), (2, 0.0), (3, 10.0), (4, 0.0), (5, 0.0), (6, 0.0)
+          ) AS s(t, v)
+        ) ORDER BY time",
+    )
+    .await;
+
+    assert!(results.contains("| 1    | 0.0   |"), "{results}");
+    assert!(results.contains("| 3    | 10.0  |"), "{results}");
+    assert!(results.contains("| 6    | 0.0   |"), "{results}");
+    assert!(!results.contains("| 2    |"), "{results}");
+
+    let too_many = ctx
+      .sql("SELECT * FROM time_buckets('2000-01-01', '2024-01-01', INTERVAL '1 second')")
+      .await;
+    assert!(too_many.is_err());
+
+    // intervals and spans past what nanoseconds hold are errors rather than overflowing
+    for query in [
+      "SELECT * FROM time_buckets('2000-01-01', '2024-01-01', INTERVAL '200000 days')",
+      "SELECT * FROM time_buckets('2262-04-11', '1677-09-22', INTERVAL '1 day')",
+      "SELECT * FROM time_buckets('1677-09-22', '2262-04-11', INTERVAL '1 day')",
+    ] {
+      let e = ctx.sql(query).await.unwrap_err().to_string();
+      assert!(e.contains("too"), "{e}");
+    }
+  }
+
+  #[tokio::test]
+  async fn query_legacy_table_with_filesystem() {
+    let db_connection_string = Configuration::from_path(Some(String::from(".config.yml")))
+      .unwrap()
+      .db_connection_string
+      .unwrap();
+    let pool = PgPool::connect(db_connection_string.as_str()).await.unwrap();
+
+    sqlx::query("DROP TABLE IF EXISTS y_9014")
+      .execute(&pool)
+      .await
+      .unwrap();
+    sqlx::query(
+      "CREATE TABLE y_9014 (
+        \"reading_id\" integer DEFAULT NULL,
+        \"measured_at\" timestamp DEFAULT NULL,
+        \"pressure\" numeric DEFAULT NULL,
+        \"label\" text DEFAULT NULL,
+        _metadata jsonb DEFAULT NULL,
+        _import_id bigint DEFAULT NULL
+      )",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+      "INSERT INTO y_9014 (reading_id, measured_at, pressure, label, _metadata)
+        SELECT i, '2024-07-30 07:32:27'::timestamp + i * interval '1 second', i * 1.5, 'reading ' || i, '{\"a\": 1}'
+        FROM generate_series(0, 9) i",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let ctx = populate_session(
+      &"provider=filesystem;rootFilePath=./test_files/timeseries2/"
+        .parse()
+        .unwrap(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    .unwrap();
+    register_legacy_tables(
+      &ctx,
+      &LegacyTables {
+        db_connection_string: db_connection_string.clone(),
+        data_source_ids: vec!["9014".to_string()],
+      },
+    )
+    .await
+    .unwrap();
+    // later queries reuse the connections the first one opened
+    let legacy_tables = LegacyTables {
+      db_connection_string: db_connection_string.clone(),
+      data_source_ids: vec![],
+    };
+    assert!(legacy_tables.pool().unwrap().size() > 0);
+
+    let query = |sql: &'static str| {
+      let ctx = ctx.clone();
+      async move {
+        let results = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        pretty_format_batches(&results).unwrap().to_string()
+      }
+    };
+
+    let results = query(
+      "SELECT COUNT(*), SUM(y.pressure), MAX(y._metadata) FROM y_9014 y
+        JOIN table_1 t ON t.\"Temperature (K)\" = y.reading_id
+        WHERE y.reading_id >= 5",
+    )
+    .await;
+    assert!(results.contains("| 5 "), "{results}");
+    assert!(results.contains("| 52.5 "), "{results}");
+    assert!(results.contains(r#"{"a": 1}"#), "{results}");
+
+    // both the filter and the limit are run by Postgres
+    let plan = query(
+      "EXPLAIN SELECT reading_id FROM y_9014
+        WHERE measured_at > '2024-07-30 07:32:30' AND label LIKE 'reading%' LIMIT 2",
+    )
+    .await;
+    assert!(!plan.contains("FilterExec"), "{plan}");
+    let results = query(
+      "SELECT reading_id FROM y_9014
+        WHERE measured_at > '2024-07-30 07:32:30' AND label LIKE 'reading%' ORDER BY reading_id LIMIT 2",
+    )
+    .await;
+    assert!(results.contains("| 4 "), "{results}");
+    assert!(results.contains("| 5 "), "{results}");
+    assert!(!results.contains("| 6 "), "{results}");
+
+    // text is ordered by the database's collation, so that's left to DataFusion
+    let plan = query("EXPLAIN SELECT label FROM y_9014 WHERE label > 'reading 7'").await;
+    assert!(plan.contains("FilterExec"), "{plan}");
+    let results = query("SELECT COUNT(*) FROM y_9014 WHERE label > 'reading 7'").await;
+    assert!(results.contains("| 2 "), "{results}");
+
+    // a timestamp past what Postgres' microseconds hold is left for DataFusion to filter
+    let provider = ctx.table_provider("y_9014").await.unwrap();
+    let too_late = datafusion::prelude::col("measured_at").lt(datafusion::prelude::lit(
+      datafusion::common::ScalarValue::TimestampSecond(Some(9223372036855), None),
+    ));
+    assert_eq!(
+      provider.supports_filters_pushdown(&[&too_late]).unwrap(),
+      vec![datafusion::logical_expr::TableProviderFilterPushDown::Unsupported]
+    );
+
+    let missing = register_legacy_tables(
+      &ctx,
+      &LegacyTables {
+        db_connection_string,
+        data_source_ids: vec!["missing".to_string()],
+      },
+    )
+    .await;
+    assert!(missing.is_err());
+
+    sqlx::query("DROP TABLE y_9014").execute(&pool).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn query_graph_snapshot_with_filesystem() {
+    let nodes = DataFrame::new(vec![
+      Series::new("id".into(), vec![1u64, 2, 3]).into(),
+      Series::new("container_id".into(), vec![1u64, 1, 1]).into(),
+      Series::new("metatype_id".into(), vec![7u64, 7, 8]).into(),
+      Series::new("data_source_id".into(), vec![1u64, 1, 1]).into(),
+      Series::new("original_data_id".into(), vec!["0", "1", "pump"]).into(),
+      Series::new("properties".into(), vec!["{}", "{}", "{}"]).into(),
+      Series::new("metatype_name".into(), vec!["Sensor", "Sensor", "Pump"]).into(),
+      Series::new("metatype_uuid".into(), vec!["a", "a", "b"]).into(),
+    ])
+    .unwrap();
+    let edges = DataFrame::new(vec![
+      Series::new("id".into(), vec![10u64, 11]).into(),
+      Series::new("origin_id".into(), vec![Some(1u64), Some(2)]).into(),
+      Series::new("destination_id".into(), vec![Some(3u64), None]).into(),
+      Series::new("metatype_relationship_name".into(), vec![Some("monitors"), None]).into(),
+    ])
+    .unwrap();
+
+    let ctx = populate_session(
+      &"provider=filesystem;rootFilePath=./test_files/timeseries2/"
+        .parse()
+        .unwrap(),
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "ten-entries.csv".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    .unwrap();
+    let tables = register_snapshot_tables(
+      &ctx,
+      to_record_batch(&nodes).unwrap(),
+      to_record_batch(&edges).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(tables, vec!["nodes", "edges"]);
+
+    // the readings of each sensor along with the pump it monitors
+    let results = ctx
+      .sql(
+        "SELECT sensor.original_data_id, pump.metatype_name, COUNT(*) FROM table_1 t
+          JOIN nodes sensor ON sensor.original_data_id = CAST(t.\"Temperature (K)\" AS VARCHAR)
+          JOIN edges e ON e.origin_id = sensor.id AND e.metatype_relationship_name = 'monitors'
+          JOIN nodes pump ON pump.id = e.destination_id
+          GROUP BY 1, 2",
+      )
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+
+    assert!(results.contains("| 0                | Pump          | 1 "), "{results}");
+    assert!(!results.contains("| 1                |"), "{results}");
+  }
+
+  #[tokio::test]
+  async fn ingest_parquet_with_filesystem() {
+    let root = std::env::temp_dir().join(format!("deeplynx_ingest_{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let storage_connection: StorageConnection =
+      format!("provider=filesystem;rootFilePath={}", root.display())
+        .parse()
+        .unwrap();
+
+    let column = |name: &str, data_type: &str| CsvColumn {
+      name: name.to_string(),
+      data_type: data_type.to_string(),
+      nullable: None,
+    };
+    let options = ParquetIngestionOptions {
+      id: "1".to_string(),
+      file_path: "containers/1/datasources/1/ingested".to_string(),
+      format: None,
+      schema: vec![
+        column("sensor", "Utf8"),
+        column("day", "Int32"),
+        column("reading", "Float64"),
+      ],
+      csv_options: Some(CsvDialect {
+        delimiter: Some(";".to_string()),
+        null_value: Some("NA".to_string()),
+        ..Default::default()
+      }),
+      partition_by: Some(vec!["day".to_string()]),
+    };
+
+    let csv = "sensor;day;reading\na;30;1.5\nb;30;2.5\na;31;NA\nb;31;4\n";
+    let partitioned = ingest_parquet(
+      &storage_connection,
+      std::io::Cursor::new(csv),
+      options.clone(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(partitioned.file_name, "**/*.parquet");
+    assert!(root
+      .join("containers/1/datasources/1/ingested/day=31")
+      .is_dir());
+
+    let json = "{\"sensor\": \"c\", \"day\": 1, \"reading\": 10}\n{\"sensor\": \"d\", \"day\": 2, \"reading\": null}\n";
+    let unpartitioned = ingest_parquet(
+      &storage_connection,
+      std::io::Cursor::new(json),
+      ParquetIngestionOptions {
+        id: "2".to_string(),
+        file_path: "containers/1/datasources/1/ingested_json".to_string(),
+        format: Some("json".to_string()),
+        partition_by: None,
+        ..options.clone()
+      },
+    )
+    .await
+    .unwrap();
+    assert!(unpartitioned.file_name.ends_with(".parquet"));
+    assert!(unpartitioned.partition_columns.is_none());
+
+    let ctx = populate_session(&storage_connection, vec![partitioned, unpartitioned])
+      .await
+      .unwrap();
+    let results = ctx
+      .sql("SELECT day, COUNT(reading), SUM(reading) FROM table_1 GROUP BY day ORDER BY day")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    assert!(results.contains("| 30  | 2 "), "{results}");
+    assert!(results.contains("| 31  | 1 "), "{results}");
+    assert!(results.contains("| 4.0 "), "{results}");
+
+    let results = ctx
+      .sql("SELECT COUNT(*), SUM(reading) FROM table_2")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    assert!(results.contains("| 2 "), "{results}");
+    assert!(results.contains("| 10.0 "), "{results}");
+
+    // partition values read back the same as they were written
+    let by_sensor = ParquetIngestionOptions {
+      id: "3".to_string(),
+      file_path: "containers/1/datasources/1/by_sensor".to_string(),
+      partition_by: Some(vec!["sensor".to_string()]),
+      ..options.clone()
+    };
+    let csv = "sensor;day;reading\nrack 2.a-1;30;1.5\n";
+    let by_sensor = ingest_parquet(
+      &storage_connection,
+      std::io::Cursor::new(csv),
+      by_sensor.clone(),
+    )
+    .await
+    .unwrap();
+    let sensor_ctx = populate_session(&storage_connection, vec![by_sensor.clone()])
+      .await
+      .unwrap();
+    let results = sensor_ctx
+      .sql("SELECT sensor FROM table_3")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    assert!(results.contains("| rack 2.a-1 |"), "{results}");
+
+    // values that would have to be escaped in the path are rejected rather than read back changed
+    for sensor in ["rack/2", "50%", "capteur é"] {
+      let csv = format!("sensor;day;reading\n{sensor};30;1.5\n");
+      let escaped = ingest_parquet(
+        &storage_connection,
+        std::io::Cursor::new(csv),
+        ParquetIngestionOptions {
+          file_path: "containers/1/datasources/1/escaped".to_string(),
+          partition_by: Some(vec!["sensor".to_string()]),
+          ..options.clone()
+        },
+      )
+      .await;
+      assert!(
+        escaped.unwrap_err().to_string().contains("can't be written to a path"),
+        "{sensor}"
+      );
+    }
+
+    // a row that doesn't fit the schema fails the ingestion without leaving files behind
+    let bad_rows = ParquetIngestionOptions {
+      file_path: "containers/1/datasources/1/bad_rows".to_string(),
+      ..options.clone()
+    };
+    let csv = "sensor;day;reading\na;30;1.5\nb;30;high\n";
+    assert!(
+      ingest_parquet(&storage_connection, std::io::Cursor::new(csv), bad_rows)
+        .await
+        .is_err()
+    );
+    let bad_rows = root.join("containers/1/datasources/1/bad_rows");
+    assert!(!bad_rows.exists() || std::fs::read_dir(&bad_rows).unwrap().next().is_none());
+
+    let csv = "sensor;reading;day\na;1.5;30\n";
+    let header = ingest_parquet(
+      &storage_connection,
+      std::io::Cursor::new(csv),
+      options.clone(),
+    )
+    .await;
+    assert!(header.unwrap_err().to_string().contains("does not match"));
+
+    // a partitioned ingestion without any rows has nothing to query either
+    let empty = ingest_parquet(
+      &storage_connection,
+      std::io::Cursor::new("sensor;day;reading\n"),
+      options.clone(),
+    )
+    .await;
+    assert!(empty.unwrap_err().to_string().contains("no parquet file"));
+
+    let float_partition = ingest_parquet(
+      &storage_connection,
+      std::io::Cursor::new(""),
+      ParquetIngestionOptions {
+        partition_by: Some(vec!["reading".to_string()]),
+        ..options
+      },
+    )
+    .await;
+    assert!(float_partition.is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[tokio::test]
+  async fn query_interleaved_tdms_with_filesystem() {
+    let storage_connection = "provider=filesystem;rootFilePath=./test_files/timeseries2/"
+      .parse()
+      .unwrap();
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "interleaved.tdms".to_string(),
+        file_path: "containers/1/datasources/1".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    .unwrap();
+
+    // more than one group means the columns are qualified by their group name, and the shorter
+    // channels are padded with nulls
+    let results = ctx
+      .sql("SELECT \"Run 1/Pressure\", \"Run 1/Valve Open\", \"Run 2/Pressure\" FROM table_1")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+    let results = pretty_format_batches(&results).unwrap().to_string();
+    let expected = [
+      "+----------------+------------------+----------------+",
+      "| Run 1/Pressure | Run 1/Valve Open | Run 2/Pressure |",
+      "+----------------+------------------+----------------+",
+      "| 0.0            | true             | 100.0          |",
+      "| 1.5            | false            | 101.0          |",
+      "| 3.0            | true             | 102.0          |",
+      "| 4.5            | false            | 103.0          |",
+      "+----------------+------------------+----------------+",
+    ];
+
+    assert_eq!(results, expected.join("\n"));
+  }
+
+  #[test]
+  fn tdms_value_count_overflow() {
+    // a single segment whose only channel claims more doubles than fit in memory
+    let path = "/'group'/'channel'";
+    let mut metadata = vec![];
+    metadata.extend(1_u32.to_le_bytes());
+    metadata.extend((path.len() as u32).to_le_bytes());
+    metadata.extend(path.as_bytes());
+    metadata.extend(20_u32.to_le_bytes());
+    metadata.extend(0x0A_u32.to_le_bytes());
+    metadata.extend(1_u32.to_le_bytes());
+    metadata.extend((u64::MAX / 2).to_le_bytes());
+    metadata.extend(0_u32.to_le_bytes());
+
+    let mut bytes = b"TDSm".to_vec();
+    bytes.extend(((1_u32 << 1) | (1 << 2) | (1 << 3)).to_le_bytes());
+    bytes.extend(4713_u32.to_le_bytes());
+    bytes.extend((metadata.len() as u64).to_le_bytes());
+    bytes.extend((metadata.len() as u64).to_le_bytes());
+    bytes.extend(metadata);
+
+    let error = crate::timeseries::tdms_reader::TdmsFile::parse(&bytes).err().unwrap();
+    assert!(error.to_string().contains("more values than can be addressed"));
+  }
+}
+
+#[cfg(all(test, feature = "hdf5"))]
+mod hdf5_tests {
+  use crate::timeseries::{datafusion_session::populate_session, file_metadata::FileMetadata};
+  use datafusion::arrow::array::{AsArray, RecordBatch};
+  use datafusion::arrow::datatypes::{Float64Type, Int64Type};
+
+  #[tokio::test]
+  async fn query_hdf5_groups_with_filesystem() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("runs")).unwrap();
+
+    {
+      let file = hdf5::File::create(dir.path().join("runs/simulation.hdf5")).unwrap();
+      file
+        .new_dataset_builder()
+        .with_data(&[1_i64, 2, 3, 4])
+        .create("step")
+        .unwrap();
+      file
+        .new_dataset_builder()
+        .with_data(&[10.5_f64, 11.5, 12.5, 13.5])
+        .create("temperature")
+        .unwrap();
+
+      let group = file.create_group("Run 2").unwrap();
+      group
+        .new_dataset_builder()
+        .with_data(&[1_i64, 2, 3])
+        .create("step")
+        .unwrap();
+      group
+        .new_dataset_builder()
+        .with_data(&[0.5_f64, 1.5, 2.5])
+        .create("pressure")
+        .unwrap();
+    }
+
+    let storage_connection = format!("provider=filesystem;rootFilePath={}", dir.path().display())
+      .parse()
+      .unwrap();
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "simulation.hdf5".to_string(),
+        file_path: "runs".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    .unwrap();
+
+    // the root group and the nested group are both registered as tables and can be joined
+    let results: Vec<RecordBatch> = ctx
+      .sql(
+        "SELECT COUNT(*) AS matched, SUM(t.temperature + r.pressure) AS total FROM table_1 t JOIN table_1_run_2 r ON t.step = r.step",
+      )
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+
+    assert_eq!(results[0].column(0).as_primitive::<Int64Type>().value(0), 3);
+    assert_eq!(
+      results[0].column(1).as_primitive::<Float64Type>().value(0),
+      39.0
+    );
+  }
+
+  #[tokio::test]
+  async fn query_hdf5_fixed_strings_with_filesystem() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("runs")).unwrap();
+
+    {
+      let file = hdf5::File::create(dir.path().join("runs/labels.hdf5")).unwrap();
+      file
+        .new_dataset_builder()
+        .with_data(&[1_i64, 2])
+        .create("step")
+        .unwrap();
+      file
+        .new_dataset_builder()
+        .with_data(&[
+          hdf5::types::FixedAscii::<20>::from_ascii("warm up").unwrap(),
+          hdf5::types::FixedAscii::<20>::from_ascii("steady state").unwrap(),
+        ])
+        .create("phase")
+        .unwrap();
+      file
+        .new_dataset::<i64>()
+        .shape(())
+        .create("runs")
+        .unwrap();
+    }
+
+    let storage_connection = format!("provider=filesystem;rootFilePath={}", dir.path().display())
+      .parse()
+      .unwrap();
+
+    let ctx = populate_session(
+      &storage_connection,
+      vec![FileMetadata {
+        id: "1".to_string(),
+        file_name: "labels.hdf5".to_string(),
+        file_path: "runs".to_string(),
+        partition_columns: None,
+        csv_options: None,
+        glob: None,
+      }],
+    )
+    .await
+    .unwrap();
+
+    // the scalar can't be a column, it's listed in the schema metadata instead of dropped silently
+    let table = ctx.table("table_1").await.unwrap();
+    assert_eq!(
+      table
+        .schema()
+        .metadata()
+        .get(crate::timeseries::hdf5_table::SKIPPED_DATASETS)
+        .unwrap(),
+      "runs (0 dimensions)"
+    );
+
+    let results: Vec<RecordBatch> = ctx
+      .sql("SELECT phase FROM table_1 WHERE step = 2")
+      .await
+      .unwrap()
+      .collect()
+      .await
+      .unwrap();
+
+    assert_eq!(
+      results[0].column(0).as_string::<i32>().value(0),
+      "steady state"
+    );
+  }
 }
@@ -0,0 +1,571 @@
+use std::any::Any;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{
+  new_null_array, Array, ArrayRef, AsArray, Float64Array, Int64Array, ListArray, RecordBatch,
+  StructArray, TimestampNanosecondArray, UInt32Array,
+};
+use datafusion::arrow::buffer::OffsetBuffer;
+use datafusion::arrow::compute::{cast, take};
+use datafusion::arrow::datatypes::{
+  DataType, Field, FieldRef, Fields, Float64Type, Int64Type, IntervalUnit, Schema, TimeUnit,
+};
+use datafusion::catalog::{TableFunctionImpl, TableProvider};
+use datafusion::common::{exec_err, plan_err, Result, ScalarValue};
+use datafusion::datasource::MemTable;
+use datafusion::functions::datetime::date_bin;
+use datafusion::logical_expr::function::{
+  AccumulatorArgs, PartitionEvaluatorArgs, StateFieldsArgs, WindowUDFFieldArgs,
+};
+use datafusion::logical_expr::{
+  Accumulator, AggregateUDF, AggregateUDFImpl, Expr, PartitionEvaluator, ScalarUDF, Signature,
+  Volatility, WindowUDF, WindowUDFImpl,
+};
+use datafusion::physical_expr::PhysicalExpr;
+use datafusion::physical_plan::expressions::Literal;
+use datafusion::prelude::SessionContext;
+
+/// time_buckets refuses to generate more rows than this, a typo in the interval shouldn't be able to
+/// fill up memory
+const MAX_BUCKETS: i64 = 1_000_000;
+
+/// Registers the time-series functions with a session so they can be used in any query:
+///
+/// - `time_bucket(interval, ts [, origin])` truncates `ts` to the start of its bucket, same as
+///   DataFusion's `date_bin`, e.g. `GROUP BY time_bucket(INTERVAL '15 minutes', ts)`
+/// - `time_buckets(start, end, interval)` is a table of every bucket from `start` up to, but not
+///   including, `end` in a `bucket` column. Left joining the grouped results onto it fills the gaps
+///   in them with nulls
+/// - `locf(value) OVER (ORDER BY ts)` replaces nulls with the last value that wasn't null
+/// - `interpolate(value, ts) OVER (ORDER BY ts)` replaces nulls with a value linearly interpolated
+///   by time between the values either side of them. Nulls before the first or after the last value
+///   stay null
+/// - `moving_average(value, n) OVER (ORDER BY ts)` is the average of the value over the current and
+///   previous `n - 1` rows, ignoring nulls
+/// - `lttb(ts, value, n)` downsamples to `n` points using Largest-Triangle-Three-Buckets, keeping
+///   the shape of the series. It returns a list of `{time, value}` structs ordered by time, `unnest`
+///   turns it back into rows
+pub fn register_time_functions(ctx: &SessionContext) {
+  ctx.register_udf(ScalarUDF::clone(&date_bin()).with_aliases(["time_bucket"]));
+  ctx.register_udtf("time_buckets", Arc::new(TimeBuckets));
+  ctx.register_udwf(WindowUDF::new_from_impl(Locf::new()));
+  ctx.register_udwf(WindowUDF::new_from_impl(Interpolate::new()));
+  ctx.register_udwf(WindowUDF::new_from_impl(MovingAverage::new()));
+  ctx.register_udaf(AggregateUDF::new_from_impl(Lttb::new()));
+}
+
+#[derive(Debug)]
+struct TimeBuckets;
+
+impl TableFunctionImpl for TimeBuckets {
+  fn call(&self, args: &[Expr]) -> Result<Arc<dyn TableProvider>> {
+    let (start, end, interval) = match args {
+      [start, end, interval] => (start, end, interval),
+      _ => return plan_err!("time_buckets takes a start, an end and an interval"),
+    };
+
+    let timestamp = DataType::Timestamp(TimeUnit::Nanosecond, None);
+    let start = match literal(start, "start")?.cast_to(&timestamp)? {
+      ScalarValue::TimestampNanosecond(Some(start), _) => start,
+      _ => return plan_err!("time_buckets start can't be null"),
+    };
+    let end = match literal(end, "end")?.cast_to(&timestamp)? {
+      ScalarValue::TimestampNanosecond(Some(end), _) => end,
+      _ => return plan_err!("time_buckets end can't be null"),
+    };
+    let step = match literal(interval, "interval")?
+      .cast_to(&DataType::Interval(IntervalUnit::MonthDayNano))?
+    {
+      ScalarValue::IntervalMonthDayNano(Some(i)) if i.months == 0 => {
+        match (i.days as i64)
+          .checked_mul(86_400_000_000_000)
+          .and_then(|days| days.checked_add(i.nanoseconds))
+        {
+          Some(step) => step,
+          None => return plan_err!("time_buckets interval is too long"),
+        }
+      }
+      ScalarValue::IntervalMonthDayNano(Some(_)) => {
+        return plan_err!("time_buckets interval can't be in months or years, they vary in length")
+      }
+      _ => return plan_err!("time_buckets interval can't be null"),
+    };
+
+    if step <= 0 {
+      return plan_err!("time_buckets interval must be greater than 0");
+    }
+    let span = match end.checked_sub(start) {
+      Some(span) => span,
+      None => return plan_err!("time_buckets start and end are too far apart"),
+    };
+    if span / step >= MAX_BUCKETS {
+      return plan_err!("time_buckets would return more than {MAX_BUCKETS} buckets");
+    }
+
+    let buckets: TimestampNanosecondArray = (start..end).step_by(step as usize).map(Some).collect();
+    let schema = Arc::new(Schema::new(vec![Field::new("bucket", timestamp, false)]));
+    let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(buckets)])?;
+
+    Ok(Arc::new(MemTable::try_new(schema, vec![vec![batch]])?))
+  }
+}
+
+/// The value of a table function argument, which has to be a constant such as `'2024-07-30'`,
+/// `TIMESTAMP '2024-07-30 00:00:00'` or `INTERVAL '1 hour'`
+fn literal(expr: &Expr, name: &str) -> Result<ScalarValue> {
+  match expr {
+    Expr::Literal(value, _) => Ok(value.clone()),
+    Expr::Cast(c) => literal(&c.expr, name)?.cast_to(&c.data_type),
+    Expr::TryCast(c) => literal(&c.expr, name)?.cast_to(&c.data_type),
+    _ => plan_err!("time_buckets {name} must be a constant, got {expr}"),
+  }
+}
+
+/// The value of a window or aggregate function argument that has to be a positive integer constant
+fn positive_integer(exprs: &[Arc<dyn PhysicalExpr>], index: usize, name: &str) -> Result<usize> {
+  let value = exprs
+    .get(index)
+    .and_then(|e| e.as_any().downcast_ref::<Literal>())
+    .map(|l| l.value().cast_to(&DataType::Int64));
+
+  match value {
+    Some(Ok(ScalarValue::Int64(Some(n)))) if n > 0 => Ok(n as usize),
+    _ => plan_err!("{name} must be an integer constant greater than 0"),
+  }
+}
+
+#[derive(Debug)]
+struct Locf {
+  signature: Signature,
+}
+
+impl Locf {
+  fn new() -> Self {
+    Locf {
+      signature: Signature::any(1, Volatility::Immutable),
+    }
+  }
+}
+
+impl WindowUDFImpl for Locf {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn name(&self) -> &str {
+    "locf"
+  }
+
+  fn signature(&self) -> &Signature {
+    &self.signature
+  }
+
+  fn partition_evaluator(
+    &self,
+    _args: PartitionEvaluatorArgs,
+  ) -> Result<Box<dyn PartitionEvaluator>> {
+    Ok(Box::new(LocfEvaluator))
+  }
+
+  fn field(&self, field_args: WindowUDFFieldArgs) -> Result<FieldRef> {
+    let data_type = field_args
+      .get_input_field(0)
+      .map(|f| f.data_type().clone())
+      .unwrap_or(DataType::Null);
+
+    Ok(Arc::new(Field::new(field_args.name(), data_type, true)))
+  }
+}
+
+#[derive(Debug)]
+struct LocfEvaluator;
+
+impl PartitionEvaluator for LocfEvaluator {
+  fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+    let values = &values[0];
+
+    // every row takes the value of the last row before it that wasn't null, this works for any type
+    let mut last = None;
+    let indices: UInt32Array = (0..num_rows)
+      .map(|i| {
+        if values.is_valid(i) {
+          last = Some(i as u32);
+        }
+        last
+      })
+      .collect();
+
+    Ok(take(values.as_ref(), &indices, None)?)
+  }
+}
+
+#[derive(Debug)]
+struct Interpolate {
+  signature: Signature,
+}
+
+impl Interpolate {
+  fn new() -> Self {
+    Interpolate {
+      signature: Signature::any(2, Volatility::Immutable),
+    }
+  }
+}
+
+impl WindowUDFImpl for Interpolate {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn name(&self) -> &str {
+    "interpolate"
+  }
+
+  fn signature(&self) -> &Signature {
+    &self.signature
+  }
+
+  fn partition_evaluator(
+    &self,
+    _args: PartitionEvaluatorArgs,
+  ) -> Result<Box<dyn PartitionEvaluator>> {
+    Ok(Box::new(InterpolateEvaluator))
+  }
+
+  fn field(&self, field_args: WindowUDFFieldArgs) -> Result<FieldRef> {
+    Ok(Arc::new(Field::new(
+      field_args.name(),
+      DataType::Float64,
+      true,
+    )))
+  }
+}
+
+#[derive(Debug)]
+struct InterpolateEvaluator;
+
+impl PartitionEvaluator for InterpolateEvaluator {
+  fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+    let (values, times) = (float64(&values[0])?, int64(&values[1])?);
+    let point = |i: usize| {
+      (values.is_valid(i) && times.is_valid(i)).then(|| (times.value(i), values.value(i)))
+    };
+
+    let mut interpolated = Vec::with_capacity(num_rows);
+    let mut previous = None;
+    // the next row with a value, found again only once the rows before it have been passed
+    let mut next: Option<(usize, (i64, f64))> = None;
+
+    for i in 0..num_rows {
+      if let Some(p) = point(i) {
+        previous = Some(p);
+        interpolated.push(Some(p.1));
+        continue;
+      }
+
+      if next.is_none_or(|(index, _)| index < i) {
+        next = (i + 1..num_rows).find_map(|j| point(j).map(|p| (j, p)));
+      }
+
+      interpolated.push(match (previous, next, times.is_valid(i)) {
+        (Some((t0, v0)), Some((_, (t1, v1))), true) if t1 != t0 => {
+          Some(v0 + (v1 - v0) * (times.value(i) - t0) as f64 / (t1 - t0) as f64)
+        }
+        _ => None,
+      });
+    }
+
+    Ok(Arc::new(Float64Array::from(interpolated)))
+  }
+}
+
+#[derive(Debug)]
+struct MovingAverage {
+  signature: Signature,
+}
+
+impl MovingAverage {
+  fn new() -> Self {
+    MovingAverage {
+      signature: Signature::any(2, Volatility::Immutable),
+    }
+  }
+}
+
+impl WindowUDFImpl for MovingAverage {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn name(&self) -> &str {
+    "moving_average"
+  }
+
+  fn signature(&self) -> &Signature {
+    &self.signature
+  }
+
+  fn partition_evaluator(
+    &self,
+    args: PartitionEvaluatorArgs,
+  ) -> Result<Box<dyn PartitionEvaluator>> {
+    Ok(Box::new(MovingAverageEvaluator {
+      window: positive_integer(args.input_exprs(), 1, "moving_average window size")?,
+    }))
+  }
+
+  fn field(&self, field_args: WindowUDFFieldArgs) -> Result<FieldRef> {
+    Ok(Arc::new(Field::new(
+      field_args.name(),
+      DataType::Float64,
+      true,
+    )))
+  }
+}
+
+#[derive(Debug)]
+struct MovingAverageEvaluator {
+  window: usize,
+}
+
+impl PartitionEvaluator for MovingAverageEvaluator {
+  fn evaluate_all(&mut self, values: &[ArrayRef], num_rows: usize) -> Result<ArrayRef> {
+    let values = float64(&values[0])?;
+    let (mut sum, mut count) = (0.0, 0);
+    let mut averages = Vec::with_capacity(num_rows);
+
+    for i in 0..num_rows {
+      if values.is_valid(i) {
+        sum += values.value(i);
+        count += 1;
+      }
+      // the row leaving the window
+      if i >= self.window && values.is_valid(i - self.window) {
+        sum -= values.value(i - self.window);
+        count -= 1;
+      }
+
+      averages.push((count > 0).then(|| sum / count as f64));
+    }
+
+    Ok(Arc::new(Float64Array::from(averages)))
+  }
+}
+
+#[derive(Debug)]
+struct Lttb {
+  signature: Signature,
+}
+
+impl Lttb {
+  fn new() -> Self {
+    Lttb {
+      signature: Signature::any(3, Volatility::Immutable),
+    }
+  }
+
+  fn point_fields(time_type: &DataType) -> Fields {
+    Fields::from(vec![
+      Field::new("time", time_type.clone(), false),
+      Field::new("value", DataType::Float64, false),
+    ])
+  }
+}
+
+impl AggregateUDFImpl for Lttb {
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+
+  fn name(&self) -> &str {
+    "lttb"
+  }
+
+  fn signature(&self) -> &Signature {
+    &self.signature
+  }
+
+  fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+    Ok(DataType::new_list(
+      DataType::Struct(Lttb::point_fields(&arg_types[0])),
+      true,
+    ))
+  }
+
+  fn accumulator(&self, acc_args: AccumulatorArgs) -> Result<Box<dyn Accumulator>> {
+    let threshold = positive_integer(acc_args.exprs, 2, "lttb point count")?;
+    if threshold < 3 {
+      return plan_err!(
+        "lttb point count must be at least 3, the first and last points are always kept"
+      );
+    }
+
+    Ok(Box::new(LttbAccumulator {
+      time_type: acc_args.exprs[0].data_type(acc_args.schema)?,
+      threshold,
+      points: vec![],
+    }))
+  }
+
+  fn state_fields(&self, args: StateFieldsArgs) -> Result<Vec<FieldRef>> {
+    Ok(vec![
+      Arc::new(Field::new_list(
+        format!("{}[times]", args.name),
+        Field::new_list_field(DataType::Int64, true),
+        true,
+      )),
+      Arc::new(Field::new_list(
+        format!("{}[values]", args.name),
+        Field::new_list_field(DataType::Float64, true),
+        true,
+      )),
+    ])
+  }
+}
+
+#[derive(Debug)]
+struct LttbAccumulator {
+  time_type: DataType,
+  threshold: usize,
+  /// every point seen, the time as an integer so any timestamp unit works
+  points: Vec<(i64, f64)>,
+}
+
+impl LttbAccumulator {
+  fn push(&mut self, times: &Int64Array, values: &Float64Array) {
+    for i in 0..times.len() {
+      if times.is_valid(i) && values.is_valid(i) {
+        self.points.push((times.value(i), values.value(i)));
+      }
+    }
+  }
+}
+
+impl Accumulator for LttbAccumulator {
+  fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+    self.push(&int64(&values[0])?, &float64(&values[1])?);
+    Ok(())
+  }
+
+  fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+    let (times, values) = (states[0].as_list::<i32>(), states[1].as_list::<i32>());
+
+    for i in 0..times.len() {
+      self.push(
+        times.value(i).as_primitive::<Int64Type>(),
+        values.value(i).as_primitive::<Float64Type>(),
+      );
+    }
+    Ok(())
+  }
+
+  fn state(&mut self) -> Result<Vec<ScalarValue>> {
+    let times = Int64Array::from_iter_values(self.points.iter().map(|p| p.0));
+    let values = Float64Array::from_iter_values(self.points.iter().map(|p| p.1));
+
+    Ok(vec![
+      ScalarValue::List(Arc::new(single_row_list(Arc::new(times), true))),
+      ScalarValue::List(Arc::new(single_row_list(Arc::new(values), true))),
+    ])
+  }
+
+  fn evaluate(&mut self) -> Result<ScalarValue> {
+    self.points.sort_by_key(|p| p.0);
+    let sampled = lttb(&self.points, self.threshold);
+
+    let times = Int64Array::from_iter_values(sampled.iter().map(|p| p.0));
+    let values = Float64Array::from_iter_values(sampled.iter().map(|p| p.1));
+    let struct_array = StructArray::try_new(
+      Lttb::point_fields(&self.time_type),
+      vec![cast(&times, &self.time_type)?, Arc::new(values)],
+      None,
+    )?;
+
+    Ok(ScalarValue::List(Arc::new(single_row_list(
+      Arc::new(struct_array),
+      true,
+    ))))
+  }
+
+  fn size(&self) -> usize {
+    size_of::<Self>() + self.points.capacity() * size_of::<(i64, f64)>()
+  }
+}
+
+/// Largest-Triangle-Three-Buckets over `points` sorted by time. The first and last points are kept
+/// and the rest are split into `threshold - 2` buckets, keeping the point from each that makes the
+/// largest triangle with the point kept before it and the average of the next bucket
+fn lttb(points: &[(i64, f64)], threshold: usize) -> Vec<(i64, f64)> {
+  if points.len() <= threshold {
+    return points.to_vec();
+  }
+
+  let every = (points.len() - 2) as f64 / (threshold - 2) as f64;
+  let bucket = |i: usize| {
+    let start = (i as f64 * every) as usize + 1;
+    let end = (((i + 1) as f64 * every) as usize + 1).min(points.len() - 1);
+    start..end
+  };
+
+  let mut sampled = Vec::with_capacity(threshold);
+  let mut a = points[0];
+  sampled.push(a);
+
+  for i in 0..threshold - 2 {
+    // the last bucket is followed by the last point
+    let next = bucket(i + 1);
+    let next = if next.is_empty() {
+      &points[points.len() - 1..]
+    } else {
+      &points[next]
+    };
+    let avg_time = next.iter().map(|p| p.0 as f64).sum::<f64>() / next.len() as f64;
+    let avg_value = next.iter().map(|p| p.1).sum::<f64>() / next.len() as f64;
+
+    let area = |p: &(i64, f64)| {
+      ((a.0 as f64 - avg_time) * (p.1 - a.1) - (a.0 as f64 - p.0 as f64) * (avg_value - a.1)).abs()
+    };
+    if let Some(largest) = points[bucket(i)]
+      .iter()
+      .max_by(|x, y| area(x).total_cmp(&area(y)))
+    {
+      a = *largest;
+      sampled.push(a);
+    }
+  }
+
+  sampled.push(points[points.len() - 1]);
+  sampled
+}
+
+fn single_row_list(values: ArrayRef, nullable: bool) -> ListArray {
+  let field = Arc::new(Field::new_list_field(values.data_type().clone(), nullable));
+  ListArray::new(
+    field,
+    OffsetBuffer::from_lengths([values.len()]),
+    values,
+    None,
+  )
+}
+
+fn float64(values: &ArrayRef) -> Result<Float64Array> {
+  match cast(values, &DataType::Float64) {
+    Ok(values) => Ok(values.as_primitive::<Float64Type>().clone()),
+    Err(_) => exec_err!("expected a numeric value, got {}", values.data_type()),
+  }
+}
+
+/// Timestamps as integers in whatever unit they're in, only their differences matter
+fn int64(values: &ArrayRef) -> Result<Int64Array> {
+  let values = match values.data_type() {
+    DataType::Timestamp(unit, _) => cast(values, &DataType::Timestamp(*unit, None))?,
+    DataType::Null => new_null_array(&DataType::Int64, values.len()),
+    _ => values.clone(),
+  };
+
+  match cast(&values, &DataType::Int64) {
+    Ok(values) => Ok(values.as_primitive::<Int64Type>().clone()),
+    Err(_) => exec_err!("expected a timestamp or number, got {}", values.data_type()),
+  }
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use datafusion::arrow::array::timezone::Tz;
+
+/// Formats the legacy ingestion detects on its own when a date column doesn't have a format string.
+/// The ones with an offset are tried first, see parse_auto for numbers
+const OFFSET_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"];
+const NAIVE_FORMATS: [&str; 6] = [
+  "%Y-%m-%d %H:%M:%S%.f",
+  "%Y-%m-%dT%H:%M:%S%.f",
+  "%Y-%m-%d %H:%M",
+  "%Y-%m-%dT%H:%M",
+  "%Y/%m/%d %H:%M:%S%.f",
+  "%Y/%m/%d %H:%M",
+];
+
+/// Excel counts days from the end of 1899, the 30th rather than the 31st makes up for it treating
+/// 1900 as a leap year
+const EXCEL_EPOCH: NaiveDate = NaiveDate::from_ymd_opt(1899, 12, 30).unwrap();
+/// The serial of 9999-12-31, the last date Excel has. Larger numbers are taken to be epoch times
+const MAX_EXCEL_SERIAL: f64 = 2_958_466.0;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Format {
+  Auto,
+  Strftime(String),
+  EpochSeconds,
+  EpochMillis,
+  Excel,
+}
+
+/// How the values of a legacy date column are turned into UTC timestamps. `format_string` is either a
+/// strftime format, `epoch` for seconds since 1970, `epoch_ms` for milliseconds since 1970 or `excel`
+/// for Excel serial dates. Without one the format is detected per value, which covers ISO-8601 and
+/// RFC 3339 with or without an offset, epoch seconds, milliseconds, microseconds or nanoseconds, and
+/// Excel serial dates. `timezone` is the IANA name or fixed offset, e.g. `America/Denver` or
+/// `-07:00`, of values that don't have an offset of their own. Defaults to UTC
+#[derive(Debug, Clone)]
+pub struct TimestampParser {
+  format: Format,
+  timezone: Tz,
+}
+
+impl TimestampParser {
+  pub fn new(format_string: Option<&str>, timezone: Option<&str>) -> Result<Self, String> {
+    let format = match format_string.map(|f| f.trim()) {
+      None | Some("") | Some("auto") => Format::Auto,
+      Some("epoch") => Format::EpochSeconds,
+      Some("epoch_ms") => Format::EpochMillis,
+      Some("excel") => Format::Excel,
+      Some(format) => Format::Strftime(format.to_string()),
+    };
+    let timezone = timezone
+      .unwrap_or("+00:00")
+      .parse::<Tz>()
+      .map_err(|e| e.to_string())?;
+
+    Ok(TimestampParser { format, timezone })
+  }
+
+  /// The value as a UTC timestamp, or why it isn't one
+  pub fn parse(&self, value: &str) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+
+    match &self.format {
+      Format::Auto => self.parse_auto(value),
+      Format::Strftime(format) => match DateTime::parse_from_str(value, format) {
+        Ok(timestamp) => Ok(timestamp.with_timezone(&Utc)),
+        // an offset in the format is the only way DateTime can parse it, everything else is naive
+        Err(_) => NaiveDateTime::parse_from_str(value, format)
+          .map_err(|e| format!("date doesn't match the format {format}: {e}"))
+          .and_then(|naive| self.localize(naive)),
+      },
+      Format::EpochSeconds => epoch(value, 1_000_000_000),
+      Format::EpochMillis => epoch(value, 1_000_000),
+      Format::Excel => number(value).and_then(|n| self.excel(n)),
+    }
+  }
+
+  fn parse_auto(&self, value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(n) = value.parse::<f64>() {
+      // the magnitude is all there is to go on. Excel serials stop at 9999, the smallest epoch times
+      // that don't fit them are in 1970 and each unit after seconds is a thousand times larger
+      let magnitude = n.abs();
+      return if magnitude < MAX_EXCEL_SERIAL {
+        self.excel(n)
+      } else if magnitude < 1e11 {
+        epoch(value, 1_000_000_000)
+      } else if magnitude < 1e14 {
+        epoch(value, 1_000_000)
+      } else if magnitude < 1e17 {
+        epoch(value, 1_000)
+      } else {
+        epoch(value, 1)
+      };
+    }
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+      return Ok(timestamp.with_timezone(&Utc));
+    }
+    for format in OFFSET_FORMATS {
+      if let Ok(timestamp) = DateTime::parse_from_str(value, format) {
+        return Ok(timestamp.with_timezone(&Utc));
+      }
+    }
+    for format in NAIVE_FORMATS {
+      if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+        return self.localize(naive);
+      }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+      return self.localize(date.and_time(Default::default()));
+    }
+
+    Err("not a date in any of the formats detected without a format string".to_string())
+  }
+
+  /// A time without an offset is taken to be in the column's timezone
+  fn localize(&self, naive: NaiveDateTime) -> Result<DateTime<Utc>, String> {
+    self
+      .timezone
+      .from_local_datetime(&naive)
+      // the earlier of the two when the clocks go back, like Postgres does
+      .earliest()
+      .map(|timestamp| timestamp.with_timezone(&Utc))
+      .ok_or_else(|| format!("{naive} doesn't exist in the column's timezone"))
+  }
+
+  /// Excel serials are days, and fractions of days, in the column's timezone
+  fn excel(&self, serial: f64) -> Result<DateTime<Utc>, String> {
+    if !(0.0..MAX_EXCEL_SERIAL).contains(&serial) {
+      return Err("not an Excel serial date".to_string());
+    }
+
+    let millis = (serial * 86_400_000.0).round() as i64;
+    let naive = EXCEL_EPOCH.and_time(Default::default()) + TimeDelta::milliseconds(millis);
+    self.localize(naive)
+  }
+}
+
+fn number(value: &str) -> Result<f64, String> {
+  value
+    .parse::<f64>()
+    .ok()
+    .filter(|n| n.is_finite())
+    .ok_or_else(|| "not a number".to_string())
+}
+
+/// Epoch times are UTC whatever the column's timezone, `nanos_per_unit` is how many nanoseconds one
+/// of the value's units is. Whole numbers are kept away from floats so no precision is lost
+fn epoch(value: &str, nanos_per_unit: i64) -> Result<DateTime<Utc>, String> {
+  let nanos = match value.parse::<i64>() {
+    Ok(n) => n.checked_mul(nanos_per_unit),
+    Err(_) => {
+      let nanos = (number(value)? * nanos_per_unit as f64).round();
+      (nanos.abs() < i64::MAX as f64).then_some(nanos as i64)
+    }
+  };
+
+  nanos
+    .map(DateTime::from_timestamp_nanos)
+    .ok_or_else(|| "epoch time is out of range".to_string())
+}
@@ -398,7 +398,7 @@ export default class DataSourceMapper extends Mapper {
                 }
 
                 case 'date': {
-                    type = 'timestamp';
+                    type = 'timestamptz';
                     break;
                 }
 
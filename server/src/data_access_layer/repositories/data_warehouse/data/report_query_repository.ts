@@ -294,8 +294,22 @@ export default class ReportQueryRepository extends Repository implements Reposit
             const results = await processUpload(reportID, query, storageConnection, files);
 
             // since we have a nested json we need to do some initial parsing before loading data into the DB
-            const descriptionsList = JSON.parse(results)['descriptions'];
+            const parsed = JSON.parse(results);
+            const descriptionsList = parsed['descriptions'];
+            const describeErrors: {file_id?: string; statement?: string; error: string}[] = parsed['errors'] ?? [];
             descriptionsList.map((o: {[key: string]: any}) => o.description = JSON.parse(o['description']) as FileDescription);
+
+            // files that couldn't be described don't stop the rest from being saved
+            const failureMessage = describeErrors.map((e) => `${e.file_id ?? e.statement}: ${e.error}`).join('; ');
+            if (describeErrors.length > 0) {
+                Logger.error(`error describing files for report ${reportID}: ${failureMessage}`);
+            }
+
+            if (descriptionsList.length === 0) {
+                void this.#reportRepo.setStatus(reportID, 'error', `error describing files for report ${reportID}: ${failureMessage}`);
+                return;
+            }
+
             const described = await this.#fileRepo.setDescriptions(descriptionsList as FileDescription[]);
 
             // if there is an error describing, set report status to "error"
@@ -306,7 +320,10 @@ export default class ReportQueryRepository extends Repository implements Reposit
             }
 
             // if everything was successful, set the report status to completed
-            const successMessage = `successfully uploaded description(s) for files ${files.map(f => f.id).join()}`;
+            const describedIDs = descriptionsList.map((d: FileDescription) => d.file_id).join();
+            const successMessage = describeErrors.length > 0
+                ? `uploaded description(s) for files ${describedIDs}, unable to describe ${failureMessage}`
+                : `successfully uploaded description(s) for files ${describedIDs}`;
             void this.#reportRepo.setStatus(reportID, 'completed', successMessage);
         } catch (e) {
             // set report status to "error"
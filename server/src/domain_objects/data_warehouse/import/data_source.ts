@@ -300,6 +300,11 @@ export class TimeseriesColumn {
     @IsOptional()
     @IsString()
     date_conversion_format_string?: string;
+
+    // IANA name or fixed offset of the timezone dates without an offset are in, they're stored as UTC
+    @IsOptional()
+    @IsString()
+    timezone?: string;
 }
 
 export class TimeseriesNodeParameter {
@@ -31,30 +31,50 @@ export default class TimeseriesDataSourceImpl implements DataSource {
         let timeseriesService = await TimeseriesService.GetInstance();
 
         return new Promise((resolve, reject) => {
-            timeseriesService.beginLegacyCsvIngestion(
+            const ingestion = timeseriesService.beginLegacyCsvIngestion(
                 this.DataSourceRecord?.id!,
                 (this.DataSourceRecord?.config as TimeseriesDataSourceConfig).columns as LegacyTimeseriesColumn[],
             );
 
             let pass = new PassThrough();
 
+            // readData resolves once the ingestion has room for the chunk, holding off on the next one
+            // until then keeps a fast upload from buffering more than the ingestion can take
             pass.on('data', (chunk: any) => {
-                timeseriesService.readData(chunk);
+                pass.pause();
+                ingestion
+                    .readData(chunk)
+                    .then(() => pass.resume())
+                    .catch((e) => {
+                        pass.destroy();
+                        reject(e.message);
+                    });
             });
 
             pass.on('error', (e: any) => {
+                // nothing more is coming, so whatever was ingested so far is thrown away
+                void ingestion.abort();
                 return Promise.resolve(Result.Failure(JSON.stringify(e)));
             });
 
-            pass.on('finish', () => {
-                timeseriesService
-                    .completeIngestion()
+            // end rather than finish, finish fires before the paused chunks have all been read
+            pass.on('end', () => {
+                ingestion
+                    .complete()
                     .then(() => resolve())
                     .catch((e) => {
                         reject(e.message);
                     });
             });
 
+            // pipe doesn't pass errors along, an upload that stops part way (e.g. the client disconnected)
+            // has to abort the ingestion itself so none of it is kept
+            payloadStream.on('error', (e: any) => {
+                void ingestion.abort();
+                pass.destroy();
+                reject(e.message);
+            });
+
             payloadStream.pipe(pass);
         });
     }
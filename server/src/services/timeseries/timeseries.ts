@@ -1,21 +1,28 @@
-import {BucketRepository, LegacyTimeseriesColumn} from 'deeplynx';
+import {
+    BucketColumnChange,
+    BucketRepository,
+    BucketTable,
+    BucketTableOptions,
+    Ingestion,
+    LegacyIngestionOptions,
+    LegacyTimeseriesColumn,
+} from 'deeplynx';
 import Config from '../config';
 
 export default class TimeseriesService {
+    private static instance: Promise<TimeseriesService> | undefined;
     private repo: BucketRepository;
 
+    // every ingestion gets its own handle, so all uploads share the one repository and its pool
     public static GetInstance(): Promise<TimeseriesService> {
-        const instance = new TimeseriesService();
-        return new Promise((resolve, reject) => {
-            instance
-                .init(Config.core_db_connection_string)
-                .then(() => {
-                    resolve(instance);
-                })
-                .catch((e) => {
-                    reject(e);
-                });
-        });
+        if (!TimeseriesService.instance) {
+            const instance = new TimeseriesService();
+            TimeseriesService.instance = instance.init(Config.core_db_connection_string).then(() => instance);
+            // a failed init shouldn't stick, the next caller gets to try again
+            TimeseriesService.instance.catch(() => (TimeseriesService.instance = undefined));
+        }
+
+        return TimeseriesService.instance;
     }
 
     init(dbConnection: string, maxColumns?: number): Promise<void> {
@@ -26,15 +33,23 @@ export default class TimeseriesService {
         this.repo = new BucketRepository();
     }
 
-    beginLegacyCsvIngestion(dataSourceID: string, columns: LegacyTimeseriesColumn[]): void {
-        return this.repo.beginLegacyCsvIngestion(dataSourceID, columns);
+    beginLegacyCsvIngestion(dataSourceID: string, columns: LegacyTimeseriesColumn[], options?: LegacyIngestionOptions): Ingestion {
+        return this.repo.beginLegacyCsvIngestion(dataSourceID, columns, options);
+    }
+
+    beginLegacyJsonIngestion(dataSourceID: string, columns: LegacyTimeseriesColumn[], options?: LegacyIngestionOptions): Ingestion {
+        return this.repo.beginLegacyJsonIngestion(dataSourceID, columns, options);
+    }
+
+    createBucket(dataSourceID: string, columns: LegacyTimeseriesColumn[], options?: BucketTableOptions): Promise<BucketTable> {
+        return this.repo.createBucket(dataSourceID, columns, options);
     }
 
-    readData(bytes: Buffer): void {
-        return this.repo.readData(bytes);
+    alterBucket(dataSourceID: string, changes: BucketColumnChange[]): Promise<void> {
+        return this.repo.alterBucket(dataSourceID, changes);
     }
 
-    completeIngestion(): Promise<void> {
-        return this.repo.completeIngestion();
+    dropBucket(dataSourceID: string): Promise<void> {
+        return this.repo.dropBucket(dataSourceID);
     }
 }
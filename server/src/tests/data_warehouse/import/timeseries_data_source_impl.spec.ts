@@ -671,20 +671,24 @@ describe('A Timeseries DataSource Implementation can', async () => {
         let loader = await TimeseriesService.GetInstance();
         const pass = new PassThrough();
 
-        loader.beginLegacyCsvIngestion(
+        const ingestion = loader.beginLegacyCsvIngestion(
             source?.DataSourceRecord?.id!,
             (source?.DataSourceRecord!.config as TimeseriesDataSourceConfig).columns as LegacyTimeseriesColumn[],
         );
 
         pass.on('data', (chunk) => {
-            loader.readData(chunk);
+            pass.pause();
+            ingestion
+                .readData(chunk)
+                .then(() => pass.resume())
+                .catch((e) => expect.fail(e));
         });
 
         const stream = fs.createReadStream(__dirname + '/1million.csv');
 
-        pass.on('finish', () => {
-            loader
-                .completeIngestion()
+        pass.on('end', () => {
+            ingestion
+                .complete()
                 .then(() => {
                     sourceRepo.delete(source as DataSource, {removeData: true, force: true});
                     fs.unlinkSync(__dirname + '/1million.csv');